@@ -1,3 +1,8 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use url::Url;
+
 pub const MAINNET: &str = "https://api.mainnet-beta.solana.com";
 pub const TESTNET: &str = "https://api.testnet.solana.com";
 pub const DEVNET: &str = "https://api.devnet.solana.com";
@@ -48,3 +53,278 @@ impl RpcCluster {
         )
     }
 }
+
+// -----------------
+// Parsing
+// -----------------
+/// Renders the cluster in the shortest form that parses back into it, i.e. a
+/// moniker, a single HTTP URL if the WS URL can be derived from it or an
+/// `http,ws` pair otherwise.
+impl fmt::Display for RpcCluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcCluster::Mainnet => write!(f, "mainnet"),
+            RpcCluster::Testnet => write!(f, "testnet"),
+            RpcCluster::Devnet => write!(f, "devnet"),
+            RpcCluster::Development => write!(f, "localhost"),
+            RpcCluster::Custom(url, ws_url) => {
+                if self == &Self::magicblock_devnet() {
+                    write!(f, "magicblock-devnet")
+                } else if derive_ws_url(url).as_deref() == Some(ws_url) {
+                    write!(f, "{}", url)
+                } else {
+                    write!(f, "{},{}", url, ws_url)
+                }
+            }
+        }
+    }
+}
+
+/// Parses one of the following:
+///
+/// - a moniker: `mainnet`, `testnet`, `devnet`, `localhost` or
+///   `magicblock-devnet`
+/// - a single HTTP URL, the WS URL is derived from it by switching the scheme
+///   and incrementing the port if one is provided, i.e.
+///   `http://localhost:7799` -> `ws://localhost:7800`
+/// - an explicit `http,ws` pair, i.e.
+///   `https://rpc.example.com,wss://ws.example.com`
+impl FromStr for RpcCluster {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "mainnet" => return Ok(RpcCluster::Mainnet),
+            "testnet" => return Ok(RpcCluster::Testnet),
+            "devnet" => return Ok(RpcCluster::Devnet),
+            "localhost" => return Ok(RpcCluster::Development),
+            "magicblock-devnet" => return Ok(Self::magicblock_devnet()),
+            _ => {}
+        }
+        match s.split_once(',') {
+            Some((url, ws_url)) => {
+                let (url, ws_url) = (url.trim(), ws_url.trim());
+                check_scheme(url, &["http", "https"])?;
+                check_scheme(ws_url, &["ws", "wss"])?;
+                Ok(RpcCluster::Custom(url.to_string(), ws_url.to_string()))
+            }
+            None => {
+                check_scheme(s, &["http", "https"])?;
+                let ws_url = derive_ws_url(s).ok_or_else(|| {
+                    format!("Invalid cluster '{s}', unable to derive WS URL")
+                })?;
+                Ok(RpcCluster::Custom(s.to_string(), ws_url))
+            }
+        }
+    }
+}
+
+impl Serialize for RpcCluster {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RpcCluster {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+const MONIKERS: &str = "mainnet, testnet, devnet, localhost, magicblock-devnet";
+
+fn check_scheme(url: &str, schemes: &[&str]) -> Result<(), String> {
+    let valid = match Url::parse(url) {
+        Ok(parsed) => schemes.contains(&parsed.scheme()) && parsed.has_host(),
+        Err(_) => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid cluster '{}', expected one of: {} or a URL with scheme {}",
+            url,
+            MONIKERS,
+            schemes.join("/")
+        ))
+    }
+}
+
+/// Derives the WS URL from an HTTP URL following the convention of solana
+/// validators to serve pubsub on the port following the RPC port.
+fn derive_ws_url(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let ws_scheme = match scheme {
+        "http" => "ws",
+        "https" => "wss",
+        _ => return None,
+    };
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    // The port follows the last colon unless that is part of an IPv6 host
+    let authority = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            let port = port.parse::<u16>().ok()?.checked_add(1)?;
+            format!("{host}:{port}")
+        }
+        _ => authority.to_string(),
+    };
+    Some(format!("{ws_scheme}://{authority}{path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(url: &str, ws_url: &str) -> RpcCluster {
+        RpcCluster::Custom(url.to_string(), ws_url.to_string())
+    }
+
+    fn assert_round_trip(cluster: &RpcCluster) {
+        assert_eq!(
+            &cluster.to_string().parse::<RpcCluster>().unwrap(),
+            cluster
+        );
+    }
+
+    #[test]
+    fn test_monikers() {
+        for (moniker, cluster) in [
+            ("mainnet", RpcCluster::Mainnet),
+            ("testnet", RpcCluster::Testnet),
+            ("devnet", RpcCluster::Devnet),
+            ("localhost", RpcCluster::Development),
+            ("magicblock-devnet", RpcCluster::magicblock_devnet()),
+        ] {
+            assert_eq!(moniker.parse::<RpcCluster>().unwrap(), cluster);
+            assert_eq!(cluster.to_string(), moniker);
+        }
+    }
+
+    #[test]
+    fn test_single_url_with_port() {
+        let cluster = "http://127.0.0.1:7799".parse::<RpcCluster>().unwrap();
+        assert_eq!(
+            cluster,
+            custom("http://127.0.0.1:7799", "ws://127.0.0.1:7800")
+        );
+        assert_eq!(cluster.to_string(), "http://127.0.0.1:7799");
+    }
+
+    #[test]
+    fn test_single_url_without_port() {
+        let cluster = "https://rpc.example.com/some/path?key=1"
+            .parse::<RpcCluster>()
+            .unwrap();
+        assert_eq!(
+            cluster,
+            custom(
+                "https://rpc.example.com/some/path?key=1",
+                "wss://rpc.example.com/some/path?key=1"
+            )
+        );
+        assert_round_trip(&cluster);
+    }
+
+    #[test]
+    fn test_single_url_with_ipv6_host() {
+        assert_eq!(
+            "http://[::1]:8899".parse::<RpcCluster>().unwrap(),
+            custom("http://[::1]:8899", "ws://[::1]:8900")
+        );
+        assert_eq!(
+            "http://[::1]".parse::<RpcCluster>().unwrap(),
+            custom("http://[::1]", "ws://[::1]")
+        );
+    }
+
+    #[test]
+    fn test_single_url_with_max_port_is_rejected() {
+        assert!("http://localhost:65535".parse::<RpcCluster>().is_err());
+    }
+
+    #[test]
+    fn test_url_pair() {
+        let cluster = "https://rpc.example.com, wss://ws.example.com:443"
+            .parse::<RpcCluster>()
+            .unwrap();
+        assert_eq!(
+            cluster,
+            custom("https://rpc.example.com", "wss://ws.example.com:443")
+        );
+        assert_eq!(
+            cluster.to_string(),
+            "https://rpc.example.com,wss://ws.example.com:443"
+        );
+        assert_round_trip(&cluster);
+    }
+
+    #[test]
+    fn test_url_pair_matching_derived_ws_url_displays_single_url() {
+        let cluster = "http://localhost:7799,ws://localhost:7800"
+            .parse::<RpcCluster>()
+            .unwrap();
+        assert_eq!(cluster.to_string(), "http://localhost:7799");
+        assert_round_trip(&cluster);
+    }
+
+    #[test]
+    fn test_invalid_schemes() {
+        assert!("ws://localhost:8900".parse::<RpcCluster>().is_err());
+        assert!("localhost:8899".parse::<RpcCluster>().is_err());
+        assert!("http://localhost:8899,http://localhost:8900"
+            .parse::<RpcCluster>()
+            .is_err());
+        assert!("ws://localhost:8900,http://localhost:8899"
+            .parse::<RpcCluster>()
+            .is_err());
+        assert!("http://".parse::<RpcCluster>().is_err());
+        assert!("http://a b".parse::<RpcCluster>().is_err());
+        assert!("http://localhost:7799,ws://a b"
+            .parse::<RpcCluster>()
+            .is_err());
+        assert!("".parse::<RpcCluster>().is_err());
+    }
+
+    #[test]
+    fn test_round_trip_of_all_forms() {
+        for cluster in [
+            RpcCluster::Mainnet,
+            RpcCluster::Testnet,
+            RpcCluster::Devnet,
+            RpcCluster::Development,
+            RpcCluster::magicblock_devnet(),
+            custom(DEVNET, WS_DEVNET),
+            custom("http://localhost:8899", "ws://localhost:8900"),
+            custom("http://localhost:8899", "ws://other:1234"),
+        ] {
+            assert_round_trip(&cluster);
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let clusters = vec![
+            RpcCluster::Devnet,
+            custom("http://localhost:7799", "ws://localhost:7800"),
+            custom("https://rpc.example.com", "wss://ws.example.com"),
+        ];
+        let json = serde_json::to_string(&clusters).unwrap();
+        assert_eq!(
+            json,
+            r#"["devnet","http://localhost:7799","https://rpc.example.com,wss://ws.example.com"]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Vec<RpcCluster>>(&json).unwrap(),
+            clusters
+        );
+        assert!(serde_json::from_str::<RpcCluster>(r#""nowhere""#).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::cluster::RpcCluster;
+
+/// Maps the authorities of ephemeral validators to the RPC and websocket
+/// URLs they serve
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidatorRegistry {
+    validators: HashMap<Pubkey, RpcCluster>,
+}
+
+impl ValidatorRegistry {
+    pub fn with_validator(
+        mut self,
+        authority: Pubkey,
+        cluster: RpcCluster,
+    ) -> Self {
+        self.insert(authority, cluster);
+        self
+    }
+
+    pub fn insert(&mut self, authority: Pubkey, cluster: RpcCluster) {
+        self.validators.insert(authority, cluster);
+    }
+
+    pub fn get(&self, authority: &Pubkey) -> Option<&RpcCluster> {
+        self.validators.get(authority)
+    }
+
+    pub fn authorities(&self) -> impl Iterator<Item = &Pubkey> {
+        self.validators.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pubkey, &RpcCluster)> {
+        self.validators.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+}
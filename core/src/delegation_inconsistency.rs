@@ -6,4 +6,7 @@ pub enum DelegationInconsistency {
     DelegationRecordNotFound,
     DelegationRecordInvalidOwner,
     DelegationRecordDataInvalid(String),
+    /// The delegation record was written by a version of the delegation
+    /// program whose layout we don't know
+    DelegationRecordVersionUnknown(String),
 }
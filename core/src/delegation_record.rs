@@ -1,12 +1,50 @@
 use std::{fmt, time::Duration};
 
 use serde::{Deserialize, Serialize};
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{clock::DEFAULT_MS_PER_SLOT, pubkey::Pubkey};
+
+/// Value of the `commit_frequency_ms` of a dlp delegation record meaning the
+/// account is never committed
+pub const COMMIT_FREQUENCY_NEVER_MS: u64 = u32::MAX as u64;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum CommitFrequency {
     /// Commit every time after n number of milliseconds passed.
     Millis(u64),
+    /// Commit every time after n number of slots passed.
+    Slots(u64),
+    /// Only commit when the account is undelegated.
+    OnUndelegate,
+    /// Never commit the account.
+    Never,
+}
+
+impl CommitFrequency {
+    /// Maps the `commit_frequency_ms` of a dlp delegation record, which uses
+    /// [COMMIT_FREQUENCY_NEVER_MS] for accounts that are never committed.
+    /// The record has no way to express the other policies.
+    pub fn from_commit_frequency_ms(millis: u64) -> Self {
+        match millis {
+            COMMIT_FREQUENCY_NEVER_MS => CommitFrequency::Never,
+            millis => CommitFrequency::Millis(millis),
+        }
+    }
+
+    /// The interval between commits, `None` if the account isn't committed
+    /// periodically.
+    /// Slots are converted assuming the default slot duration and saturate
+    /// instead of overflowing.
+    pub fn to_duration(&self) -> Option<Duration> {
+        match self {
+            CommitFrequency::Millis(millis) => {
+                Some(Duration::from_millis(*millis))
+            }
+            CommitFrequency::Slots(slots) => Some(Duration::from_millis(
+                slots.saturating_mul(DEFAULT_MS_PER_SLOT),
+            )),
+            CommitFrequency::OnUndelegate | CommitFrequency::Never => None,
+        }
+    }
 }
 
 impl Default for CommitFrequency {
@@ -19,15 +57,17 @@ impl fmt::Display for CommitFrequency {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CommitFrequency::Millis(millis) => write!(f, "{}ms", millis),
+            CommitFrequency::Slots(slots) => write!(f, "{} slots", slots),
+            CommitFrequency::OnUndelegate => write!(f, "on undelegate"),
+            CommitFrequency::Never => write!(f, "never"),
         }
     }
 }
 
+/// Accounts that aren't committed periodically saturate to [Duration::MAX]
 impl From<CommitFrequency> for Duration {
     fn from(freq: CommitFrequency) -> Duration {
-        match freq {
-            CommitFrequency::Millis(millis) => Duration::from_millis(millis),
-        }
+        freq.to_duration().unwrap_or(Duration::MAX)
     }
 }
 
@@ -44,3 +84,89 @@ pub struct DelegationRecord {
     /// The frequency at which to commit the account state of the ephemeral validator back to the chain.
     pub commit_frequency: CommitFrequency,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_commit_frequency_ms() {
+        assert_eq!(
+            CommitFrequency::from_commit_frequency_ms(30_000),
+            CommitFrequency::Millis(30_000)
+        );
+        assert_eq!(
+            CommitFrequency::from_commit_frequency_ms(0),
+            CommitFrequency::Millis(0)
+        );
+        assert_eq!(
+            CommitFrequency::from_commit_frequency_ms(u32::MAX as u64),
+            CommitFrequency::Never
+        );
+        assert_eq!(
+            CommitFrequency::from_commit_frequency_ms(u32::MAX as u64 + 1),
+            CommitFrequency::Millis(u32::MAX as u64 + 1)
+        );
+    }
+
+    #[test]
+    fn test_to_duration() {
+        assert_eq!(
+            CommitFrequency::Millis(1_000).to_duration(),
+            Some(Duration::from_millis(1_000))
+        );
+        assert_eq!(
+            CommitFrequency::Slots(10).to_duration(),
+            Some(Duration::from_millis(10 * DEFAULT_MS_PER_SLOT))
+        );
+        assert_eq!(
+            CommitFrequency::Millis(u64::MAX).to_duration(),
+            Some(Duration::from_millis(u64::MAX))
+        );
+        assert_eq!(
+            CommitFrequency::Slots(u64::MAX).to_duration(),
+            Some(Duration::from_millis(u64::MAX))
+        );
+        assert_eq!(CommitFrequency::OnUndelegate.to_duration(), None);
+        assert_eq!(CommitFrequency::Never.to_duration(), None);
+    }
+
+    #[test]
+    fn test_into_duration() {
+        assert_eq!(
+            Duration::from(CommitFrequency::Millis(1_000)),
+            Duration::from_millis(1_000)
+        );
+        assert_eq!(
+            Duration::from(CommitFrequency::Slots(u64::MAX)),
+            Duration::from_millis(u64::MAX)
+        );
+        assert_eq!(
+            Duration::from(CommitFrequency::OnUndelegate),
+            Duration::MAX
+        );
+        assert_eq!(Duration::from(CommitFrequency::Never), Duration::MAX);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(CommitFrequency::Millis(30_000).to_string(), "30000ms");
+        assert_eq!(CommitFrequency::Slots(5).to_string(), "5 slots");
+        assert_eq!(CommitFrequency::OnUndelegate.to_string(), "on undelegate");
+        assert_eq!(CommitFrequency::Never.to_string(), "never");
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        for freq in [
+            CommitFrequency::Millis(30_000),
+            CommitFrequency::Slots(5),
+            CommitFrequency::OnUndelegate,
+            CommitFrequency::Never,
+        ] {
+            let json = serde_json::to_string(&freq).unwrap();
+            let parsed: CommitFrequency = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, freq);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::{delegation_record::DelegationRecord, errors::CoreResult};
 
-pub trait DelegationRecordParser {
+pub trait DelegationRecordParser:
+    std::marker::Sync + std::marker::Send + 'static
+{
     fn try_parse(&self, data: &[u8]) -> CoreResult<DelegationRecord>;
 }
@@ -8,6 +8,8 @@ pub enum CoreError {
     RpcClientError(#[from] solana_rpc_client_api::client_error::Error),
     #[error("Failed to get account from cluster")]
     FailedToGetAccountFromCluster,
-    #[error("Failed to parse account data")]
+    #[error("Failed to parse delegation record: {0}")]
     FailedToParseDelegationRecord(String),
+    #[error("Unknown delegation record version: {0}")]
+    UnknownDelegationRecordVersion(String),
 }
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use solana_sdk::{
-    account::Account, clock::Slot, pubkey::Pubkey, signature::Signature,
-    transaction,
+    account::Account, clock::Slot, commitment_config::CommitmentLevel,
+    pubkey::Pubkey, signature::Signature, transaction,
 };
 
 use crate::errors::CoreResult;
@@ -20,6 +20,17 @@ pub trait AccountProvider:
         pubkeys: &[Pubkey],
         min_context_slot: Option<Slot>,
     ) -> CoreResult<(Slot, Vec<Option<Account>>)>;
+    /// Same as [AccountProvider::get_multiple_accounts], but overrides the
+    /// commitment the provider was configured with.
+    /// Providers that have no notion of commitment ignore it.
+    async fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+        _commitment: Option<CommitmentLevel>,
+    ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        self.get_multiple_accounts(pubkeys, min_context_slot).await
+    }
 }
 
 #[async_trait]
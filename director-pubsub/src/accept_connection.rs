@@ -1,18 +1,124 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+};
 
 use conjunto_core::{
     AccountProvider, RequestEndpoint, SignatureStatusProvider,
 };
-use futures_util::{SinkExt, StreamExt};
+use conjunto_guidepoint::{
+    auth::{api_key_subprotocol, ApiKeyAuth, API_KEY_SUBPROTOCOL_PREFIX},
+    client_stream::ClientStream,
+    rate_limit::{
+        api_key_from_query_and_header, ClientConnection, API_KEY_HEADER,
+    },
+    shutdown::{shutdown_requested, ShutdownReceiver},
+    RouteOverride, ROUTE_OVERRIDE_HEADER,
+};
+use conjunto_metrics::{
+    add_pubsub_subscriptions, dec_pubsub_connections, inc_pubsub_connections,
+    inc_pubsub_routed_message,
+};
+use futures_util::{stream::SplitStream, SinkExt, StreamExt};
 use log::*;
-use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::Message;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue, StatusCode},
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
 
 use crate::{
-    director::DirectorPubsub, errors::DirectorPubsubResult, BackendWebSocket,
-    BackendWebSocketWriter,
+    director::{DirectorPubsub, EphemeralValidators},
+    errors::DirectorPubsubResult,
+    messages::{
+        strip_route_hint, unsubscribe_subscription_id, BackendResponseMessage,
+        ClientRequestMessage, ClientSubMethod,
+    },
+    BackendWebSocket, BackendWebSocketWriter,
 };
 
+/// Message read from the socket of a registered ephemeral validator,
+/// `None` signals that the socket was closed
+type ValidatorMessage = (Pubkey, Option<Result<Message, tungstenite::Error>>);
+
+/// Same error codes the RPC server responds with when a limit is exceeded
+/// or the API key doesn't allow the method
+const RATE_LIMIT_EXCEEDED: i32 = 4;
+const UNAUTHORIZED: i32 = 5;
+
+/// The websocket of a client together with what the client told about
+/// itself in the handshake request
+pub(crate) struct ClientHandshake {
+    pub(crate) socket: WebSocketStream<ClientStream>,
+    pub(crate) addr: SocketAddr,
+    pub(crate) route_override: Option<RouteOverride>,
+    pub(crate) api_key: Option<String>,
+}
+
+pub(crate) async fn accept_handshake(
+    incoming_stream: ClientStream,
+    addr: SocketAddr,
+) -> DirectorPubsubResult<ClientHandshake> {
+    debug!("Peer address: {}", addr);
+
+    let mut route_override = None;
+    let mut api_key = None;
+    let socket = tokio_tungstenite::accept_hdr_async(
+        incoming_stream,
+        |req: &Request, mut res: Response| {
+            api_key = api_key_from_request(req);
+            // Clients fail the handshake unless the server accepts one of
+            // the subprotocols they asked for
+            if let Some(protocol) = api_key_subprotocol_from_request(req) {
+                if let Ok(value) = HeaderValue::from_str(protocol) {
+                    res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+                }
+            }
+            match route_override_from_request(req) {
+                Ok(route) => {
+                    route_override = route;
+                    Ok(res)
+                }
+                Err(err) => Err(bad_request(err)),
+            }
+        },
+    )
+    .await?;
+    if let Some(route) = route_override {
+        debug!("Client {} picked route: {}", addr, route);
+    }
+    Ok(ClientHandshake {
+        socket,
+        addr,
+        route_override,
+        api_key,
+    })
+}
+
+/// Closes the websocket of a client that won't be served
+pub(crate) async fn close_client(
+    mut socket: WebSocketStream<ClientStream>,
+    code: CloseCode,
+    reason: String,
+) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    if let Err(err) = socket.close(Some(frame)).await {
+        debug!("Failed to close client connection: {:?}", err);
+    }
+}
+
 pub(crate) async fn accept_connection<
     T: AccountProvider,
     U: SignatureStatusProvider,
@@ -20,105 +126,520 @@ pub(crate) async fn accept_connection<
     director: Arc<DirectorPubsub<T, U>>,
     chain_socket: BackendWebSocket,
     ephem_socket: BackendWebSocket,
-    incoming_stream: TcpStream,
+    client: ClientHandshake,
+    mut connection: ClientConnection,
+    mut shutdown_rx: ShutdownReceiver,
 ) -> DirectorPubsubResult<()> {
-    let addr = incoming_stream.peer_addr()?;
-    debug!("Peer address: {}", addr);
-
-    let client_stream =
-        tokio_tungstenite::accept_async(incoming_stream).await?;
-
-    let (mut write_client, mut read_client) = client_stream.split();
+    let ClientHandshake {
+        socket: client_socket,
+        addr,
+        route_override,
+        api_key,
+    } = client;
+    let (mut write_client, mut read_client) = client_socket.split();
     let (mut write_chain, mut read_chain) = chain_socket.split();
-    let (mut write_ephem, mut read_ephem) = ephem_socket.split();
+    let (write_ephem, mut read_ephem) = ephem_socket.split();
+    let (validator_tx, mut validator_rx) = unbounded_channel();
+    let mut ephem_sockets = EphemeralSockets {
+        default: write_ephem,
+        validators: HashMap::new(),
+        validator_tx,
+        pending_requests: HashMap::new(),
+        subscriptions: HashMap::new(),
+    };
 
-    tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                // We pipe both chain and ephemeral messages to the client
-                next = read_chain.next() => {
-                    match next {
-                        Some(Ok(msg)) => {
-                            trace!("Chain message: {:?}", msg);
-                            let res = handle_downstream_msg(&mut write_chain, &msg).await;
-                            if res.fwd_to_client {
-                                write_client.send(msg).await.unwrap();
-                            }
-                            if res.done {
-                                break;
-                            }
-                        }
-                        Some(Err(msg)) => {
-                            // We get a Protocol(ResetWithoutClosingHandshake) right before
-                            // the chain stream gets interrupted for subscriptions
-                            trace!("Error reading chain message: {:?}", msg);
+    inc_pubsub_connections();
+    // Subscriptions the client requested which end with the connection
+    let mut subscriptions = 0;
+    loop {
+        tokio::select! {
+            // We pipe both chain and ephemeral messages to the client
+            next = read_chain.next() => {
+                match next {
+                    Some(Ok(msg)) => {
+                        trace!("Chain message: {:?}", msg);
+                        let res = handle_downstream_msg(&mut write_chain, &msg).await;
+                        if res.fwd_to_client {
+                            write_client.send(msg).await.unwrap();
                         }
-                        None => {
-                            // If either downstream disconnects we need to make the client
-                            // aware and thus disconnect ourselves as well
+                        if res.done {
                             break;
                         }
                     }
+                    Some(Err(msg)) => {
+                        // We get a Protocol(ResetWithoutClosingHandshake) right before
+                        // the chain stream gets interrupted for subscriptions
+                        trace!("Error reading chain message: {:?}", msg);
+                    }
+                    None => {
+                        // If either downstream disconnects we need to make the client
+                        // aware and thus disconnect ourselves as well
+                        break;
+                    }
                 }
-                next = read_ephem.next() => {
-                    match next {
-                        Some(Ok(msg)) => {
-                            trace!("Ephem message: {:?}", msg);
-                            let res = handle_downstream_msg(&mut write_ephem, &msg).await;
-                            if res.fwd_to_client {
-                                write_client.send(msg).await.unwrap();
-                            }
-                            if res.done {
-                                break;
-                            }
+            }
+            next = read_ephem.next() => {
+                match next {
+                    Some(Ok(msg)) => {
+                        trace!("Ephem message: {:?}", msg);
+                        ephem_sockets.track_response(None, &msg);
+                        let res = handle_downstream_msg(&mut ephem_sockets.default, &msg).await;
+                        if res.fwd_to_client {
+                            write_client.send(msg).await.unwrap();
+                        }
+                        if res.done {
+                            break;
                         }
-                        Some(Err(msg)) => {
-                            trace!("Error reading ephem message: {:?}", msg);
+                    }
+                    Some(Err(msg)) => {
+                        trace!("Error reading ephem message: {:?}", msg);
+                    }
+                    None => {
+                        break;
+                    }
+                }
+            }
+            // Sockets of registered validators are read by separate tasks
+            // since they are opened on demand
+            next = validator_rx.recv() => {
+                match next {
+                    Some((authority, Some(Ok(msg)))) => {
+                        trace!("Validator {} message: {:?}", authority, msg);
+                        ephem_sockets.track_response(Some(authority), &msg);
+                        let Some(write_validator) = ephem_sockets.validators.get_mut(&authority) else {
+                            continue;
+                        };
+                        let res = handle_downstream_msg(write_validator, &msg).await;
+                        if res.fwd_to_client {
+                            write_client.send(msg).await.unwrap();
                         }
-                        None => {
+                        if res.done {
                             break;
                         }
                     }
+                    Some((authority, Some(Err(msg)))) => {
+                        trace!("Error reading validator {} message: {:?}", authority, msg);
+                    }
+                    Some((_, None)) | None => {
+                        break;
+                    }
                 }
-                // For client messages we decide by message content if to send it
-                // to chain or ephem socket
-                next = read_client.next() => {
-                    match next {
-                        Some(Ok(msg)) => {
-                            trace!("Client message: {:?}", msg);
-                            use RequestEndpoint::*;
-                            match director.guide_msg(&msg).await {
-                                Some(Chain) => {
-                                    trace!("Sending message to chain: {:?}", msg);
-                                    write_chain.send(msg).await.unwrap()
-                                },
-                                Some(Ephemeral) => {
-                                    trace!("Sending message to ephemeral: {:?}", msg);
-                                    write_ephem.send(msg).await.unwrap();
+            }
+            // For client messages we decide by message content if to send it
+            // to chain or ephem socket
+            next = read_client.next() => {
+                match next {
+                    Some(Ok(msg)) => {
+                        trace!("Client message: {:?}", msg);
+                        let delta = match check_client_msg(
+                            director.auth(),
+                            api_key.as_deref(),
+                            &mut connection,
+                            &msg,
+                            subscriptions,
+                        ) {
+                            Ok(delta) => delta,
+                            Err(response) => {
+                                if let Some(res) = response {
+                                    let _ = write_client.send(res).await;
                                 }
-                                Some(Both) => {
-                                    trace!("Sending message to chain and ephemeral: {:?}", msg);
-                                    write_chain.send(msg.clone()).await.unwrap();
-                                    write_ephem.send(msg).await.unwrap();
+                                continue;
+                            }
+                        };
+                        use RequestEndpoint::*;
+                        let endpoint = director
+                            .guide_msg_with_route_override(&msg, route_override)
+                            .await;
+                        let recipients = match endpoint {
+                            Some(Ephemeral) | Some(Both) => {
+                                match ephem_sockets.unsubscribe_recipients(&msg) {
+                                    Some(recipients) => recipients,
+                                    None => Recipients::new(
+                                        &director,
+                                        director.guide_ephemeral_validators(&msg).await,
+                                    ),
                                 }
-                                // If client sends a "close" message we return None as endpoint
-                                None => break
                             }
+                            _ => Recipients::default_validator(),
+                        };
+                        let msg = without_route_hint(msg);
+                        if let Some(endpoint) = &endpoint {
+                            inc_pubsub_routed_message(endpoint_label(endpoint));
+                            add_pubsub_subscriptions(delta);
+                            subscriptions += delta;
                         }
-                        Some(Err(err)) => {
-                            error!("Error reading client message: {:?}", err);
-                            break;
-                        }
-                        None => {
-                            debug!("Client stream ended");
-                            break;
+                        match endpoint {
+                            Some(Chain) => {
+                                trace!("Sending message to chain: {:?}", msg);
+                                write_chain.send(msg).await.unwrap()
+                            },
+                            Some(Ephemeral) => {
+                                trace!("Sending message to ephemeral: {:?}", msg);
+                                ephem_sockets.send(&director, recipients, msg).await;
+                            }
+                            Some(Both) => {
+                                trace!("Sending message to chain and ephemeral: {:?}", msg);
+                                write_chain.send(msg.clone()).await.unwrap();
+                                ephem_sockets.send(&director, recipients, msg).await;
+                            }
+                            // If client sends a "close" message we return None as endpoint
+                            None => break
                         }
                     }
-                },
+                    Some(Err(err)) => {
+                        error!("Error reading client message: {:?}", err);
+                        break;
+                    }
+                    None => {
+                        debug!("Client stream ended");
+                        break;
+                    }
+                }
+            },
+            _ = shutdown_requested(&mut shutdown_rx) => {
+                debug!("Closing connection of {} on shutdown", addr);
+                let close = Message::Close(Some(CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Director is shutting down".into(),
+                }));
+                if let Err(err) = write_client.send(close).await {
+                    debug!("Failed to close connection of {}: {:?}", addr, err);
+                }
+                break;
+            }
+        };
+    }
+    ephem_sockets.close_validators().await;
+    add_pubsub_subscriptions(-subscriptions);
+    dec_pubsub_connections();
+    Ok(())
+}
+
+fn endpoint_label(endpoint: &RequestEndpoint) -> &'static str {
+    match endpoint {
+        RequestEndpoint::Chain => "chain",
+        RequestEndpoint::Ephemeral => "ephemeral",
+        RequestEndpoint::Both => "both",
+    }
+}
+
+/// Checks a client message against its API key and its subscription
+/// limit.
+/// Returns by how much the message changes the subscriptions of the client
+/// if it may be forwarded, otherwise the error response if it was a request.
+fn check_client_msg(
+    auth: &ApiKeyAuth,
+    api_key: Option<&str>,
+    connection: &mut ClientConnection,
+    msg: &Message,
+    subscriptions: i64,
+) -> Result<i64, Option<Message>> {
+    let Message::Text(txt) = msg else {
+        return Ok(0);
+    };
+    let request = ClientRequestMessage::try_from(txt.as_str()).ok();
+    let method = request.as_ref().and_then(|req| req.method.as_deref());
+    if let Some(method) = method {
+        if let Err(err) = auth.authorize(api_key, method) {
+            debug!("Rejecting message of {}: {}", connection.client(), err);
+            return Err(request.map(|req| {
+                error_response(
+                    req.id,
+                    UNAUTHORIZED,
+                    err.to_string(),
+                    json!({ "reason": err.reason() }),
+                )
+            }));
+        }
+    }
+
+    let delta = subscriptions_delta(txt, subscriptions);
+    if let Err(err) = connection.add_subscriptions(delta) {
+        debug!("Rejecting message of {}: {}", connection.client(), err);
+        return Err(request.map(|req| {
+            error_response(
+                req.id,
+                RATE_LIMIT_EXCEEDED,
+                err.to_string(),
+                json!({ "limit": err.limit() }),
+            )
+        }));
+    }
+    Ok(delta)
+}
+
+/// By how much the subscriptions of the client change if the message
+/// succeeds
+fn subscriptions_delta(txt: &str, subscriptions: i64) -> i64 {
+    ClientSubMethod::try_from(txt)
+        .map(|method| method.subscriptions_delta())
+        .unwrap_or_default()
+        // Unsubscribing more than was subscribed doesn't end any
+        .max(-subscriptions)
+}
+
+/// JSON-RPC error response to the request with the given `id`
+fn error_response(
+    id: Value,
+    code: i32,
+    message: String,
+    data: Value,
+) -> Message {
+    let response = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+            "data": data,
+        },
+    });
+    Message::Text(response.to_string())
+}
+
+/// Ephemeral validator of a connection, `None` being the default one and
+/// `Some` a registered one with the given authority
+type EphemeralValidator = Option<Pubkey>;
+
+/// The ephemeral validators a message of the client is sent to
+struct Recipients {
+    default: bool,
+    registered: Vec<Pubkey>,
+}
+
+impl Recipients {
+    fn new<T: AccountProvider, U: SignatureStatusProvider>(
+        director: &DirectorPubsub<T, U>,
+        validators: EphemeralValidators,
+    ) -> Self {
+        match validators {
+            EphemeralValidators::Default => Self::default_validator(),
+            EphemeralValidators::Registered(authority) => Self {
+                default: false,
+                registered: vec![authority],
+            },
+            EphemeralValidators::All => Self {
+                default: true,
+                registered: director
+                    .validator_registry()
+                    .authorities()
+                    .cloned()
+                    .collect(),
+            },
+        }
+    }
+
+    fn default_validator() -> Self {
+        Self {
+            default: true,
+            registered: vec![],
+        }
+    }
+}
+
+/// The writers of the default ephemeral validator and of the registered
+/// validators that were connected so far
+struct EphemeralSockets {
+    default: BackendWebSocketWriter,
+    validators: HashMap<Pubkey, BackendWebSocketWriter>,
+    validator_tx: UnboundedSender<ValidatorMessage>,
+    /// Validators by the ids of the requests sent to them that they didn't
+    /// respond to yet
+    pending_requests: HashMap<String, HashSet<EphemeralValidator>>,
+    /// Validators by the ids of the subscriptions they hold, unsubscribing
+    /// only works on the validators that handed out the id
+    subscriptions: HashMap<u64, HashSet<EphemeralValidator>>,
+}
+
+impl EphemeralSockets {
+    /// Sends the message to the given validators.
+    /// Only text messages open sockets to validators that weren't connected
+    /// yet, control messages only concern the sockets that are open already.
+    async fn send<T: AccountProvider, U: SignatureStatusProvider>(
+        &mut self,
+        director: &DirectorPubsub<T, U>,
+        recipients: Recipients,
+        msg: Message,
+    ) {
+        let connect = matches!(msg, Message::Text(_));
+        for authority in recipients.registered {
+            if connect && !self.validators.contains_key(&authority) {
+                self.connect_validator(director, authority).await;
+            }
+            let Some(write_validator) = self.validators.get_mut(&authority)
+            else {
+                continue;
             };
+            trace!("Sending message to validator {}: {:?}", authority, msg);
+            if let Err(err) = write_validator.send(msg.clone()).await {
+                error!("Failed to send to validator {}: {:?}", authority, err);
+                continue;
+            }
+            self.track_request(Some(authority), &msg);
+        }
+        if recipients.default {
+            self.track_request(None, &msg);
+            self.default.send(msg).await.unwrap();
+        }
+    }
+
+    /// The validators holding the subscription the message ends if it is an
+    /// unsubscribe request, the subscription is forgotten
+    fn unsubscribe_recipients(&mut self, msg: &Message) -> Option<Recipients> {
+        let Message::Text(txt) = msg else {
+            return None;
+        };
+        let subscription = unsubscribe_subscription_id(txt)?;
+        let validators = self.subscriptions.remove(&subscription)?;
+        Some(Recipients {
+            default: validators.contains(&None),
+            registered: validators.into_iter().flatten().collect(),
+        })
+    }
+
+    /// Remembers that the validator was sent the message if it is a request
+    fn track_request(&mut self, validator: EphemeralValidator, msg: &Message) {
+        let Message::Text(txt) = msg else {
+            return;
+        };
+        let Ok(request) = ClientRequestMessage::try_from(txt.as_str()) else {
+            return;
+        };
+        if request.id.is_null() {
+            return;
+        }
+        self.pending_requests
+            .entry(request.id.to_string())
+            .or_default()
+            .insert(validator);
+    }
+
+    /// Remembers which validator handed out the subscription id if the
+    /// message responds to a subscribe request sent to it
+    fn track_response(&mut self, validator: EphemeralValidator, msg: &Message) {
+        let Message::Text(txt) = msg else {
+            return;
+        };
+        let Ok(response) = BackendResponseMessage::try_from(txt.as_str())
+        else {
+            return;
+        };
+        let id = response.id.to_string();
+        let Some(pending) = self.pending_requests.get_mut(&id) else {
+            return;
+        };
+        if !pending.remove(&validator) {
+            return;
+        }
+        if pending.is_empty() {
+            self.pending_requests.remove(&id);
+        }
+        if let Some(subscription) = response.result.as_u64() {
+            self.subscriptions
+                .entry(subscription)
+                .or_default()
+                .insert(validator);
+        }
+    }
+
+    async fn connect_validator<
+        T: AccountProvider,
+        U: SignatureStatusProvider,
+    >(
+        &mut self,
+        director: &DirectorPubsub<T, U>,
+        authority: Pubkey,
+    ) {
+        let socket = match director.try_validator_client(&authority).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                error!("Failed to connect to validator {}: {}", authority, err);
+                return;
+            }
+        };
+        debug!("Connected to validator {}", authority);
+        let (write_validator, read_validator) = socket.split();
+        self.validators.insert(authority, write_validator);
+        spawn_validator_reader(
+            authority,
+            read_validator,
+            self.validator_tx.clone(),
+        );
+    }
+
+    async fn close_validators(&mut self) {
+        for (_, mut write_validator) in self.validators.drain() {
+            let _ = write_validator.close().await;
+        }
+    }
+}
+
+fn spawn_validator_reader(
+    authority: Pubkey,
+    mut read_validator: SplitStream<BackendWebSocket>,
+    validator_tx: UnboundedSender<ValidatorMessage>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let next = read_validator.next().await;
+            let closed = next.is_none();
+            if validator_tx.send((authority, next)).is_err() || closed {
+                break;
+            }
         }
     });
-    Ok(())
+}
+
+fn route_override_from_request(
+    req: &Request,
+) -> Result<Option<RouteOverride>, String> {
+    let header = match req.headers().get(ROUTE_OVERRIDE_HEADER) {
+        Some(value) => Some(value.to_str().map_err(|err| {
+            format!("Invalid {} header: {:?}", ROUTE_OVERRIDE_HEADER, err)
+        })?),
+        None => None,
+    };
+    RouteOverride::try_from_path_and_header(req.uri().path(), header)
+}
+
+/// Resolves the API key from the query, the header or the subprotocol in
+/// that order
+fn api_key_from_request(req: &Request) -> Option<String> {
+    let header = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    api_key_from_query_and_header(req.uri().query(), header).or_else(|| {
+        api_key_subprotocol_from_request(req)
+            .and_then(|protocol| {
+                protocol.strip_prefix(API_KEY_SUBPROTOCOL_PREFIX)
+            })
+            .map(str::to_string)
+    })
+}
+
+fn api_key_subprotocol_from_request(req: &Request) -> Option<&str> {
+    req.headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(api_key_subprotocol)
+}
+
+fn bad_request(msg: String) -> ErrorResponse {
+    let mut res = ErrorResponse::new(Some(msg));
+    *res.status_mut() = StatusCode::BAD_REQUEST;
+    res
+}
+
+/// Removes the director specific route hint since the backends don't know
+/// about it and may reject the message otherwise
+fn without_route_hint(msg: Message) -> Message {
+    if let Message::Text(txt) = &msg {
+        if let Some(stripped) = strip_route_hint(txt) {
+            return Message::Text(stripped);
+        }
+    }
+    msg
 }
 
 struct HandleDownstreamMsgResult {
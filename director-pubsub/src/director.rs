@@ -1,25 +1,51 @@
-use conjunto_addresses::cluster::RpcCluster;
+use std::str::FromStr;
+
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
 use conjunto_core::{
-    AccountProvider, RequestEndpoint, SignatureStatusProvider,
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+    RequestEndpoint, SignatureStatusProvider,
+};
+use conjunto_guidepoint::{
+    auth::ApiKeyAuth,
+    rate_limit::{RateLimitConfig, RateLimiter},
+    tls::TlsTermination,
+    GuideStrategyResolver, RouteOverride,
+};
+use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
+    delegation_record_parser_impl::DelegationRecordParserImpl,
 };
-use conjunto_guidepoint::GuideStrategyResolver;
 use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_provider_config::RpcProviderConfig,
     rpc_signature_status_provider::RpcSignatureStatusProvider,
 };
 use log::*;
+use solana_sdk::pubkey::Pubkey;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use url::Url;
 
 use crate::{
-    errors::DirectorPubsubResult,
-    guide_strategy::guide_strategy_from_pubsub_msg, BackendWebSocket,
+    errors::{DirectorPubsubError, DirectorPubsubResult},
+    guide_strategy::guide_strategy_from_pubsub_msg,
+    messages::ParsedClientMessage,
+    BackendWebSocket,
 };
 
 pub struct DirectorPubsubConfig {
     pub chain_cluster: RpcCluster,
     pub ephem_rpc_provider_config: RpcProviderConfig,
+    /// Ephemeral validators besides the default one at
+    /// `ephem_rpc_provider_config`, keyed by their authority
+    pub validator_registry: ValidatorRegistry,
+    /// How many connections and subscriptions each client may have
+    pub rate_limit: RateLimitConfig,
+    /// The API keys clients need to provide, the default lets everyone in
+    pub auth: ApiKeyAuth,
+    /// If provided the server only accepts WSS connections
+    pub tls: Option<TlsTermination>,
 }
 
 impl DirectorPubsubConfig {
@@ -27,13 +53,34 @@ impl DirectorPubsubConfig {
         Self {
             chain_cluster: RpcCluster::Devnet,
             ephem_rpc_provider_config: RpcProviderConfig::magicblock_devnet(),
+            validator_registry: ValidatorRegistry::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: ApiKeyAuth::default(),
+            tls: None,
         }
     }
 }
 
+/// The ephemeral validators a message is sent to when it is guided to
+/// the ephemeral endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EphemeralValidators {
+    /// Only the default ephemeral validator
+    Default,
+    /// Only the registered validator with the given authority
+    Registered(Pubkey),
+    /// The default and all registered validators, control messages only
+    /// reach the ones the connection opened a socket to already
+    All,
+}
+
 pub struct DirectorPubsub<T: AccountProvider, U: SignatureStatusProvider> {
     config: DirectorPubsubConfig,
     guide_strategy_resolver: GuideStrategyResolver<T, U>,
+    /// Provides the delegation records of accounts in order to find the
+    /// validator they are delegated to
+    chain_account_provider: Option<T>,
+    rate_limiter: RateLimiter,
 }
 
 impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
@@ -46,11 +93,15 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
             RpcSignatureStatusProvider::new(
                 config.ephem_rpc_provider_config.clone(),
             );
+        let chain_account_provider = RpcAccountProvider::new(
+            RpcProviderConfig::new(config.chain_cluster.clone(), None),
+        );
         DirectorPubsub::with_providers(
             config,
             ephemeral_account_provider,
             ephemeral_signature_status_provider,
         )
+        .with_chain_account_provider(chain_account_provider)
     }
 
     pub fn with_providers(
@@ -62,12 +113,38 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
             ephemeral_account_provider,
             ephemeral_signature_status_provider,
         );
+        let rate_limiter = RateLimiter::new(config.rate_limit.clone());
         Self {
             config,
             guide_strategy_resolver,
+            chain_account_provider: None,
+            rate_limiter,
         }
     }
 
+    /// Sets the provider used to look up delegation records on chain.
+    /// Without it all ephemeral messages go to the default ephemeral validator.
+    pub fn with_chain_account_provider(mut self, provider: T) -> Self {
+        self.chain_account_provider = Some(provider);
+        self
+    }
+
+    pub fn validator_registry(&self) -> &ValidatorRegistry {
+        &self.config.validator_registry
+    }
+
+    pub(crate) fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    pub(crate) fn auth(&self) -> &ApiKeyAuth {
+        &self.config.auth
+    }
+
+    pub(crate) fn tls(&self) -> Option<&TlsTermination> {
+        self.config.tls.as_ref()
+    }
+
     pub(super) async fn guide_msg(
         &self,
         msg: &Message,
@@ -97,6 +174,102 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
         Some(endpoint)
     }
 
+    /// Guides text messages to the backend the client explicitly picked for
+    /// the connection if any, all other messages are guided as usual.
+    pub(super) async fn guide_msg_with_route_override(
+        &self,
+        msg: &Message,
+        route_override: Option<RouteOverride>,
+    ) -> Option<RequestEndpoint> {
+        match (route_override, msg) {
+            (Some(route), Message::Text(_)) => {
+                debug!("Guiding message to picked route: {}", route);
+                Some(route.into())
+            }
+            _ => self.guide_msg(msg).await,
+        }
+    }
+
+    /// Determines which ephemeral validators should receive a message.
+    /// Account subscriptions go to the validator the account is delegated to.
+    /// Program and signature subscriptions go to all validators since they
+    /// may concern accounts or transactions of any of them, all other
+    /// requests to the default validator. Unsubscribing is guided per
+    /// connection since only it knows which validators hold a subscription.
+    /// Control messages concern all validators.
+    pub(super) async fn guide_ephemeral_validators(
+        &self,
+        msg: &Message,
+    ) -> EphemeralValidators {
+        if self.config.validator_registry.is_empty() {
+            return EphemeralValidators::Default;
+        }
+        let Message::Text(txt) = msg else {
+            return EphemeralValidators::All;
+        };
+        match ParsedClientMessage::try_from(txt.as_str()) {
+            Ok(ParsedClientMessage::AccountSubscribe { address }) => {
+                let Ok(pubkey) = Pubkey::from_str(&address) else {
+                    return EphemeralValidators::Default;
+                };
+                match self.delegation_authority(&pubkey).await {
+                    Some(authority)
+                        if self
+                            .config
+                            .validator_registry
+                            .get(&authority)
+                            .is_some() =>
+                    {
+                        debug!(
+                            "Guiding account {} to validator {}",
+                            pubkey, authority
+                        );
+                        EphemeralValidators::Registered(authority)
+                    }
+                    _ => EphemeralValidators::Default,
+                }
+            }
+            Ok(ParsedClientMessage::ProgramSubscribe { .. })
+            | Ok(ParsedClientMessage::SignatureSubscribe { .. }) => {
+                EphemeralValidators::All
+            }
+            _ => EphemeralValidators::Default,
+        }
+    }
+
+    /// Finds the authority of the validator the account is delegated to via
+    /// its delegation record
+    async fn delegation_authority(&self, pubkey: &Pubkey) -> Option<Pubkey> {
+        let provider = self.chain_account_provider.as_ref()?;
+        let delegation_record_pda =
+            AccountChainSnapshot::delegation_record_pda(pubkey);
+        let account =
+            match provider.get_account(&delegation_record_pda, None).await {
+                Ok((_, account)) => account?,
+                Err(err) => {
+                    warn!(
+                        "Failed to fetch delegation record of {}: {:?}",
+                        pubkey, err
+                    );
+                    return None;
+                }
+            };
+        if account.owner != AccountChainSnapshot::delegation_record_pda_owner()
+        {
+            return None;
+        }
+        match DelegationRecordParserImpl.try_parse(&account.data) {
+            Ok(record) => Some(record.authority),
+            Err(err) => {
+                warn!(
+                    "Failed to parse delegation record of {}: {:?}",
+                    pubkey, err
+                );
+                None
+            }
+        }
+    }
+
     pub async fn try_chain_client(
         &self,
     ) -> DirectorPubsubResult<BackendWebSocket> {
@@ -112,6 +285,22 @@ impl<T: AccountProvider, U: SignatureStatusProvider> DirectorPubsub<T, U> {
         let (socket, _) = connect_async(Url::parse(url)?).await?;
         Ok(socket)
     }
+
+    /// Connects to the registered ephemeral validator with the given authority
+    pub async fn try_validator_client(
+        &self,
+        authority: &Pubkey,
+    ) -> DirectorPubsubResult<BackendWebSocket> {
+        let cluster = self
+            .config
+            .validator_registry
+            .get(authority)
+            .ok_or_else(|| {
+                DirectorPubsubError::UnknownValidator(authority.to_string())
+            })?;
+        let (socket, _) = connect_async(Url::parse(cluster.ws_url())?).await?;
+        Ok(socket)
+    }
 }
 
 #[cfg(test)]
@@ -210,5 +399,115 @@ mod tests {
         guide_and_assert(&director, subscribe, &RequestEndpoint::Chain).await;
     }
 
+    #[tokio::test]
+    async fn test_guide_with_route_override() {
+        let director = DirectorPubsub::with_providers(
+            DirectorPubsubConfig::devnet(),
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        let msg = Message::Text(subscribe_signature().to_string());
+
+        // Without override the signature is not found in ephemeral
+        let actual = director
+            .guide_msg_with_route_override(&msg, None)
+            .await
+            .unwrap();
+        assert_eq!(actual, RequestEndpoint::Both);
+
+        let actual = director
+            .guide_msg_with_route_override(&msg, Some(RouteOverride::Ephemeral))
+            .await
+            .unwrap();
+        assert_eq!(actual, RequestEndpoint::Ephemeral);
+
+        // Control messages are not affected by the override
+        let actual = director
+            .guide_msg_with_route_override(
+                &Message::Ping(vec![]),
+                Some(RouteOverride::Chain),
+            )
+            .await
+            .unwrap();
+        assert_eq!(actual, RequestEndpoint::Both);
+    }
+
+    // -----------------
+    // Ephemeral Validators
+    // -----------------
+    fn director_with_validator(
+        chain_account_provider: AccountProviderStub,
+    ) -> DirectorPubsub<AccountProviderStub, SignatureStatusProviderStub> {
+        let config = DirectorPubsubConfig {
+            validator_registry: ValidatorRegistry::default()
+                .with_validator(Pubkey::new_unique(), RpcCluster::Development),
+            ..DirectorPubsubConfig::devnet()
+        };
+        DirectorPubsub::with_providers(
+            config,
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        )
+        .with_chain_account_provider(chain_account_provider)
+    }
+
+    fn account_subscribe(pubkey: &Pubkey) -> Message {
+        Message::Text(
+            serde_json::json! {{
+                "method": "accountSubscribe",
+                "params": [pubkey.to_string()]
+            }}
+            .to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_guide_ephemeral_validators_without_registry() {
+        let director = DirectorPubsub::with_providers(
+            DirectorPubsubConfig::devnet(),
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        let msg = Message::Text(subscribe_signature().to_string());
+        assert_eq!(
+            director.guide_ephemeral_validators(&msg).await,
+            EphemeralValidators::Default
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guide_ephemeral_validators_account_without_delegation_record()
+    {
+        let director = director_with_validator(AccountProviderStub::default());
+        let msg = account_subscribe(&Pubkey::new_unique());
+        assert_eq!(
+            director.guide_ephemeral_validators(&msg).await,
+            EphemeralValidators::Default
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guide_ephemeral_validators_other_messages_go_to_all() {
+        let director = director_with_validator(AccountProviderStub::default());
+        let msg = Message::Text(subscribe_signature().to_string());
+        assert_eq!(
+            director.guide_ephemeral_validators(&msg).await,
+            EphemeralValidators::All
+        );
+        let msg = Message::Text(
+            serde_json::json! {{ "method": "slotSubscribe" }}.to_string(),
+        );
+        assert_eq!(
+            director.guide_ephemeral_validators(&msg).await,
+            EphemeralValidators::Default
+        );
+        assert_eq!(
+            director
+                .guide_ephemeral_validators(&Message::Ping(vec![]))
+                .await,
+            EphemeralValidators::All
+        );
+    }
+
     // TODO(thlorenz): Add more tests for other pubsub messages
 }
@@ -15,4 +15,7 @@ pub enum DirectorPubsubError {
 
     #[error("ParseClientSubscription error: {0}")]
     ParseClientSubscription(String),
+
+    #[error("Validator {0} is not registered")]
+    UnknownValidator(String),
 }
@@ -2,7 +2,17 @@ use conjunto_core::GuideStrategy;
 use log::*;
 use solana_rpc_client_api::config::RpcTransactionLogsFilter;
 
-use crate::messages::ParsedClientMessage;
+use crate::messages::{ParsedClientMessage, RouteHint};
+
+impl From<RouteHint> for GuideStrategy {
+    fn from(route: RouteHint) -> Self {
+        match route {
+            RouteHint::Chain => GuideStrategy::Chain,
+            RouteHint::Ephemeral => GuideStrategy::Ephemeral,
+            RouteHint::Both => GuideStrategy::Both,
+        }
+    }
+}
 
 pub fn guide_strategy_from_pubsub_msg(msg: &str) -> GuideStrategy {
     let parsed = match ParsedClientMessage::try_from(msg) {
@@ -35,13 +45,16 @@ pub fn guide_strategy_from_pubsub_msg(msg: &str) -> GuideStrategy {
         | SlotsUpdatesUnsubscribe
         | VoteUnsubscribe => GuideStrategy::Both,
 
-        // Subscribe methods that always go to chain since they
+        // Subscribe methods that go to chain by default since they
         // are either not at all supported by the ephem validator
-        // and/or still in beta
-        BlockSubscribe
-        | RootSubscribe
-        | SlotsUpdatesSubscribe
-        | VoteSubscribe => GuideStrategy::Chain,
+        // and/or still in beta.
+        // The client can opt into another backend via a route hint.
+        BlockSubscribe { route }
+        | RootSubscribe { route }
+        | SlotsUpdatesSubscribe { route }
+        | VoteSubscribe { route } => route
+            .map(GuideStrategy::from)
+            .unwrap_or(GuideStrategy::Chain),
 
         // We expect the client to want to see faster moving slots of our
         // ephemeral validator
@@ -179,6 +192,41 @@ mod tests {
         );
     }
     #[test]
+    fn test_guide_block_subscribe_with_route_hint() {
+        guide_and_assert(
+            serde_json::json! {{
+                "method": "blockSubscribe",
+                "params": ["all"]
+            }},
+            &GuideStrategy::Chain,
+        );
+        guide_and_assert(
+            serde_json::json! {{
+                "method": "blockSubscribe",
+                "params": ["all"],
+                "conjuntoRoute": "ephemeral"
+            }},
+            &GuideStrategy::Ephemeral,
+        );
+    }
+    #[test]
+    fn test_guide_slots_updates_subscribe_with_route_hint() {
+        guide_and_assert(
+            serde_json::json! {{
+                "method": "slotsUpdatesSubscribe",
+                "conjuntoRoute": "both"
+            }},
+            &GuideStrategy::Both,
+        );
+        guide_and_assert(
+            serde_json::json! {{
+                "method": "slotsUpdatesSubscribe",
+                "conjuntoRoute": "chain"
+            }},
+            &GuideStrategy::Chain,
+        );
+    }
+    #[test]
     fn test_guide_account_unsubscribe() {
         guide_and_assert(
             serde_json::json! {{
@@ -1,16 +1,25 @@
-use std::sync::Arc;
+use std::{io, net::SocketAddr, sync::Arc};
 
+use accept_connection::{accept_handshake, close_client};
 use conjunto_core::{AccountProvider, SignatureStatusProvider};
+use conjunto_guidepoint::{
+    client_stream::{ClientStream, WebSocketUpgrade, WebSocketUpgradeReceiver},
+    shutdown::{
+        drain_connections, shutdown_channel, shutdown_requested, ServerHandle,
+        ShutdownReceiver, ShutdownSummary,
+    },
+};
 use director::{DirectorPubsub, DirectorPubsubConfig};
 use errors::DirectorPubsubResult;
 use futures_util::stream::SplitSink;
 use log::*;
 use tokio::{
     net::{TcpListener, TcpStream},
-    task::JoinHandle,
+    task::JoinSet,
 };
 use tokio_tungstenite::{
-    tungstenite::Message, MaybeTlsStream, WebSocketStream,
+    tungstenite::{protocol::frame::coding::CloseCode, Message},
+    MaybeTlsStream, WebSocketStream,
 };
 
 mod accept_connection;
@@ -31,34 +40,217 @@ pub async fn start_pubsub_server<
 >(
     config: DirectorPubsubConfig,
     url: Option<&str>,
-) -> DirectorPubsubResult<(String, JoinHandle<()>)> {
+) -> DirectorPubsubResult<(String, ServerHandle)> {
+    let director = DirectorPubsub::<T, U>::new(config);
+    start_pubsub_server_with_director(director, url).await
+}
+
+/// Starts the pubsub server with a director that was already set up, i.e.
+/// with custom providers.
+/// Returns the address the server is listening on which is useful when
+/// binding to port `0`.
+pub async fn start_pubsub_server_with_director<
+    T: AccountProvider,
+    U: SignatureStatusProvider,
+>(
+    director: DirectorPubsub<T, U>,
+    url: Option<&str>,
+) -> DirectorPubsubResult<(String, ServerHandle)> {
     let url = url.unwrap_or(DEFAULT_DIRECTOR_PUBSUB_URL);
     let listener = TcpListener::bind(&url).await?;
-    let director = Arc::new(DirectorPubsub::<T, U>::new(config));
-    let pubsub_handle = tokio::spawn(async move {
-        while let Ok((stream, _)) = listener.accept().await {
-            let chain_client = match director.try_chain_client().await {
-                Err(err) => {
-                    error!("Failed to connect to chain client: {}", err);
-                    continue;
+    let addr = listener.local_addr()?;
+    let handle = spawn_pubsub_server(Incoming::Listener(listener), director);
+    Ok((addr.to_string(), handle))
+}
+
+/// Starts the pubsub server for the websocket upgrades the RPC server hands
+/// over, serving both on the port of the RPC server
+pub fn start_pubsub_server_for_upgrades<
+    T: AccountProvider,
+    U: SignatureStatusProvider,
+>(
+    config: DirectorPubsubConfig,
+    upgrades: WebSocketUpgradeReceiver,
+) -> ServerHandle {
+    let director = DirectorPubsub::<T, U>::new(config);
+    start_pubsub_server_for_upgrades_with_director(director, upgrades)
+}
+
+/// Same as [start_pubsub_server_for_upgrades] with a director that was
+/// already set up
+pub fn start_pubsub_server_for_upgrades_with_director<
+    T: AccountProvider,
+    U: SignatureStatusProvider,
+>(
+    director: DirectorPubsub<T, U>,
+    upgrades: WebSocketUpgradeReceiver,
+) -> ServerHandle {
+    spawn_pubsub_server(Incoming::Upgrades(upgrades), director)
+}
+
+fn spawn_pubsub_server<T: AccountProvider, U: SignatureStatusProvider>(
+    incoming: Incoming,
+    director: DirectorPubsub<T, U>,
+) -> ServerHandle {
+    let (shutdown_tx, shutdown_rx) = shutdown_channel();
+    let task =
+        tokio::spawn(serve_pubsub(incoming, Arc::new(director), shutdown_rx));
+    ServerHandle::new("Pubsub", shutdown_tx, task)
+}
+
+/// Where the pubsub server gets the connections of its clients from
+enum Incoming {
+    Listener(TcpListener),
+    /// Websocket upgrades the RPC server received on its port
+    Upgrades(WebSocketUpgradeReceiver),
+}
+
+/// A client connection as it was accepted
+enum IncomingStream {
+    Tcp(TcpStream),
+    /// TLS was already terminated by the RPC server if configured
+    Upgrade(ClientStream),
+}
+
+impl Incoming {
+    async fn accept(&mut self) -> io::Result<(IncomingStream, SocketAddr)> {
+        match self {
+            Incoming::Listener(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((IncomingStream::Tcp(stream), addr))
+            }
+            Incoming::Upgrades(upgrades) => match upgrades.recv().await {
+                Some(WebSocketUpgrade { stream, addr }) => {
+                    Ok((IncomingStream::Upgrade(stream), addr))
+                }
+                // The RPC server stopped, the existing connections are
+                // served until this server is shut down as well
+                None => std::future::pending().await,
+            },
+        }
+    }
+}
+
+/// Accepts connections until the server is shut down, then closes all of
+/// them
+async fn serve_pubsub<T: AccountProvider, U: SignatureStatusProvider>(
+    mut incoming: Incoming,
+    director: Arc<DirectorPubsub<T, U>>,
+    mut shutdown_rx: ShutdownReceiver,
+) -> ShutdownSummary {
+    director.auth().spawn_reloader();
+    if let Some(tls) = director.tls() {
+        tls.spawn_reloader();
+    }
+    let mut connections = JoinSet::new();
+    let drain_timeout = loop {
+        tokio::select! {
+            accepted = incoming.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    connections.spawn(serve_connection(
+                        director.clone(),
+                        stream,
+                        addr,
+                        shutdown_rx.clone(),
+                    ));
                 }
-                Ok(client) => client,
-            };
-            let ephem_client = match director.try_ephemeral_client().await {
                 Err(err) => {
-                    error!("Failed to connect to ephemeral client: {}", err);
-                    continue;
+                    error!("Failed to accept pubsub connection: {:?}", err);
                 }
-                Ok(client) => client,
-            };
-            tokio::spawn(accept_connection::accept_connection(
-                director.clone(),
-                chain_client,
-                ephem_client,
-                stream,
-            ));
+            },
+            // Reaps the connections that were closed
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            drain_timeout = shutdown_requested(&mut shutdown_rx) => {
+                break drain_timeout;
+            }
         }
-    });
+    };
+    drop(incoming);
+
+    info!(
+        "Shutting down pubsub server, closing {} connection(s)",
+        connections.len()
+    );
+    // Clients have no requests to wait for, they are sent a close frame
+    drain_connections(connections, drain_timeout, 0).await
+}
 
-    Ok((url.to_string(), pubsub_handle))
+async fn serve_connection<T: AccountProvider, U: SignatureStatusProvider>(
+    director: Arc<DirectorPubsub<T, U>>,
+    stream: IncomingStream,
+    addr: SocketAddr,
+    shutdown_rx: ShutdownReceiver,
+) {
+    let stream = match (stream, director.tls()) {
+        (IncomingStream::Tcp(stream), Some(tls)) => {
+            match tls.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("TLS handshake with {} failed: {:?}", addr, err);
+                    return;
+                }
+            }
+        }
+        (IncomingStream::Tcp(stream), None) => ClientStream::plain(stream),
+        (IncomingStream::Upgrade(stream), _) => stream,
+    };
+    let client = match accept_handshake(stream, addr).await {
+        Err(err) => {
+            debug!("Pubsub handshake failed: {:?}", err);
+            return;
+        }
+        Ok(client) => client,
+    };
+    // Checked before connecting to the backends so that rejected clients
+    // don't cost any upstream connections
+    if let Err(err) = director.auth().authenticate(client.api_key.as_deref()) {
+        debug!("Rejecting connection of {}: {}", client.addr, err);
+        close_client(client.socket, CloseCode::Policy, err.to_string()).await;
+        return;
+    }
+    let rate_limiter = director.rate_limiter();
+    let client_key = rate_limiter.client_key(
+        client.addr.ip(),
+        client.api_key.as_deref(),
+        director.auth(),
+    );
+    let connection = match rate_limiter.open_connection(client_key) {
+        Err(err) => {
+            debug!("Rejecting connection of {}: {}", client.addr, err);
+            close_client(client.socket, CloseCode::Policy, err.to_string())
+                .await;
+            return;
+        }
+        Ok(connection) => connection,
+    };
+    let chain_client = match director.try_chain_client().await {
+        Err(err) => {
+            error!("Failed to connect to chain client: {}", err);
+            let reason = "Failed to connect to chain".to_string();
+            close_client(client.socket, CloseCode::Error, reason).await;
+            return;
+        }
+        Ok(client) => client,
+    };
+    let ephem_client = match director.try_ephemeral_client().await {
+        Err(err) => {
+            error!("Failed to connect to ephemeral client: {}", err);
+            let reason = "Failed to connect to ephemeral validator".to_string();
+            close_client(client.socket, CloseCode::Error, reason).await;
+            return;
+        }
+        Ok(client) => client,
+    };
+    if let Err(err) = accept_connection::accept_connection(
+        director,
+        chain_client,
+        ephem_client,
+        client,
+        connection,
+        shutdown_rx,
+    )
+    .await
+    {
+        debug!("Pubsub connection failed: {:?}", err);
+    }
 }
@@ -37,6 +37,35 @@ pub enum ClientSubMethod {
     VoteUnsubscribe,
 }
 
+impl ClientSubMethod {
+    /// How the number of subscriptions of the client changes if the request
+    /// succeeds
+    pub fn subscriptions_delta(&self) -> i64 {
+        use ClientSubMethod::*;
+        match self {
+            Ping | Pong => 0,
+            AccountSubscribe
+            | BlockSubscribe
+            | LogsSubscribe
+            | ProgramSubscribe
+            | RootSubscribe
+            | SignatureSubscribe
+            | SlotSubscribe
+            | SlotsUpdatesSubscribe
+            | VoteSubscribe => 1,
+            AccountUnsubscribe
+            | BlockUnsubscribe
+            | LogsUnsubscribe
+            | ProgramUnsubscribe
+            | RootUnsubscribe
+            | SignatureUnsubscribe
+            | SlotUnsubscribe
+            | SlotsUpdatesUnsubscribe
+            | VoteUnsubscribe => -1,
+        }
+    }
+}
+
 impl TryFrom<&str> for ClientSubMethod {
     type Error = serde_json::Error;
 
@@ -45,6 +74,109 @@ impl TryFrom<&str> for ClientSubMethod {
         Ok(msg.method)
     }
 }
+
+// -----------------
+// ClientRequest
+// -----------------
+/// Message which only pulls out the id and the method of the request when
+/// deserialized
+#[derive(Deserialize)]
+pub struct ClientRequestMessage {
+    #[serde(default)]
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub method: Option<String>,
+}
+
+impl TryFrom<&str> for ClientRequestMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<ClientRequestMessage>(value)
+    }
+}
+
+// -----------------
+// Subscription ids
+// -----------------
+/// Unsubscribe request which only pulls out the id of the subscription it
+/// ends when deserialized
+#[derive(Deserialize)]
+struct ClientUnsubscribeMessage {
+    params: (u64,),
+}
+
+/// The id of the subscription the message ends if it is an unsubscribe
+/// request
+pub fn unsubscribe_subscription_id(msg: &str) -> Option<u64> {
+    let method = ClientSubMethod::try_from(msg).ok()?;
+    if method.subscriptions_delta() >= 0 {
+        return None;
+    }
+    let msg = serde_json::from_str::<ClientUnsubscribeMessage>(msg).ok()?;
+    Some(msg.params.0)
+}
+
+/// Response of a backend which only pulls out the id of the request and the
+/// result when deserialized, notifications don't have an id
+#[derive(Deserialize)]
+pub struct BackendResponseMessage {
+    pub id: serde_json::Value,
+    #[serde(default)]
+    pub result: serde_json::Value,
+}
+
+impl TryFrom<&str> for BackendResponseMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<BackendResponseMessage>(value)
+    }
+}
+
+// -----------------
+// RouteHint
+// -----------------
+/// Director specific top level field of a client message which allows the
+/// client to explicitly pick the backend for subscriptions that we'd otherwise
+/// pin to chain.
+/// It is removed from the message before it is forwarded to any backend.
+pub const ROUTE_HINT_FIELD: &str = "conjuntoRoute";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteHint {
+    Chain,
+    Ephemeral,
+    Both,
+}
+
+/// Message which only pulls out the route hint when deserialized
+#[derive(Deserialize)]
+pub struct ClientRouteHintMessage {
+    #[serde(rename = "conjuntoRoute", default)]
+    pub route: Option<RouteHint>,
+}
+
+impl TryFrom<&str> for ClientRouteHintMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        serde_json::from_str::<ClientRouteHintMessage>(value)
+    }
+}
+
+/// Returns the message without the [ROUTE_HINT_FIELD] if it included one,
+/// otherwise [None] is returned and the message can be forwarded as is.
+pub fn strip_route_hint(msg: &str) -> Option<String> {
+    // Avoid parsing every message if it cannot contain the hint
+    if !msg.contains(ROUTE_HINT_FIELD) {
+        return None;
+    }
+    let mut value = serde_json::from_str::<serde_json::Value>(msg).ok()?;
+    value.as_object_mut()?.remove(ROUTE_HINT_FIELD)?;
+    Some(value.to_string())
+}
 // -----------------
 // ClientSubWithParams
 // -----------------
@@ -125,7 +257,7 @@ pub enum ParsedClientMessage {
     AccountSubscribe { address: String },
     AccountUnsubscribe,
 
-    BlockSubscribe,
+    BlockSubscribe { route: Option<RouteHint> },
     BlockUnsubscribe,
 
     LogsSubscribe { filter: RpcTransactionLogsFilter },
@@ -134,7 +266,7 @@ pub enum ParsedClientMessage {
     ProgramSubscribe { program_id: String },
     ProgramUnsubscribe,
 
-    RootSubscribe,
+    RootSubscribe { route: Option<RouteHint> },
     RootUnsubscribe,
 
     SignatureSubscribe { signature: String },
@@ -142,9 +274,9 @@ pub enum ParsedClientMessage {
 
     SlotSubscribe,
     SlotUnsubscribe,
-    SlotsUpdatesSubscribe,
+    SlotsUpdatesSubscribe { route: Option<RouteHint> },
     SlotsUpdatesUnsubscribe,
-    VoteSubscribe,
+    VoteSubscribe { route: Option<RouteHint> },
     VoteUnsubscribe,
 }
 
@@ -172,7 +304,9 @@ impl TryFrom<&str> for ParsedClientMessage {
                 }
             }
             AccountUnsubscribe => Ok(Self::AccountUnsubscribe),
-            BlockSubscribe => Ok(Self::BlockSubscribe),
+            BlockSubscribe => Ok(Self::BlockSubscribe {
+                route: ClientRouteHintMessage::try_from(msg)?.route,
+            }),
             BlockUnsubscribe => Ok(Self::BlockUnsubscribe),
             LogsSubscribe => {
                 let params = ClientSubWithParams::try_from(msg)?;
@@ -201,7 +335,9 @@ impl TryFrom<&str> for ParsedClientMessage {
                 }
             }
             ProgramUnsubscribe => Ok(Self::ProgramUnsubscribe),
-            RootSubscribe => Ok(Self::RootSubscribe),
+            RootSubscribe => Ok(Self::RootSubscribe {
+                route: ClientRouteHintMessage::try_from(msg)?.route,
+            }),
             RootUnsubscribe => Ok(Self::RootUnsubscribe),
             SignatureSubscribe => {
                 let params = ClientSubWithParams::try_from(msg)?;
@@ -220,9 +356,13 @@ impl TryFrom<&str> for ParsedClientMessage {
             SignatureUnsubscribe => Ok(Self::SignatureUnsubscribe),
             SlotSubscribe => Ok(Self::SlotSubscribe),
             SlotUnsubscribe => Ok(Self::SlotUnsubscribe),
-            SlotsUpdatesSubscribe => Ok(Self::SlotsUpdatesSubscribe),
+            SlotsUpdatesSubscribe => Ok(Self::SlotsUpdatesSubscribe {
+                route: ClientRouteHintMessage::try_from(msg)?.route,
+            }),
             SlotsUpdatesUnsubscribe => Ok(Self::SlotsUpdatesUnsubscribe),
-            VoteSubscribe => Ok(Self::VoteSubscribe),
+            VoteSubscribe => Ok(Self::VoteSubscribe {
+                route: ClientRouteHintMessage::try_from(msg)?.route,
+            }),
             VoteUnsubscribe => Ok(Self::VoteUnsubscribe),
         }
     }
@@ -454,4 +594,102 @@ mod tests {
             &ParsedClientMessage::SlotSubscribe,
         );
     }
+
+    #[test]
+    fn test_parse_route_hint() {
+        parse_and_assert(
+            serde_json::json! {{
+                "method": "blockSubscribe",
+                "params": ["all"]
+            }},
+            &ParsedClientMessage::BlockSubscribe { route: None },
+        );
+        parse_and_assert(
+            serde_json::json! {{
+                "method": "blockSubscribe",
+                "params": ["all"],
+                "conjuntoRoute": "ephemeral"
+            }},
+            &ParsedClientMessage::BlockSubscribe {
+                route: Some(RouteHint::Ephemeral),
+            },
+        );
+        parse_and_assert(
+            serde_json::json! {{
+                "method": "slotsUpdatesSubscribe",
+                "conjuntoRoute": "both"
+            }},
+            &ParsedClientMessage::SlotsUpdatesSubscribe {
+                route: Some(RouteHint::Both),
+            },
+        );
+        parse_and_assert(
+            serde_json::json! {{
+                "method": "voteSubscribe",
+                "conjuntoRoute": "chain"
+            }},
+            &ParsedClientMessage::VoteSubscribe {
+                route: Some(RouteHint::Chain),
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_route_hint() {
+        let msg = serde_json::json! {{
+            "method": "rootSubscribe",
+            "conjuntoRoute": "moon"
+        }};
+        assert!(
+            ParsedClientMessage::try_from(msg.to_string().as_str()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_strip_route_hint() {
+        let msg = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "blockSubscribe",
+            "params": ["all"],
+            "conjuntoRoute": "ephemeral"
+        }};
+        let stripped = strip_route_hint(msg.to_string().as_str()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&stripped).unwrap(),
+            serde_json::json! {{
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "blockSubscribe",
+                "params": ["all"]
+            }}
+        );
+
+        let msg = serde_json::json! {{
+            "method": "blockSubscribe",
+            "params": ["all"]
+        }};
+        assert!(strip_route_hint(msg.to_string().as_str()).is_none());
+    }
+
+    #[test]
+    fn test_unsubscribe_subscription_id() {
+        let unsubscribe = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "accountUnsubscribe",
+            "params": [11]
+        }};
+        assert_eq!(
+            unsubscribe_subscription_id(&unsubscribe.to_string()),
+            Some(11)
+        );
+        let subscribe = serde_json::json! {{
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "slotSubscribe",
+            "params": [11]
+        }};
+        assert_eq!(unsubscribe_subscription_id(&subscribe.to_string()), None);
+    }
 }
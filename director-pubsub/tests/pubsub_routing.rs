@@ -0,0 +1,837 @@
+use std::{fs, mem::size_of, path::PathBuf, time::Duration};
+
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
+use conjunto_director_pubsub::{
+    director::{DirectorPubsub, DirectorPubsubConfig},
+    start_pubsub_server_with_director,
+};
+use conjunto_guidepoint::{
+    auth::ApiKeyAuth,
+    rate_limit::RateLimitConfig,
+    shutdown::{ServerHandle, ShutdownSummary},
+    tls::TlsTermination,
+    RouteOverride, ROUTE_OVERRIDE_HEADER,
+};
+use conjunto_providers::rpc_provider_config::RpcProviderConfig;
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    accounts::{
+        account_owned_by_system_program, delegated_account_ids,
+        DELEGATION_PROGRAM_ID,
+    },
+    mock_websocket_server::MockWebsocketServer,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+    tls::TestCertificate,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Signature};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    client_async, connect_async,
+    tungstenite::{
+        client::IntoClientRequest, http::header::SEC_WEBSOCKET_PROTOCOL,
+        protocol::frame::coding::CloseCode, Error as WsError, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+type ClientWebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct TestSetup {
+    chain: MockWebsocketServer,
+    ephem: MockWebsocketServer,
+    director_url: String,
+    handle: ServerHandle,
+}
+
+impl TestSetup {
+    async fn start(account_provider: AccountProviderStub) -> Self {
+        Self::start_with_validators(
+            account_provider,
+            None,
+            ValidatorRegistry::default(),
+        )
+        .await
+    }
+
+    async fn start_with_validators(
+        account_provider: AccountProviderStub,
+        chain_account_provider: Option<AccountProviderStub>,
+        validator_registry: ValidatorRegistry,
+    ) -> Self {
+        Self::start_with_config(
+            account_provider,
+            chain_account_provider,
+            validator_registry,
+            RateLimitConfig::default(),
+            ApiKeyAuth::default(),
+            None,
+        )
+        .await
+    }
+
+    async fn start_with_rate_limit(rate_limit: RateLimitConfig) -> Self {
+        Self::start_with_config(
+            setup_account_provider(&[]),
+            None,
+            ValidatorRegistry::default(),
+            rate_limit,
+            ApiKeyAuth::default(),
+            None,
+        )
+        .await
+    }
+
+    async fn start_with_auth(auth: ApiKeyAuth) -> Self {
+        Self::start_with_config(
+            setup_account_provider(&[]),
+            None,
+            ValidatorRegistry::default(),
+            RateLimitConfig::default(),
+            auth,
+            None,
+        )
+        .await
+    }
+
+    async fn start_with_tls(cert: &TestCertificate) -> Self {
+        let tls = TlsTermination::from_files(
+            &cert.cert_file,
+            &cert.key_file,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        Self::start_with_config(
+            setup_account_provider(&[]),
+            None,
+            ValidatorRegistry::default(),
+            RateLimitConfig::default(),
+            ApiKeyAuth::default(),
+            Some(tls),
+        )
+        .await
+    }
+
+    async fn start_with_config(
+        account_provider: AccountProviderStub,
+        chain_account_provider: Option<AccountProviderStub>,
+        validator_registry: ValidatorRegistry,
+        rate_limit: RateLimitConfig,
+        auth: ApiKeyAuth,
+        tls: Option<TlsTermination>,
+    ) -> Self {
+        let chain = MockWebsocketServer::start().await;
+        let ephem = MockWebsocketServer::start().await;
+        let scheme = if tls.is_some() { "wss" } else { "ws" };
+        let config = DirectorPubsubConfig {
+            chain_cluster: RpcCluster::Custom(
+                "http://127.0.0.1:0".to_string(),
+                chain.ws_url(),
+            ),
+            ephem_rpc_provider_config: RpcProviderConfig::new(
+                RpcCluster::Custom(
+                    "http://127.0.0.1:0".to_string(),
+                    ephem.ws_url(),
+                ),
+                None,
+            ),
+            validator_registry,
+            rate_limit,
+            auth,
+            tls,
+        };
+        let mut director = DirectorPubsub::with_providers(
+            config,
+            account_provider,
+            SignatureStatusProviderStub::default(),
+        );
+        if let Some(chain_account_provider) = chain_account_provider {
+            director =
+                director.with_chain_account_provider(chain_account_provider);
+        }
+        let (addr, handle) =
+            start_pubsub_server_with_director(director, Some("127.0.0.1:0"))
+                .await
+                .unwrap();
+        Self {
+            chain,
+            ephem,
+            director_url: format!("{}://{}", scheme, addr),
+            handle,
+        }
+    }
+
+    async fn connect(&self, path: &str) -> ClientWebSocket {
+        let (client, _) =
+            connect_async(format!("{}{}", self.director_url, path))
+                .await
+                .unwrap();
+        client
+    }
+
+    async fn connect_with_route_header(
+        &self,
+        route: String,
+    ) -> Result<ClientWebSocket, WsError> {
+        let mut request = self.director_url.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(ROUTE_OVERRIDE_HEADER, route.parse().unwrap());
+        let (client, _) = connect_async(request).await?;
+        Ok(client)
+    }
+}
+
+fn setup_account_provider(accounts: &[Pubkey]) -> AccountProviderStub {
+    let mut account_provider = AccountProviderStub::default();
+    for pubkey in accounts {
+        account_provider.add(*pubkey, account_owned_by_system_program());
+    }
+    account_provider
+}
+
+fn custom_cluster(ws_url: String) -> RpcCluster {
+    RpcCluster::Custom("http://127.0.0.1:0".to_string(), ws_url)
+}
+
+fn delegation_record_account(authority: Pubkey) -> Account {
+    let mut data = [0u8; size_of::<dlp::state::DelegationRecord>() + 8];
+    dlp::state::DelegationRecord {
+        authority,
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    }
+    .to_bytes_with_discriminator(&mut data)
+    .unwrap();
+    Account {
+        owner: DELEGATION_PROGRAM_ID,
+        data: data.to_vec(),
+        ..Account::default()
+    }
+}
+
+async fn send_json(client: &mut ClientWebSocket, msg: Value) {
+    client.send(Message::Text(msg.to_string())).await.unwrap();
+}
+
+async fn next_message(client: &mut ClientWebSocket) -> Message {
+    tokio::time::timeout(TIMEOUT, client.next())
+        .await
+        .expect("timed out waiting for message")
+        .expect("client stream ended")
+        .expect("failed to read message")
+}
+
+async fn next_json(client: &mut ClientWebSocket) -> Value {
+    loop {
+        if let Message::Text(txt) = next_message(client).await {
+            return serde_json::from_str(&txt).unwrap();
+        }
+    }
+}
+
+fn account_subscribe(id: u64, pubkey: &Pubkey) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "accountSubscribe",
+        "params": [pubkey.to_string(), { "encoding": "base64" }]
+    })
+}
+
+fn account_unsubscribe(id: u64, subscription: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "accountUnsubscribe",
+        "params": [subscription]
+    })
+}
+
+fn account_notification(subscription: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "accountNotification",
+        "params": {
+            "result": { "context": { "slot": 42 }, "value": null },
+            "subscription": subscription
+        }
+    })
+}
+
+fn signature_subscribe(id: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "signatureSubscribe",
+        "params": [
+            Signature::default().to_string(),
+            { "commitment": "confirmed" }
+        ]
+    })
+}
+
+fn signature_unsubscribe(id: u64, subscription: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "signatureUnsubscribe",
+        "params": [subscription]
+    })
+}
+
+fn signature_notification(subscription: u64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "signatureNotification",
+        "params": {
+            "result": { "context": { "slot": 42 }, "value": { "err": null } },
+            "subscription": subscription
+        }
+    })
+}
+
+// -----------------
+// Subscribe/Notify/Unsubscribe
+// -----------------
+#[tokio::test]
+async fn test_account_subscribe_delegated_account_flow() {
+    let (delegated_id, _) = delegated_account_ids();
+    let setup = TestSetup::start(setup_account_provider(&[delegated_id])).await;
+    setup.ephem.respond_to("accountSubscribe", json!(7));
+    setup.chain.respond_to("accountUnsubscribe", json!(false));
+    setup.ephem.respond_to("accountUnsubscribe", json!(true));
+
+    let mut client = setup.connect("").await;
+
+    // Subscribe is only sent to ephemeral which has the account
+    send_json(&mut client, account_subscribe(1, &delegated_id)).await;
+    let response = next_json(&mut client).await;
+    assert_eq!(response["id"], json!(1));
+    assert_eq!(response["result"], json!(7));
+    assert_eq!(
+        setup.ephem.received_json(),
+        vec![account_subscribe(1, &delegated_id)]
+    );
+    assert!(setup.chain.received().is_empty());
+
+    // Notifications of the backend are piped to the client
+    setup.ephem.notify(account_notification(7));
+    assert_eq!(next_json(&mut client).await, account_notification(7));
+
+    // Unsubscribe is sent to both since we don't track subscription ids
+    send_json(&mut client, account_unsubscribe(2, 7)).await;
+    let responses =
+        vec![next_json(&mut client).await, next_json(&mut client).await];
+    assert!(responses.contains(&json!({
+        "jsonrpc": "2.0", "result": true, "id": 2
+    })));
+    assert!(responses.contains(&json!({
+        "jsonrpc": "2.0", "result": false, "id": 2
+    })));
+    assert_eq!(setup.chain.received_json(), vec![account_unsubscribe(2, 7)]);
+    assert_eq!(
+        setup.ephem.received_json(),
+        vec![
+            account_subscribe(1, &delegated_id),
+            account_unsubscribe(2, 7)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_account_subscribe_unknown_account_goes_to_both() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup.connect("").await;
+
+    let pubkey = Pubkey::new_unique();
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+
+    let chain_received = setup.chain.wait_for_received(1, TIMEOUT).await;
+    let ephem_received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert_eq!(chain_received.len(), 1);
+    assert_eq!(ephem_received.len(), 1);
+
+    // The client sees the notifications of either backend
+    setup.chain.notify(account_notification(1));
+    assert_eq!(next_json(&mut client).await, account_notification(1));
+    setup.ephem.notify(account_notification(2));
+    assert_eq!(next_json(&mut client).await, account_notification(2));
+}
+
+#[tokio::test]
+async fn test_block_subscribe_route_hint_is_stripped() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup.connect("").await;
+
+    send_json(
+        &mut client,
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "blockSubscribe",
+            "params": ["all"],
+            "conjuntoRoute": "ephemeral"
+        }),
+    )
+    .await;
+
+    let received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        setup.ephem.received_json(),
+        vec![json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "blockSubscribe",
+            "params": ["all"]
+        })]
+    );
+    assert!(setup.chain.received().is_empty());
+}
+
+// -----------------
+// Route Override
+// -----------------
+#[tokio::test]
+async fn test_route_override_via_path() {
+    let (delegated_id, _) = delegated_account_ids();
+    let setup = TestSetup::start(setup_account_provider(&[delegated_id])).await;
+    setup.chain.respond_to("accountSubscribe", json!(3));
+
+    let mut client = setup.connect("/chain").await;
+
+    // Guiding would pick ephemeral, but the client picked chain
+    send_json(&mut client, account_subscribe(1, &delegated_id)).await;
+    let response = next_json(&mut client).await;
+    assert_eq!(response["result"], json!(3));
+    assert_eq!(
+        setup.chain.received_json(),
+        vec![account_subscribe(1, &delegated_id)]
+    );
+    assert!(setup.ephem.received().is_empty());
+}
+
+#[tokio::test]
+async fn test_route_override_via_header() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup
+        .connect_with_route_header(RouteOverride::Ephemeral.to_string())
+        .await
+        .unwrap();
+
+    // Guiding would pick both, but the client picked ephemeral
+    let pubkey = Pubkey::new_unique();
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+    let received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert_eq!(received.len(), 1);
+    assert!(setup.chain.received().is_empty());
+}
+
+#[tokio::test]
+async fn test_invalid_route_override_header_is_rejected() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let res = setup.connect_with_route_header("nowhere".to_string()).await;
+    assert!(res.is_err());
+}
+
+// -----------------
+// Multiple Ephemeral Validators
+// -----------------
+/// Starts the director with a registered validator the delegated account is
+/// delegated to
+async fn start_with_delegated_to_validator(
+    validator: &MockWebsocketServer,
+) -> (TestSetup, Pubkey) {
+    let (delegated_id, delegation_pda) = delegated_account_ids();
+    let authority = Pubkey::new_unique();
+    let mut chain_account_provider = AccountProviderStub::default();
+    chain_account_provider
+        .add(delegation_pda, delegation_record_account(authority));
+    let setup = TestSetup::start_with_validators(
+        setup_account_provider(&[delegated_id]),
+        Some(chain_account_provider),
+        ValidatorRegistry::default()
+            .with_validator(authority, custom_cluster(validator.ws_url())),
+    )
+    .await;
+    (setup, delegated_id)
+}
+
+#[tokio::test]
+async fn test_account_subscribe_goes_to_validator_of_delegation_record() {
+    let validator = MockWebsocketServer::start().await;
+    validator.respond_to("accountSubscribe", json!(11));
+    let (setup, delegated_id) =
+        start_with_delegated_to_validator(&validator).await;
+    let mut client = setup.connect("").await;
+
+    // The socket to the validator is only opened once it is needed
+    assert_eq!(validator.connections(), 0);
+
+    send_json(&mut client, account_subscribe(1, &delegated_id)).await;
+    let response = next_json(&mut client).await;
+    assert_eq!(response["result"], json!(11));
+    assert_eq!(
+        validator.received_json(),
+        vec![account_subscribe(1, &delegated_id)]
+    );
+    assert_eq!(validator.connections(), 1);
+    assert!(setup.ephem.received().is_empty());
+    assert!(setup.chain.received().is_empty());
+
+    // Notifications of the validator are piped to the client
+    validator.notify(account_notification(11));
+    assert_eq!(next_json(&mut client).await, account_notification(11));
+}
+
+#[tokio::test]
+async fn test_account_subscribe_delegated_to_unregistered_validator() {
+    let (delegated_id, delegation_pda) = delegated_account_ids();
+    let validator = MockWebsocketServer::start().await;
+
+    let mut chain_account_provider = AccountProviderStub::default();
+    chain_account_provider.add(
+        delegation_pda,
+        delegation_record_account(Pubkey::new_unique()),
+    );
+    let setup = TestSetup::start_with_validators(
+        setup_account_provider(&[delegated_id]),
+        Some(chain_account_provider),
+        ValidatorRegistry::default().with_validator(
+            Pubkey::new_unique(),
+            custom_cluster(validator.ws_url()),
+        ),
+    )
+    .await;
+    let mut client = setup.connect("").await;
+
+    send_json(&mut client, account_subscribe(1, &delegated_id)).await;
+    let received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert_eq!(received.len(), 1);
+    assert!(validator.received().is_empty());
+    assert_eq!(validator.connections(), 0);
+}
+
+#[tokio::test]
+async fn test_unknown_account_subscribe_goes_to_default_validator() {
+    let validator = MockWebsocketServer::start().await;
+    let setup = TestSetup::start_with_validators(
+        setup_account_provider(&[]),
+        Some(AccountProviderStub::default()),
+        ValidatorRegistry::default().with_validator(
+            Pubkey::new_unique(),
+            custom_cluster(validator.ws_url()),
+        ),
+    )
+    .await;
+    let mut client = setup.connect("").await;
+
+    let pubkey = Pubkey::new_unique();
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+
+    let chain_received = setup.chain.wait_for_received(1, TIMEOUT).await;
+    let ephem_received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert_eq!(chain_received.len(), 1);
+    assert_eq!(ephem_received.len(), 1);
+    assert!(validator.received().is_empty());
+    assert_eq!(validator.connections(), 0);
+}
+
+#[tokio::test]
+async fn test_signature_subscribe_goes_to_all_validators() {
+    let validator = MockWebsocketServer::start().await;
+    validator.respond_to("signatureSubscribe", json!(7));
+    validator.respond_to("signatureUnsubscribe", json!(true));
+    let (setup, _) = start_with_delegated_to_validator(&validator).await;
+    setup.ephem.respond_to("signatureSubscribe", json!(3));
+    let mut client = setup.connect("/ephemeral").await;
+
+    send_json(&mut client, signature_subscribe(1)).await;
+
+    // Each validator responds with its own subscription
+    let mut results = vec![
+        next_json(&mut client).await["result"].clone(),
+        next_json(&mut client).await["result"].clone(),
+    ];
+    results.sort_by_key(|result| result.as_u64());
+    assert_eq!(results, vec![json!(3), json!(7)]);
+    assert_eq!(setup.ephem.received_json(), vec![signature_subscribe(1)]);
+    assert_eq!(validator.received_json(), vec![signature_subscribe(1)]);
+
+    // The transaction may have been sent to the registered validator
+    validator.notify(signature_notification(7));
+    assert_eq!(next_json(&mut client).await, signature_notification(7));
+
+    send_json(&mut client, signature_unsubscribe(2, 7)).await;
+    let response = next_json(&mut client).await;
+    assert_eq!(response["id"], json!(2));
+    assert_eq!(response["result"], json!(true));
+    assert_eq!(
+        validator.received_json(),
+        vec![signature_subscribe(1), signature_unsubscribe(2, 7)]
+    );
+    assert_eq!(setup.ephem.received_json(), vec![signature_subscribe(1)]);
+}
+
+#[tokio::test]
+async fn test_unsubscribe_goes_to_validator_of_subscription() {
+    let validator = MockWebsocketServer::start().await;
+    validator.respond_to("accountSubscribe", json!(11));
+    validator.respond_to("accountUnsubscribe", json!(true));
+    let (setup, delegated_id) =
+        start_with_delegated_to_validator(&validator).await;
+    let mut client = setup.connect("").await;
+
+    send_json(&mut client, account_subscribe(1, &delegated_id)).await;
+    assert_eq!(next_json(&mut client).await["result"], json!(11));
+
+    send_json(&mut client, account_unsubscribe(2, 11)).await;
+    let response = next_json(&mut client).await;
+    assert_eq!(response["id"], json!(2));
+    assert_eq!(response["result"], json!(true));
+    assert_eq!(
+        validator.received_json(),
+        vec![
+            account_subscribe(1, &delegated_id),
+            account_unsubscribe(2, 11)
+        ]
+    );
+    // Unsubscribing goes to chain as usual, but not to the default validator
+    // which doesn't know the subscription
+    assert_eq!(setup.chain.wait_for_received(1, TIMEOUT).await.len(), 1);
+    assert!(setup.ephem.received().is_empty());
+
+    // Closing is forwarded to the validator that was connected
+    client.close(None).await.unwrap();
+    let validator_received = validator.wait_for_received(3, TIMEOUT).await;
+    assert!(matches!(validator_received.get(2), Some(Message::Close(_))));
+}
+
+// -----------------
+// Close
+// -----------------
+#[tokio::test]
+async fn test_client_close_is_forwarded_to_both_backends() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup.connect("").await;
+
+    client.close(None).await.unwrap();
+
+    let chain_received = setup.chain.wait_for_received(1, TIMEOUT).await;
+    let ephem_received = setup.ephem.wait_for_received(1, TIMEOUT).await;
+    assert!(matches!(chain_received.as_slice(), [Message::Close(_)]));
+    assert!(matches!(ephem_received.as_slice(), [Message::Close(_)]));
+}
+
+#[tokio::test]
+async fn test_backend_close_closes_client() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup.connect("").await;
+    assert_eq!(setup.chain.connections(), 1);
+
+    setup.chain.close_connections();
+
+    let msg = next_message(&mut client).await;
+    assert!(matches!(msg, Message::Close(_)));
+}
+
+// -----------------
+// Rate Limit
+// -----------------
+#[tokio::test]
+async fn test_connections_exceeding_the_limit_are_closed() {
+    let setup = TestSetup::start_with_rate_limit(RateLimitConfig {
+        max_connections: Some(1),
+        ..Default::default()
+    })
+    .await;
+    let _client = setup.connect("").await;
+
+    let mut rejected = setup.connect("").await;
+
+    let Message::Close(Some(frame)) = next_message(&mut rejected).await else {
+        panic!("Expected a close frame");
+    };
+    assert_eq!(frame.code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn test_subscriptions_exceeding_the_limit_are_rejected() {
+    let setup = TestSetup::start_with_rate_limit(RateLimitConfig {
+        max_subscriptions: Some(1),
+        ..Default::default()
+    })
+    .await;
+    setup.chain.respond_to("accountSubscribe", json!(7));
+    let mut client = setup.connect("/chain").await;
+    let pubkey = Pubkey::new_unique();
+
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+    assert_eq!(next_json(&mut client).await["result"], json!(7));
+    send_json(&mut client, account_subscribe(2, &pubkey)).await;
+
+    let response = next_json(&mut client).await;
+    assert_eq!(response["id"], json!(2));
+    assert_eq!(response["error"]["code"], json!(4));
+    assert_eq!(response["error"]["data"]["limit"], "subscriptions");
+    assert_eq!(
+        setup.chain.received_json(),
+        vec![account_subscribe(1, &pubkey)]
+    );
+}
+
+// -----------------
+// Auth
+// -----------------
+fn api_keys_file(content: &str) -> PathBuf {
+    let path = std::env::temp_dir()
+        .join(format!("conjunto-api-keys-{}.toml", Pubkey::new_unique()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+async fn start_with_api_keys() -> TestSetup {
+    let path = api_keys_file(
+        r#"
+        [[keys]]
+        key = "secret"
+
+        [[keys]]
+        key = "slots"
+        methods = ["slotSubscribe"]
+        "#,
+    );
+    let auth = ApiKeyAuth::from_file(path, Duration::from_secs(1)).unwrap();
+    TestSetup::start_with_auth(auth).await
+}
+
+#[tokio::test]
+async fn test_connections_without_valid_api_key_are_closed() {
+    let setup = start_with_api_keys().await;
+
+    for (path, reason) in [
+        ("", "Missing API key"),
+        ("/?api-key=unknown", "Invalid API key"),
+    ] {
+        let mut client = setup.connect(path).await;
+        let Message::Close(Some(frame)) = next_message(&mut client).await
+        else {
+            panic!("Expected a close frame");
+        };
+        assert_eq!(frame.code, CloseCode::Policy);
+        assert_eq!(frame.reason, reason);
+    }
+}
+
+#[tokio::test]
+async fn test_api_key_can_be_provided_as_subprotocol() {
+    let setup = start_with_api_keys().await;
+    setup.chain.respond_to("accountSubscribe", json!(7));
+    let mut request = format!("{}/chain", setup.director_url)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, "api-key.secret".parse().unwrap());
+
+    let (mut client, response) = connect_async(request).await.unwrap();
+
+    assert_eq!(
+        response.headers().get(SEC_WEBSOCKET_PROTOCOL).unwrap(),
+        "api-key.secret"
+    );
+    let pubkey = Pubkey::new_unique();
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+    assert_eq!(next_json(&mut client).await["result"], json!(7));
+}
+
+#[tokio::test]
+async fn test_methods_not_allowed_for_the_api_key_are_rejected() {
+    let setup = start_with_api_keys().await;
+    let mut client = setup.connect("/chain?api-key=slots").await;
+    let pubkey = Pubkey::new_unique();
+
+    send_json(&mut client, account_subscribe(1, &pubkey)).await;
+
+    let response = next_json(&mut client).await;
+    assert_eq!(response["id"], json!(1));
+    assert_eq!(response["error"]["code"], json!(5));
+    assert_eq!(response["error"]["data"]["reason"], "methodNotAllowed");
+    assert!(setup.chain.received_json().is_empty());
+}
+
+// -----------------
+// TLS
+// -----------------
+#[tokio::test]
+async fn test_clients_are_served_over_wss() {
+    let cert = TestCertificate::generate();
+    let setup = TestSetup::start_with_tls(&cert).await;
+    setup.chain.respond_to("accountSubscribe", json!(7));
+    let addr = setup.director_url.trim_start_matches("wss://");
+
+    let stream = cert.connect(addr).await.unwrap();
+    let (mut client, _) =
+        client_async(format!("{}/chain", setup.director_url), stream)
+            .await
+            .unwrap();
+
+    let pubkey = Pubkey::new_unique();
+    let msg = account_subscribe(1, &pubkey).to_string();
+    client.send(Message::Text(msg)).await.unwrap();
+    let Some(Ok(Message::Text(response))) =
+        tokio::time::timeout(TIMEOUT, client.next()).await.unwrap()
+    else {
+        panic!("Expected a text message");
+    };
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["result"], json!(7));
+}
+
+#[tokio::test]
+async fn test_plain_websocket_connections_are_dropped() {
+    let cert = TestCertificate::generate();
+    let setup = TestSetup::start_with_tls(&cert).await;
+    let url = setup.director_url.replacen("wss://", "ws://", 1);
+
+    assert!(connect_async(url).await.is_err());
+}
+
+// -----------------
+// Shutdown
+// -----------------
+#[tokio::test]
+async fn test_shutdown_closes_clients() {
+    let setup = TestSetup::start(setup_account_provider(&[])).await;
+    let mut client = setup.connect("").await;
+    let TestSetup {
+        handle,
+        director_url,
+        ..
+    } = setup;
+
+    let summary = handle.shutdown(TIMEOUT).await;
+
+    assert_eq!(
+        summary,
+        ShutdownSummary {
+            open_connections: 1,
+            in_flight_requests: 0,
+            drained_connections: 1,
+            aborted_connections: 0,
+        }
+    );
+    let Message::Close(Some(frame)) = next_message(&mut client).await else {
+        panic!("Expected a close frame");
+    };
+    assert_eq!(frame.code, CloseCode::Away);
+    assert!(connect_async(director_url).await.is_err());
+}
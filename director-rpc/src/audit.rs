@@ -0,0 +1,343 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use conjunto_lockbox::account_chain_state::AccountChainState;
+use conjunto_transwise::endpoint::Endpoint;
+use jsonrpsee::core::RpcResult;
+use log::*;
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Slot;
+
+use crate::errors::DirectorRpcResult;
+
+// -----------------
+// AuditConfig
+// -----------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// Where routing decisions are written to, `None` disables the audit log
+    pub sink: Option<AuditSink>,
+    /// Only every n-th routed request is recorded, `1` records all of them
+    pub sample_every: u64,
+    /// Leaves the data of the accounts out of the records, only its length
+    /// is kept
+    pub redact_account_data: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            sample_every: 1,
+            redact_account_data: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditSink {
+    Stdout,
+    /// Records are appended to the file which is created if needed
+    File(PathBuf),
+}
+
+impl fmt::Display for AuditSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSink::Stdout => write!(f, "stdout"),
+            AuditSink::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for AuditSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("Audit sink needs to be 'stdout' or a path".to_string()),
+            "stdout" | "-" => Ok(AuditSink::Stdout),
+            path => Ok(AuditSink::File(PathBuf::from(path))),
+        }
+    }
+}
+
+// -----------------
+// RoutingRecord
+// -----------------
+/// A single routing decision as written to the audit log, one JSON object
+/// per line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRecord {
+    /// Unix timestamp of when the request was received
+    pub timestamp_ms: u64,
+    pub method: String,
+    /// First signature of the transaction, `None` if it couldn't be decoded
+    pub signature: Option<String>,
+    /// The backend the client explicitly picked, if any
+    pub route_override: Option<String>,
+    /// State of all accounts the decision was based on, empty if the client
+    /// picked the backend
+    pub accounts: Vec<AuditedAccount>,
+    /// `chain`, `ephemeral` or `unroutable`, `None` if the request failed
+    /// before it was routed. Unroutable transactions routed by simulating
+    /// them record the backend they were sent to.
+    pub endpoint: Option<String>,
+    pub unroutable_reason: Option<String>,
+    /// The ephemeral validator the request was sent to if not the default
+    pub validator_authority: Option<String>,
+    /// Simulations run to route an unroutable transaction, in order
+    pub simulations: Vec<AuditedSimulation>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditedAccount {
+    pub pubkey: String,
+    pub writable: bool,
+    pub at_slot: Slot,
+    /// Length of the account data, also when the data itself is redacted
+    pub data_len: usize,
+    pub chain_state: AccountChainState,
+}
+
+/// Outcome of simulating an unroutable transaction on one backend, the logs
+/// of the simulation are left out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditedSimulation {
+    pub backend: String,
+    /// Set if the simulation could not be performed or the transaction failed
+    pub err: Option<String>,
+    pub written_undelegated_pubkeys: Vec<String>,
+    pub written_delegated_pubkeys: Vec<String>,
+}
+
+impl RoutingRecord {
+    pub(crate) fn new(method: &str) -> Self {
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_millis() as u64)
+                .unwrap_or_default(),
+            method: method.to_string(),
+            signature: None,
+            route_override: None,
+            accounts: vec![],
+            endpoint: None,
+            unroutable_reason: None,
+            validator_authority: None,
+            simulations: vec![],
+            latency_ms: 0,
+            error: None,
+        }
+    }
+
+    pub(crate) fn set_endpoint(
+        &mut self,
+        endpoint: &Endpoint,
+        redact_account_data: bool,
+    ) {
+        let snapshot = endpoint.transaction_accounts_snapshot();
+        let readonly = snapshot.readonly.iter().map(|acc| (acc, false));
+        let writable = snapshot.writable.iter().map(|acc| (acc, true));
+        self.accounts = writable
+            .chain(readonly)
+            .map(|(acc, writable)| {
+                let mut chain_state = acc.chain_state.clone();
+                let data_len = chain_state
+                    .account()
+                    .map_or(0, |account| account.data.len());
+                if redact_account_data {
+                    redact_data(&mut chain_state);
+                }
+                AuditedAccount {
+                    pubkey: acc.pubkey.to_string(),
+                    writable,
+                    at_slot: acc.at_slot,
+                    data_len,
+                    chain_state,
+                }
+            })
+            .collect();
+        self.endpoint = Some(endpoint.name().to_string());
+        self.unroutable_reason = endpoint
+            .unroutable_reason()
+            .map(|reason| reason.code().to_string());
+        self.validator_authority = endpoint
+            .ephemeral_validator_authority()
+            .map(|authority| authority.to_string());
+    }
+
+    pub(crate) fn finish<T>(&mut self, latency: Duration, res: &RpcResult<T>) {
+        self.latency_ms = latency.as_millis() as u64;
+        self.error = res.as_ref().err().map(|err| err.message().to_string());
+    }
+}
+
+fn redact_data(chain_state: &mut AccountChainState) {
+    match chain_state {
+        AccountChainState::FeePayer { .. } => {}
+        AccountChainState::Undelegated { account, .. }
+        | AccountChainState::Delegated { account, .. } => {
+            account.data = vec![];
+        }
+    }
+}
+
+// -----------------
+// AuditLog
+// -----------------
+/// Writes [RoutingRecord]s to the configured sink.
+/// Records are handed to a dedicated thread so that writing them never
+/// blocks the requests they are about.
+#[derive(Clone)]
+pub(crate) struct AuditLog {
+    inner: Option<Arc<AuditLogInner>>,
+}
+
+struct AuditLogInner {
+    sender: mpsc::Sender<String>,
+    sample_every: u64,
+    redact_account_data: bool,
+    requests: AtomicU64,
+}
+
+impl AuditLog {
+    pub(crate) fn new(config: &AuditConfig) -> DirectorRpcResult<Self> {
+        let Some(sink) = &config.sink else {
+            return Ok(Self { inner: None });
+        };
+        let mut writer: Box<dyn Write + Send> = match sink {
+            AuditSink::Stdout => Box::new(io::stdout()),
+            AuditSink::File(path) => Box::new(LineWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+        };
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::Builder::new()
+            .name("conjunto-audit".to_string())
+            .spawn(move || {
+                for line in receiver {
+                    if let Err(err) = writeln!(writer, "{}", line) {
+                        error!("Failed to write audit record: {:?}", err);
+                    }
+                }
+            })?;
+        info!("Writing routing audit records to {}", sink);
+        Ok(Self {
+            inner: Some(Arc::new(AuditLogInner {
+                sender,
+                sample_every: config.sample_every.max(1),
+                redact_account_data: config.redact_account_data,
+                requests: AtomicU64::new(0),
+            })),
+        })
+    }
+
+    /// Starts a record if the audit log is enabled and the request is
+    /// sampled
+    pub(crate) fn sample(&self, method: &str) -> Option<RoutingRecord> {
+        let inner = self.inner.as_ref()?;
+        let n = inner.requests.fetch_add(1, Ordering::Relaxed);
+        (n % inner.sample_every == 0).then(|| RoutingRecord::new(method))
+    }
+
+    pub(crate) fn redact_account_data(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_none_or(|inner| inner.redact_account_data)
+    }
+
+    pub(crate) fn record(&self, record: &RoutingRecord) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        match serde_json::to_string(record) {
+            Ok(line) => {
+                if inner.sender.send(line).is_err() {
+                    error!("Audit log writer stopped, dropping record");
+                }
+            }
+            Err(err) => error!("Failed to serialize audit record: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_core::delegation_inconsistency::DelegationInconsistency;
+    use solana_sdk::{account::Account, pubkey::Pubkey};
+
+    use super::*;
+
+    #[test]
+    fn test_audit_sink_from_str() {
+        assert_eq!("stdout".parse(), Ok(AuditSink::Stdout));
+        assert_eq!(
+            "/var/log/routing.jsonl".parse(),
+            Ok(AuditSink::File(PathBuf::from("/var/log/routing.jsonl")))
+        );
+        assert!("".parse::<AuditSink>().is_err());
+    }
+
+    #[test]
+    fn test_sample_every_nth_request() {
+        let path = std::env::temp_dir()
+            .join(format!("conjunto-audit-{}.jsonl", Pubkey::new_unique()));
+        let audit = AuditLog::new(&AuditConfig {
+            sink: Some(AuditSink::File(path.clone())),
+            sample_every: 3,
+            ..AuditConfig::default()
+        })
+        .unwrap();
+        let sampled = (0..7)
+            .filter(|_| audit.sample("sendTransaction").is_some())
+            .count();
+        assert_eq!(sampled, 3);
+
+        let disabled = AuditLog::new(&AuditConfig::default()).unwrap();
+        assert!(disabled.sample("sendTransaction").is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_redact_data_keeps_everything_else() {
+        let account = Account {
+            lamports: 42,
+            data: vec![1, 2, 3],
+            ..Account::default()
+        };
+        let mut chain_state = AccountChainState::Undelegated {
+            account: account.clone(),
+            delegation_inconsistency:
+                DelegationInconsistency::AccountInvalidOwner,
+        };
+        redact_data(&mut chain_state);
+        assert_eq!(
+            chain_state,
+            AccountChainState::Undelegated {
+                account: Account {
+                    data: vec![],
+                    ..account
+                },
+                delegation_inconsistency:
+                    DelegationInconsistency::AccountInvalidOwner,
+            }
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use conjunto_guidepoint::auth::{ApiKeyAuth, AuthError};
+use futures_util::future::BoxFuture;
+use jsonrpsee::{
+    server::{middleware::rpc::RpcServiceT, MethodResponse},
+    types::{ErrorObjectOwned, Request},
+};
+use log::*;
+use serde::Serialize;
+
+use crate::utils::{server_error_with_data, ServerErrorCode};
+
+// -----------------
+// AuthLayer
+// -----------------
+/// Rejects the RPC requests whose API key is missing, unknown or doesn't
+/// allow the method
+#[derive(Clone)]
+pub(crate) struct AuthLayer {
+    auth: ApiKeyAuth,
+    api_key: Option<String>,
+}
+
+impl AuthLayer {
+    pub(crate) fn new(auth: ApiKeyAuth, api_key: Option<String>) -> Self {
+        Self { auth, api_key }
+    }
+}
+
+impl<S> tower::Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Auth {
+            service,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct Auth<S> {
+    service: S,
+    layer: AuthLayer,
+}
+
+impl<'a, S> RpcServiceT<'a> for Auth<S>
+where
+    S: RpcServiceT<'a> + Send + Sync,
+    S::Future: 'a,
+{
+    type Future = BoxFuture<'a, MethodResponse>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        let AuthLayer { auth, api_key } = &self.layer;
+        match auth.authorize(api_key.as_deref(), request.method_name()) {
+            Ok(()) => Box::pin(self.service.call(request)),
+            Err(err) => {
+                debug!("Rejecting {} request: {}", request.method_name(), err);
+                let response =
+                    MethodResponse::error(request.id, auth_error(&err));
+                Box::pin(async move { response })
+            }
+        }
+    }
+}
+
+/// Error data of rejected requests, `reason` is stable and identifies why
+/// the request was rejected
+#[derive(Serialize)]
+struct AuthErrorData {
+    reason: &'static str,
+}
+
+fn auth_error(err: &AuthError) -> ErrorObjectOwned {
+    server_error_with_data(
+        err.to_string(),
+        ServerErrorCode::Unauthorized,
+        AuthErrorData {
+            reason: err.reason(),
+        },
+    )
+}
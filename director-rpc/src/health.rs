@@ -0,0 +1,319 @@
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use conjunto_addresses::cluster::RpcCluster;
+use jsonrpsee::{
+    core::{client::ClientT, ClientError},
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+    server::StopHandle,
+};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_sdk::clock::Slot;
+
+use crate::errors::DirectorRpcResult;
+
+/// Error code the Solana RPC uses for `getHealth` when the node is behind
+const NODE_UNHEALTHY: i32 = -32005;
+
+// -----------------
+// HealthConfig
+// -----------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthConfig {
+    /// How often the backends are probed
+    pub probe_interval: Duration,
+    /// How long a single probe may take before it is considered failed
+    pub probe_timeout: Duration,
+    /// A backend reporting to be more slots behind than this is not healthy
+    pub max_slot_lag: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval: Duration::from_secs(5),
+            probe_timeout: Duration::from_secs(2),
+            // Same distance the Solana RPC considers a node unhealthy at
+            max_slot_lag: 128,
+        }
+    }
+}
+
+// -----------------
+// DirectorHealth
+// -----------------
+/// Outcome of probing a single endpoint of a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeResult {
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackendHealth {
+    pub url: String,
+    pub ws_url: String,
+    /// Result of the last `getSlot` request, `None` until first probed
+    pub http: Option<ProbeResult>,
+    /// Result of the last websocket handshake, `None` until first probed
+    pub ws: Option<ProbeResult>,
+    pub slot: Option<Slot>,
+    /// Slots the backend reports to be behind via `getHealth`, `None` if it
+    /// doesn't support reporting that
+    pub slot_lag: Option<u64>,
+    /// Unix timestamp of the last probe
+    pub probed_at_ms: Option<u64>,
+}
+
+impl BackendHealth {
+    fn new(cluster: &RpcCluster) -> Self {
+        Self {
+            url: cluster.url().to_string(),
+            ws_url: cluster.ws_url().to_string(),
+            http: None,
+            ws: None,
+            slot: None,
+            slot_lag: None,
+            probed_at_ms: None,
+        }
+    }
+
+    pub fn is_healthy(&self, max_slot_lag: u64) -> bool {
+        let probe_ok =
+            |probe: &Option<ProbeResult>| probe.as_ref().is_some_and(|p| p.ok);
+        probe_ok(&self.http)
+            && probe_ok(&self.ws)
+            && self.slot_lag.is_none_or(|lag| lag <= max_slot_lag)
+    }
+}
+
+/// The health of the backends the director routes to.
+/// The director is ready once both of them are healthy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectorHealth {
+    pub ready: bool,
+    pub chain: BackendHealth,
+    pub ephemeral: BackendHealth,
+}
+
+// -----------------
+// HealthMonitor
+// -----------------
+struct Backend {
+    client: HttpClient,
+    ws_url: String,
+}
+
+/// Periodically probes the chain and ephemeral backends and keeps the
+/// [DirectorHealth] found by the last probe
+#[derive(Clone)]
+pub struct HealthMonitor {
+    config: HealthConfig,
+    chain: Arc<Backend>,
+    ephemeral: Arc<Backend>,
+    health: Arc<RwLock<DirectorHealth>>,
+}
+
+impl HealthMonitor {
+    pub fn new(
+        config: HealthConfig,
+        chain_cluster: &RpcCluster,
+        ephem_cluster: &RpcCluster,
+    ) -> DirectorRpcResult<Self> {
+        let backend = |cluster: &RpcCluster| -> DirectorRpcResult<_> {
+            Ok(Arc::new(Backend {
+                client: HttpClientBuilder::default()
+                    .request_timeout(config.probe_timeout)
+                    .build(cluster.url())?,
+                ws_url: cluster.ws_url().to_string(),
+            }))
+        };
+        Ok(Self {
+            chain: backend(chain_cluster)?,
+            ephemeral: backend(ephem_cluster)?,
+            health: Arc::new(RwLock::new(DirectorHealth {
+                ready: false,
+                chain: BackendHealth::new(chain_cluster),
+                ephemeral: BackendHealth::new(ephem_cluster),
+            })),
+            config,
+        })
+    }
+
+    /// The health found by the last probe
+    pub fn health(&self) -> DirectorHealth {
+        self.health
+            .read()
+            .expect("RwLock of health poisoned")
+            .clone()
+    }
+
+    /// Probes both backends once and updates the health
+    pub async fn probe(&self) -> DirectorHealth {
+        let (chain, ephemeral) = {
+            let health = self.health();
+            (health.chain, health.ephemeral)
+        };
+        let (chain, ephemeral) = tokio::join!(
+            self.probe_backend(&self.chain, chain),
+            self.probe_backend(&self.ephemeral, ephemeral),
+        );
+        let max_slot_lag = self.config.max_slot_lag;
+        let health = DirectorHealth {
+            ready: chain.is_healthy(max_slot_lag)
+                && ephemeral.is_healthy(max_slot_lag),
+            chain,
+            ephemeral,
+        };
+
+        let mut current =
+            self.health.write().expect("RwLock of health poisoned");
+        if current.ready != health.ready {
+            if health.ready {
+                info!("Director is ready");
+            } else {
+                warn!("Director is not ready: {:?}", health);
+            }
+        }
+        *current = health.clone();
+        health
+    }
+
+    /// Probes the backends until the server is stopped
+    pub(crate) async fn run(self, stop_handle: StopHandle) {
+        let mut interval = tokio::time::interval(self.config.probe_interval);
+        interval
+            .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let stopped = stop_handle.shutdown();
+        tokio::pin!(stopped);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    self.probe().await;
+                }
+                _ = &mut stopped => {
+                    debug!("Health monitor stopped");
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn probe_backend(
+        &self,
+        backend: &Backend,
+        previous: BackendHealth,
+    ) -> BackendHealth {
+        let ((http, slot), ws) =
+            tokio::join!(self.probe_http(backend), self.probe_ws(backend));
+        let (http, slot_lag) = match slot {
+            Some(_) => match self.slot_lag(backend).await {
+                Ok(slot_lag) => (http, slot_lag),
+                Err(err) => (ProbeResult::failed(http.latency_ms, err), None),
+            },
+            None => (http, None),
+        };
+        BackendHealth {
+            http: Some(http),
+            ws: Some(ws),
+            slot,
+            slot_lag,
+            probed_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|since_epoch| since_epoch.as_millis() as u64),
+            ..previous
+        }
+    }
+
+    async fn probe_http(
+        &self,
+        backend: &Backend,
+    ) -> (ProbeResult, Option<Slot>) {
+        let (result, latency_ms) = self
+            .timed(backend.client.request::<Slot, _>("getSlot", rpc_params![]))
+            .await;
+        match result {
+            Ok(Ok(slot)) => (ProbeResult::ok(latency_ms), Some(slot)),
+            Ok(Err(err)) => (ProbeResult::failed(latency_ms, err), None),
+            Err(err) => (ProbeResult::failed(latency_ms, err), None),
+        }
+    }
+
+    async fn probe_ws(&self, backend: &Backend) -> ProbeResult {
+        let (result, latency_ms) = self
+            .timed(tokio_tungstenite::connect_async(backend.ws_url.as_str()))
+            .await;
+        match result {
+            Ok(Ok((mut ws, _))) => {
+                if let Err(err) = ws.close(None).await {
+                    trace!("Failed to close health probe websocket: {}", err);
+                }
+                ProbeResult::ok(latency_ms)
+            }
+            Ok(Err(err)) => ProbeResult::failed(latency_ms, err),
+            Err(err) => ProbeResult::failed(latency_ms, err),
+        }
+    }
+
+    /// Asks the backend how many slots it is behind the cluster.
+    /// Fails if the backend is unhealthy without telling by how much.
+    async fn slot_lag(&self, backend: &Backend) -> Result<Option<u64>, String> {
+        match backend
+            .client
+            .request::<String, _>("getHealth", rpc_params![])
+            .await
+        {
+            Ok(_) => Ok(Some(0)),
+            Err(ClientError::Call(err)) if err.code() == NODE_UNHEALTHY => err
+                .data()
+                .and_then(|data| serde_json::from_str::<Value>(data.get()).ok())
+                .and_then(|data| data.get("numSlotsBehind")?.as_u64())
+                .map(Some)
+                .ok_or_else(|| err.message().to_string()),
+            Err(err) => {
+                trace!("Backend does not report its health: {}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn timed<F: Future>(
+        &self,
+        future: F,
+    ) -> (Result<F::Output, tokio::time::error::Elapsed>, u64) {
+        let start = Instant::now();
+        let result =
+            tokio::time::timeout(self.config.probe_timeout, future).await;
+        (result, start.elapsed().as_millis() as u64)
+    }
+}
+
+impl ProbeResult {
+    fn ok(latency_ms: u64) -> Self {
+        Self {
+            ok: true,
+            latency_ms,
+            error: None,
+        }
+    }
+
+    fn failed(latency_ms: u64, err: impl ToString) -> Self {
+        Self {
+            ok: false,
+            latency_ms,
+            error: Some(err.to_string()),
+        }
+    }
+}
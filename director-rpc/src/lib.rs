@@ -1,13 +1,26 @@
+pub mod audit;
+mod auth;
 mod decoders;
 pub mod errors;
+pub mod health;
+mod metrics;
+mod rate_limit;
 pub mod rpc;
+mod server;
+mod shutdown;
 mod utils;
 
-use std::net::SocketAddr;
-
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_guidepoint::shutdown::{shutdown_channel, ServerHandle};
+use conjunto_transwise::transwise::Transwise;
 use errors::DirectorRpcResult;
-use jsonrpsee::server::{Server, ServerHandle};
-use rpc::{create_rpc_module, DirectorConfig};
+use rpc::{
+    create_rpc_modules, create_rpc_modules_with_transwise, DirectorConfig,
+    DirectorRpcModules,
+};
+use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
 
 pub const DEFAULT_DIRECTOR_RPC_URL: &str = "127.0.0.1:9899";
@@ -32,13 +45,38 @@ pub async fn start_rpc_server(
         tower::ServiceBuilder::new().layer(cors)
     };
 
-    let server = Server::builder()
-        // .set_http_middleware(cors_middleware)
-        .http_only()
-        .build(url.parse::<SocketAddr>().unwrap())
-        .await?;
+    let rpc_modules = create_rpc_modules(config)?;
+    serve_rpc_modules(rpc_modules, url).await
+}
+
+/// Starts the RPC server with a [Transwise] that was already set up, i.e.
+/// with custom providers.
+pub async fn start_rpc_server_with_transwise<
+    T: AccountProvider,
+    U: DelegationRecordParser,
+>(
+    config: DirectorConfig,
+    transwise: Transwise<T, U>,
+    url: Option<&str>,
+) -> DirectorRpcResult<(String, ServerHandle)> {
+    let url = url.unwrap_or(DEFAULT_DIRECTOR_RPC_URL);
+    let rpc_modules = create_rpc_modules_with_transwise(&config, transwise)?;
+    serve_rpc_modules(rpc_modules, url).await
+}
+
+async fn serve_rpc_modules(
+    rpc_modules: DirectorRpcModules,
+    url: &str,
+) -> DirectorRpcResult<(String, ServerHandle)> {
+    let listener = TcpListener::bind(url).await?;
+    let addr = listener.local_addr()?;
+
+    let (shutdown_tx, shutdown_rx) = shutdown_channel();
+    let task =
+        tokio::spawn(server::serve_rpc(listener, rpc_modules, shutdown_rx));
 
-    let rpc_module = create_rpc_module(config)?;
-    let handle = server.start(rpc_module);
-    Ok((url.to_string(), handle))
+    Ok((
+        addr.to_string(),
+        ServerHandle::new("RPC", shutdown_tx, task),
+    ))
 }
@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use conjunto_metrics::{inc_rpc_request, observe_upstream_latency};
+use futures_util::future::BoxFuture;
+use jsonrpsee::{
+    core::{client::ClientT, traits::ToRpcParams, ClientError},
+    http_client::HttpClient,
+    server::{middleware::rpc::RpcServiceT, MethodResponse},
+    types::Request,
+    Methods,
+};
+use serde::de::DeserializeOwned;
+
+// -----------------
+// RequestMetricsLayer
+// -----------------
+/// Counts the RPC requests per method, route and result
+#[derive(Clone)]
+pub(crate) struct RequestMetricsLayer {
+    route: &'static str,
+    /// Requests for methods not in here are counted as `unknown` to bound
+    /// the number of label values clients can create
+    methods: Methods,
+}
+
+impl RequestMetricsLayer {
+    pub(crate) fn new(route: &'static str, methods: Methods) -> Self {
+        Self { route, methods }
+    }
+}
+
+impl<S> tower::Layer<S> for RequestMetricsLayer {
+    type Service = RequestMetrics<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RequestMetrics {
+            service,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct RequestMetrics<S> {
+    service: S,
+    layer: RequestMetricsLayer,
+}
+
+impl<'a, S> RpcServiceT<'a> for RequestMetrics<S>
+where
+    S: RpcServiceT<'a> + Send + Sync,
+    S::Future: 'a,
+{
+    type Future = BoxFuture<'a, MethodResponse>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        let method = match self.layer.methods.method(request.method_name()) {
+            Some(_) => request.method_name().to_string(),
+            None => "unknown".to_string(),
+        };
+        let route = self.layer.route;
+        let response = self.service.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            inc_rpc_request(&method, route, response.is_success());
+            response
+        })
+    }
+}
+
+// -----------------
+// Upstream Requests
+// -----------------
+/// Forwards the request to the backend and records how long that took
+pub(crate) async fn request_upstream<R, P>(
+    client: &HttpClient,
+    backend: &str,
+    method: &str,
+    params: P,
+) -> Result<R, ClientError>
+where
+    R: DeserializeOwned,
+    P: ToRpcParams + Send,
+{
+    let start = Instant::now();
+    let res = client.request(method, params).await;
+    observe_upstream_latency(backend, method, start.elapsed());
+    res
+}
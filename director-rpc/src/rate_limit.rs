@@ -0,0 +1,87 @@
+use conjunto_guidepoint::rate_limit::{
+    ClientKey, RateLimitExceeded, RateLimiter,
+};
+use futures_util::future::BoxFuture;
+use jsonrpsee::{
+    server::{middleware::rpc::RpcServiceT, MethodResponse},
+    types::{ErrorObjectOwned, Request},
+};
+use log::*;
+use serde::Serialize;
+
+use crate::utils::{server_error_with_data, ServerErrorCode};
+
+// -----------------
+// RateLimitLayer
+// -----------------
+/// Rejects the RPC requests that exceed the budget of the client
+#[derive(Clone)]
+pub(crate) struct RateLimitLayer {
+    limiter: RateLimiter,
+    client: ClientKey,
+}
+
+impl RateLimitLayer {
+    pub(crate) fn new(limiter: RateLimiter, client: ClientKey) -> Self {
+        Self { limiter, client }
+    }
+}
+
+impl<S> tower::Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        RateLimit {
+            service,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub(crate) struct RateLimit<S> {
+    service: S,
+    layer: RateLimitLayer,
+}
+
+impl<'a, S> RpcServiceT<'a> for RateLimit<S>
+where
+    S: RpcServiceT<'a> + Send + Sync,
+    S::Future: 'a,
+{
+    type Future = BoxFuture<'a, MethodResponse>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        let RateLimitLayer { limiter, client } = &self.layer;
+        match limiter.check_request(client, request.method_name()) {
+            Ok(()) => Box::pin(self.service.call(request)),
+            Err(err) => {
+                debug!("Rejecting request of {}: {}", client, err);
+                let response =
+                    MethodResponse::error(request.id, rate_limit_error(&err));
+                Box::pin(async move { response })
+            }
+        }
+    }
+}
+
+/// Error data of rejected requests, `limit` is stable and identifies the
+/// exceeded limit
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimitErrorData {
+    limit: &'static str,
+    retry_after_ms: Option<u64>,
+}
+
+fn rate_limit_error(err: &RateLimitExceeded) -> ErrorObjectOwned {
+    server_error_with_data(
+        err.to_string(),
+        ServerErrorCode::RateLimitExceeded,
+        RateLimitErrorData {
+            limit: err.limit(),
+            retry_after_ms: err
+                .retry_after()
+                .map(|retry_after| retry_after.as_millis() as u64),
+        },
+    )
+}
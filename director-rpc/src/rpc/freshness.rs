@@ -0,0 +1,245 @@
+use std::{fmt, str::FromStr};
+
+use conjunto_core::{
+    delegation_record::{CommitFrequency, DelegationRecord},
+    delegation_record_parser::DelegationRecordParser,
+    AccountProvider,
+};
+use conjunto_guidepoint::RouteOverride;
+use conjunto_lockbox::account_chain_state::AccountChainState;
+use jsonrpsee::{core::RpcResult, types::Params};
+use log::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solana_rpc_client_api::response::{
+    Response as RpcResponse, RpcResponseContext,
+};
+use solana_sdk::{
+    clock::{Slot, DEFAULT_MS_PER_SLOT},
+    pubkey::Pubkey,
+};
+
+use super::{passthrough::passthrough_impl, DirectorRpc};
+use crate::utils::{
+    invalid_params, server_error, server_error_with_data, ServerErrorCode,
+};
+
+// -----------------
+// DelegatedChainReads
+// -----------------
+/// Determines how reads of delegated accounts that are served by chain are
+/// handled. The chain copy of those accounts is only updated when the
+/// ephemeral validator commits them and thus may be stale.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum DelegatedChainReads {
+    /// Serve the read and add a [FreshnessHint] for each delegated account
+    /// to the response context
+    #[default]
+    Annotate,
+    /// Reject the read
+    Refuse,
+}
+
+impl fmt::Display for DelegatedChainReads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DelegatedChainReads::Annotate => write!(f, "annotate"),
+            DelegatedChainReads::Refuse => write!(f, "refuse"),
+        }
+    }
+}
+
+impl FromStr for DelegatedChainReads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "annotate" => Ok(DelegatedChainReads::Annotate),
+            "refuse" => Ok(DelegatedChainReads::Refuse),
+            _ => Err(format!(
+                "Invalid delegated chain reads '{s}', expected one of: annotate, refuse"
+            )),
+        }
+    }
+}
+
+// -----------------
+// FreshnessHint
+// -----------------
+/// Tells readers how stale the chain copy of a delegated account may be
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FreshnessHint {
+    pub pubkey: String,
+    pub delegation_slot: Slot,
+    pub commit_frequency: String,
+    /// Upper bound of the time that passed since the chain copy was last
+    /// updated, derived from the commit frequency and the slots that passed
+    /// since the account was delegated
+    pub estimated_staleness_ms: u64,
+}
+
+impl FreshnessHint {
+    fn new(pubkey: &Pubkey, record: &DelegationRecord, at_slot: Slot) -> Self {
+        let since_delegation_ms = at_slot
+            .saturating_sub(record.delegation_slot)
+            .saturating_mul(DEFAULT_MS_PER_SLOT);
+        Self {
+            pubkey: pubkey.to_string(),
+            delegation_slot: record.delegation_slot,
+            commit_frequency: record.commit_frequency.to_string(),
+            estimated_staleness_ms: estimated_staleness_ms(
+                record.commit_frequency,
+                since_delegation_ms,
+            ),
+        }
+    }
+}
+
+/// Accounts that aren't committed periodically may not have been updated on
+/// chain since they were delegated
+fn estimated_staleness_ms(
+    commit_frequency: CommitFrequency,
+    since_delegation_ms: u64,
+) -> u64 {
+    match commit_frequency.to_duration() {
+        Some(interval) => since_delegation_ms
+            .min(interval.as_millis().try_into().unwrap_or(u64::MAX)),
+        None => since_delegation_ms,
+    }
+}
+
+/// The context of the chain response extended with hints for the delegated
+/// accounts it includes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FreshnessContext {
+    #[serde(flatten)]
+    pub context: RpcResponseContext,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delegated: Vec<FreshnessHint>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FreshnessResponse<T> {
+    pub context: FreshnessContext,
+    pub value: T,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RefusedChainRead {
+    delegated: Vec<FreshnessHint>,
+}
+
+// -----------------
+// Account Reads
+// -----------------
+impl<T: AccountProvider, U: DelegationRecordParser> DirectorRpc<T, U> {
+    /// Reads accounts from chain unless the client picked the ephemeral
+    /// validator. Chain reads of delegated accounts are annotated or refused
+    /// depending on [DelegatedChainReads].
+    pub(super) async fn get_accounts<
+        V: DeserializeOwned + Serialize + Clone,
+    >(
+        &self,
+        method: &str,
+        params: Params<'static>,
+        pubkeys: &[String],
+    ) -> RpcResult<FreshnessResponse<V>> {
+        let route = self.route_override.unwrap_or(RouteOverride::Chain);
+        let delegated = match route {
+            RouteOverride::Chain => {
+                self.delegated_accounts(&parse_pubkeys(pubkeys)?).await?
+            }
+            RouteOverride::Ephemeral => vec![],
+        };
+
+        if self.delegated_chain_reads == DelegatedChainReads::Refuse
+            && !delegated.is_empty()
+        {
+            let delegated = delegated
+                .iter()
+                .map(|(pubkey, record, at_slot)| {
+                    FreshnessHint::new(pubkey, record, *at_slot)
+                })
+                .collect::<Vec<_>>();
+            return Err(server_error_with_data(
+                format!(
+                    "Refusing to read delegated account(s) from chain: {}",
+                    delegated
+                        .iter()
+                        .map(|hint| hint.pubkey.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                ServerErrorCode::DelegatedAccountChainRead,
+                RefusedChainRead { delegated },
+            ));
+        }
+
+        let RpcResponse { context, value } =
+            passthrough_impl::<RpcResponse<V>, _, _>(method, params, self)
+                .await?;
+        let delegated = delegated
+            .iter()
+            .map(|(pubkey, record, _)| {
+                FreshnessHint::new(pubkey, record, context.slot)
+            })
+            .collect();
+        Ok(FreshnessResponse {
+            context: FreshnessContext { context, delegated },
+            value,
+        })
+    }
+
+    /// Finds the delegated accounts among the provided ones.
+    /// If that lookup fails the read is only rejected if we'd refuse reading
+    /// delegated accounts.
+    async fn delegated_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> RpcResult<Vec<(Pubkey, DelegationRecord, Slot)>> {
+        let snapshots =
+            match self.transwise.account_chain_snapshots(pubkeys, None).await {
+                Ok(snapshots) => snapshots,
+                Err(err)
+                    if self.delegated_chain_reads
+                        == DelegatedChainReads::Refuse =>
+                {
+                    return Err(server_error(
+                        format!("error: {err}"),
+                        ServerErrorCode::FailedToFetchEndpointInformation,
+                    ));
+                }
+                Err(err) => {
+                    warn!("Failed to find delegated accounts: {err}");
+                    return Ok(vec![]);
+                }
+            };
+        Ok(snapshots
+            .into_iter()
+            .filter_map(|snapshot| match snapshot.chain_state {
+                AccountChainState::Delegated {
+                    delegation_record, ..
+                } => {
+                    Some((snapshot.pubkey, delegation_record, snapshot.at_slot))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+fn parse_pubkeys(pubkeys: &[String]) -> RpcResult<Vec<Pubkey>> {
+    pubkeys
+        .iter()
+        .map(|pubkey| {
+            pubkey
+                .parse()
+                .map_err(|err| invalid_params(format!("{pubkey}: {err}")))
+        })
+        .collect()
+}
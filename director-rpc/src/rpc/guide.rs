@@ -1,24 +1,33 @@
+use std::time::Instant;
+
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_metrics::{inc_transaction_endpoint, inc_unroutable_transaction};
 use conjunto_transwise::endpoint::Endpoint;
 use jsonrpsee::{
-    core::{client::ClientT, RegisterMethodError, RpcResult},
+    core::{RegisterMethodError, RpcResult},
     RpcModule,
 };
 use log::*;
+use solana_account_decoder::UiAccount;
 use solana_rpc_client_api::config::RpcSendTransactionConfig;
 use solana_sdk::transaction::VersionedTransaction;
 use solana_transaction_status::UiTransactionEncoding;
 
 use super::DirectorRpc;
 use crate::{
+    audit::RoutingRecord,
     decoders::decode_and_deserialize,
+    metrics::request_upstream,
     rpc::params::SendTransactionParams,
     utils::{
         invalid_params, server_error, server_error_with_data, ServerErrorCode,
     },
 };
 
-pub fn register_guide_methods(
-    module: &mut RpcModule<DirectorRpc>,
+pub fn register_guide_methods<T: AccountProvider, U: DelegationRecordParser>(
+    module: &mut RpcModule<DirectorRpc<T, U>>,
 ) -> Result<(), RegisterMethodError> {
     module.register_async_method(
         "sendTransaction",
@@ -30,24 +39,69 @@ pub fn register_guide_methods(
             rpc.send_transaction(data, config).await
         },
     )?;
+    module.register_async_method(
+        "getAccountInfo",
+        |params, rpc| async move {
+            debug!("getAccountInfo");
+            trace!("{:#?}", params);
+            let pubkey = params.sequence().next::<String>()?;
+            rpc.get_accounts::<Option<UiAccount>>(
+                "getAccountInfo",
+                params,
+                &[pubkey],
+            )
+            .await
+        },
+    )?;
+    module.register_async_method(
+        "getMultipleAccounts",
+        |params, rpc| async move {
+            debug!("getMultipleAccounts");
+            trace!("{:#?}", params);
+            let pubkeys = params.sequence().next::<Vec<String>>()?;
+            rpc.get_accounts::<Vec<Option<UiAccount>>>(
+                "getMultipleAccounts",
+                params,
+                &pubkeys,
+            )
+            .await
+        },
+    )?;
 
     Ok(())
 }
 
-impl DirectorRpc {
+impl<T: AccountProvider, U: DelegationRecordParser> DirectorRpc<T, U> {
+    /// Routes the transaction and records the decision in the audit log if
+    /// the request is sampled
     async fn send_transaction(
         &self,
         data: String,
         config: Option<RpcSendTransactionConfig>,
     ) -> RpcResult<String> {
-        debug!("send_transaction rpc request received");
+        let start = Instant::now();
+        let mut record = self.audit.sample("sendTransaction");
+        let res = self.route_transaction(data, config, &mut record).await;
+        if let Some(record) = &mut record {
+            record.finish(start.elapsed(), &res);
+            self.audit.record(record);
+        }
+        res
+    }
+
+    async fn route_transaction(
+        &self,
+        data: String,
+        config: Option<RpcSendTransactionConfig>,
+        record: &mut Option<RoutingRecord>,
+    ) -> RpcResult<String> {
         // 1. Deserialize Transaction
         let RpcSendTransactionConfig {
             skip_preflight: _,
-            preflight_commitment: _,
+            preflight_commitment,
             encoding,
             max_retries: _,
-            min_context_slot: _,
+            min_context_slot,
         } = config.unwrap_or_default();
 
         let tx_encoding = encoding.unwrap_or(UiTransactionEncoding::Base58);
@@ -61,11 +115,43 @@ impl DirectorRpc {
             &data,
             binary_encoding,
         )?;
+        if let Some(record) = record.as_mut() {
+            record.signature =
+                versioned_tx.signatures.first().map(ToString::to_string);
+        }
 
-        // 2. Determine Endpoint to be used for this Transaction
+        // 2. Forward right away if the client explicitly picked the backend
+        if let Some(route) = self.route_override {
+            debug!("send_transaction route override: {}", route);
+            inc_transaction_endpoint(route.as_str());
+            if let Some(record) = record.as_mut() {
+                record.route_override = Some(route.to_string());
+                record.endpoint = Some(route.as_str().to_string());
+            }
+            return request_upstream(
+                self.client_for_route(route),
+                route.as_str(),
+                "sendTransaction",
+                SendTransactionParams(data, config),
+            )
+            .await
+            .map_err(|err| {
+                server_error(
+                    format!("Failed to forward to {route} RPC: {err:?}"),
+                    ServerErrorCode::RpcClientError,
+                )
+            });
+        }
+
+        // 3. Determine Endpoint to be used for this Transaction, evaluating
+        //    account state at least as fresh as the client expects
         let endpoint = match self
             .transwise
-            .guide_versioned_transaction(&versioned_tx)
+            .guide_versioned_transaction(
+                &versioned_tx,
+                min_context_slot,
+                preflight_commitment,
+            )
             .await
         {
             Ok(endpoint) => endpoint,
@@ -76,36 +162,53 @@ impl DirectorRpc {
                 ));
             }
         };
-        // 3. Route transaction accordingly
-        info!("endpoint: {:#?}", endpoint);
+        // 4. Route transaction accordingly
+        inc_transaction_endpoint(endpoint.name());
+        if let Some(record) = record.as_mut() {
+            record.set_endpoint(&endpoint, self.audit.redact_account_data());
+        }
+        if let Some(reason) = endpoint.unroutable_reason() {
+            inc_unroutable_transaction(reason.code());
+        }
         match &endpoint {
-            Endpoint::Chain { .. } => Ok(self
-                .rpc_chain_client
-                .request("sendTransaction", SendTransactionParams(data, config))
-                .await
-                .map_err(|err| {
-                    server_error_with_data(
-                        format!("Failed to forward to on-chain RPC: {err:?}"),
-                        ServerErrorCode::RpcClientError,
-                        endpoint,
-                    )
-                })?),
-            Endpoint::Ephemeral { .. } => Ok(self
-                .rpc_ephem_client
-                .request("sendTransaction", SendTransactionParams(data, config))
+            Endpoint::Chain { .. } => Ok(request_upstream(
+                &self.rpc_chain_client,
+                "chain",
+                "sendTransaction",
+                SendTransactionParams(data, config),
+            )
+            .await
+            .map_err(|err| {
+                server_error_with_data(
+                    format!("Failed to forward to on-chain RPC: {err:?}"),
+                    ServerErrorCode::RpcClientError,
+                    endpoint,
+                )
+            })?),
+            Endpoint::Ephemeral { .. } => Ok(request_upstream(
+                self.ephem_client_for(endpoint.ephemeral_validator_authority()),
+                "ephemeral",
+                "sendTransaction",
+                SendTransactionParams(data, config),
+            )
+            .await
+            .map_err(|err| {
+                server_error_with_data(
+                    format!("Failed to forward to ephemeral RPC: {err:?}"),
+                    ServerErrorCode::RpcClientError,
+                    endpoint,
+                )
+            })?),
+            Endpoint::Unroutable { .. } => {
+                self.send_unroutable_transaction(
+                    data,
+                    config,
+                    tx_encoding,
+                    endpoint,
+                    record,
+                )
                 .await
-                .map_err(|err| {
-                    server_error_with_data(
-                        format!("Failed to forward to ephemeral RPC: {err:?}"),
-                        ServerErrorCode::RpcClientError,
-                        endpoint,
-                    )
-                })?),
-            Endpoint::Unroutable { .. } => Err(server_error_with_data(
-                "Transaction is unroutable".to_string(),
-                ServerErrorCode::TransactionUnroutable,
-                endpoint,
-            )),
+            }
         }
     }
 }
@@ -1,23 +1,74 @@
-use conjunto_addresses::cluster::RpcCluster;
+use std::{collections::HashMap, sync::Arc};
+
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_guidepoint::{
+    auth::ApiKeyAuth,
+    client_stream::WebSocketUpgradeSender,
+    rate_limit::{RateLimitConfig, RateLimiter},
+    tls::TlsTermination,
+    RouteOverride,
+};
 use conjunto_providers::rpc_provider_config::RpcProviderConfig;
 use conjunto_transwise::transwise::Transwise;
 use jsonrpsee::{
+    core::RpcResult,
     http_client::{HttpClient, HttpClientBuilder},
-    RpcModule,
+    Methods, RpcModule,
 };
+use solana_sdk::pubkey::Pubkey;
 
+pub use self::{freshness::DelegatedChainReads, simulate::SimulationFallback};
 use self::{
     guide::register_guide_methods, passthrough::register_passthrough_methods,
 };
-use crate::errors::DirectorRpcResult;
+use crate::{
+    audit::{AuditConfig, AuditLog},
+    errors::DirectorRpcResult,
+    health::{HealthConfig, HealthMonitor},
+};
 
+mod freshness;
 pub mod guide;
 mod params;
 pub mod passthrough;
+mod simulate;
 
 pub struct DirectorConfig {
     pub ephem_rpc_provider_config: RpcProviderConfig,
     pub chain_cluster: RpcCluster,
+    pub simulation_fallback: SimulationFallback,
+    /// Authority of the ephemeral validator, if provided transactions writing
+    /// accounts delegated to validators other than this one or the ones in
+    /// the `validator_registry` are rejected as unroutable
+    pub ephem_validator_authority: Option<Pubkey>,
+    /// Additional ephemeral validators, transactions writing accounts
+    /// delegated to one of them are sent to its RPC instead
+    pub validator_registry: ValidatorRegistry,
+    /// If provided, transactions are only routed to the ephemeral validator
+    /// if their payer is delegated or has an escrow holding at least this
+    /// many lamports, not checked by default same as with [Transwise::new]
+    pub payer_escrow_min_lamports: Option<u64>,
+    /// How reads of delegated accounts that are served by chain are handled
+    pub delegated_chain_reads: DelegatedChainReads,
+    /// How the chain and ephemeral backends are probed for `/health`,
+    /// `/ready` and `getDirectorHealth`
+    pub health: HealthConfig,
+    /// Where and how the routing decisions are recorded
+    pub audit: AuditConfig,
+    /// How many requests each client may send
+    pub rate_limit: RateLimitConfig,
+    /// The API keys clients need to provide, the default lets everyone in
+    pub auth: ApiKeyAuth,
+    /// If provided the server only accepts HTTPS connections
+    pub tls: Option<TlsTermination>,
+    /// If provided websocket upgrade requests are handed to the pubsub
+    /// server through it so that both are served on the same port
+    pub websocket_upgrades: Option<WebSocketUpgradeSender>,
 }
 
 impl DirectorConfig {
@@ -25,36 +76,205 @@ impl DirectorConfig {
         Self {
             chain_cluster: RpcCluster::Devnet,
             ephem_rpc_provider_config: RpcProviderConfig::magicblock_devnet(),
+            simulation_fallback: SimulationFallback::default(),
+            ephem_validator_authority: None,
+            validator_registry: ValidatorRegistry::default(),
+            payer_escrow_min_lamports: None,
+            delegated_chain_reads: DelegatedChainReads::default(),
+            health: HealthConfig::default(),
+            audit: AuditConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: ApiKeyAuth::default(),
+            tls: None,
+            websocket_upgrades: None,
         }
     }
 }
 
-pub struct DirectorRpc {
-    pub(super) transwise: Transwise,
+pub struct DirectorRpc<T: AccountProvider, U: DelegationRecordParser> {
+    pub(super) transwise: Arc<Transwise<T, U>>,
     pub(super) rpc_chain_client: HttpClient,
     pub(super) rpc_ephem_client: HttpClient,
+    /// Clients of the validators in the registry by their authority
+    pub(super) rpc_validator_clients: Arc<HashMap<Pubkey, HttpClient>>,
+    /// The backend the client explicitly picked which bypasses all guiding
+    pub(super) route_override: Option<RouteOverride>,
+    pub(super) simulation_fallback: SimulationFallback,
+    pub(super) delegated_chain_reads: DelegatedChainReads,
+    pub(super) health: HealthMonitor,
+    pub(super) audit: AuditLog,
 }
 
-pub fn create_rpc_module(
+// Implemented manually since deriving would require the providers to be Clone
+impl<T: AccountProvider, U: DelegationRecordParser> Clone
+    for DirectorRpc<T, U>
+{
+    fn clone(&self) -> Self {
+        Self {
+            transwise: self.transwise.clone(),
+            rpc_chain_client: self.rpc_chain_client.clone(),
+            rpc_ephem_client: self.rpc_ephem_client.clone(),
+            rpc_validator_clients: self.rpc_validator_clients.clone(),
+            route_override: self.route_override,
+            simulation_fallback: self.simulation_fallback,
+            delegated_chain_reads: self.delegated_chain_reads,
+            health: self.health.clone(),
+            audit: self.audit.clone(),
+        }
+    }
+}
+
+impl<T: AccountProvider, U: DelegationRecordParser> DirectorRpc<T, U> {
+    pub fn with_transwise(
+        config: &DirectorConfig,
+        transwise: Transwise<T, U>,
+    ) -> DirectorRpcResult<Self> {
+        let rpc_ephem_client = HttpClientBuilder::default()
+            .build(config.ephem_rpc_provider_config.url())?;
+        let rpc_chain_client =
+            HttpClientBuilder::default().build(config.chain_cluster.url())?;
+        let rpc_validator_clients: HashMap<Pubkey, HttpClient> = config
+            .validator_registry
+            .iter()
+            .map(|(authority, cluster)| {
+                Ok((
+                    *authority,
+                    HttpClientBuilder::default().build(cluster.url())?,
+                ))
+            })
+            .collect::<DirectorRpcResult<_>>()?;
+        let health = HealthMonitor::new(
+            config.health.clone(),
+            &config.chain_cluster,
+            config.ephem_rpc_provider_config.cluster(),
+        )?;
+        Ok(Self {
+            transwise: Arc::new(transwise),
+            rpc_chain_client,
+            rpc_ephem_client,
+            rpc_validator_clients: Arc::new(rpc_validator_clients),
+            route_override: None,
+            simulation_fallback: config.simulation_fallback,
+            delegated_chain_reads: config.delegated_chain_reads,
+            health,
+            audit: AuditLog::new(&config.audit)?,
+        })
+    }
+
+    pub(super) fn client_for_route(&self, route: RouteOverride) -> &HttpClient {
+        match route {
+            RouteOverride::Chain => &self.rpc_chain_client,
+            RouteOverride::Ephemeral => &self.rpc_ephem_client,
+        }
+    }
+
+    /// The client of the ephemeral validator with the provided authority,
+    /// falls back to the default ephemeral validator if it is not registered
+    pub(super) fn ephem_client_for(
+        &self,
+        validator_authority: Option<Pubkey>,
+    ) -> &HttpClient {
+        validator_authority
+            .and_then(|authority| self.rpc_validator_clients.get(&authority))
+            .unwrap_or(&self.rpc_ephem_client)
+    }
+
+    fn with_route_override(&self, route_override: RouteOverride) -> Self {
+        Self {
+            route_override: Some(route_override),
+            ..self.clone()
+        }
+    }
+}
+
+/// The RPC methods for each way a request can be routed.
+/// They all share the same clients and [Transwise] instance.
+#[derive(Clone)]
+pub struct DirectorRpcModules {
+    /// Requests are guided to the backend that best serves them
+    pub guided: Methods,
+    /// Requests the client explicitly routed to chain
+    pub chain: Methods,
+    /// Requests the client explicitly routed to the ephemeral validator
+    pub ephemeral: Methods,
+    /// Serves the `/health` and `/ready` endpoints
+    pub health: HealthMonitor,
+    /// Rejects requests of clients that exceeded their budget
+    pub rate_limiter: RateLimiter,
+    /// Rejects requests without a valid API key for the method
+    pub auth: ApiKeyAuth,
+    /// Terminates TLS for the connections of clients if provided
+    pub tls: Option<TlsTermination>,
+    /// Where websocket upgrade requests are handed to if provided
+    pub websocket_upgrades: Option<WebSocketUpgradeSender>,
+}
+
+impl DirectorRpcModules {
+    pub fn for_route(&self, route_override: Option<RouteOverride>) -> Methods {
+        match route_override {
+            None => self.guided.clone(),
+            Some(RouteOverride::Chain) => self.chain.clone(),
+            Some(RouteOverride::Ephemeral) => self.ephemeral.clone(),
+        }
+    }
+}
+
+pub fn create_rpc_modules(
     config: DirectorConfig,
-) -> DirectorRpcResult<RpcModule<DirectorRpc>> {
-    let ephem_url = config.ephem_rpc_provider_config.url().to_string();
-    let transwise = Transwise::new(config.ephem_rpc_provider_config);
+) -> DirectorRpcResult<DirectorRpcModules> {
+    let mut transwise =
+        Transwise::new(config.ephem_rpc_provider_config.clone());
+    if let Some(validator_authority) = config.ephem_validator_authority {
+        transwise = transwise.with_validator_authority(validator_authority);
+    }
+    // Accounts delegated to unregistered validators can't be written even
+    // without a default authority
+    for authority in config.validator_registry.authorities() {
+        transwise = transwise.with_validator_authority(*authority);
+    }
+    if let Some(min_lamports) = config.payer_escrow_min_lamports {
+        transwise = transwise.with_payer_escrow_check(min_lamports);
+    }
+    create_rpc_modules_with_transwise(&config, transwise)
+}
 
-    let rpc_ephem_client = HttpClientBuilder::default().build(ephem_url)?;
-    let rpc_chain_client =
-        HttpClientBuilder::default().build(config.chain_cluster.url())?;
+pub fn create_rpc_modules_with_transwise<
+    T: AccountProvider,
+    U: DelegationRecordParser,
+>(
+    config: &DirectorConfig,
+    transwise: Transwise<T, U>,
+) -> DirectorRpcResult<DirectorRpcModules> {
+    let director = DirectorRpc::with_transwise(config, transwise)?;
 
-    let director = DirectorRpc {
-        transwise,
-        rpc_ephem_client,
-        rpc_chain_client,
-    };
+    Ok(DirectorRpcModules {
+        health: director.health.clone(),
+        rate_limiter: RateLimiter::new(config.rate_limit.clone()),
+        auth: config.auth.clone(),
+        tls: config.tls.clone(),
+        websocket_upgrades: config.websocket_upgrades.clone(),
+        chain: create_rpc_module(
+            director.with_route_override(RouteOverride::Chain),
+        )?
+        .into(),
+        ephemeral: create_rpc_module(
+            director.with_route_override(RouteOverride::Ephemeral),
+        )?
+        .into(),
+        guided: create_rpc_module(director)?.into(),
+    })
+}
 
+pub fn create_rpc_module<T: AccountProvider, U: DelegationRecordParser>(
+    director: DirectorRpc<T, U>,
+) -> DirectorRpcResult<RpcModule<DirectorRpc<T, U>>> {
     let mut module = RpcModule::new(director);
 
     register_guide_methods(&mut module)?;
     register_passthrough_methods(&mut module)?;
+    module.register_method("getDirectorHealth", |_params, rpc| {
+        RpcResult::Ok(rpc.health.health())
+    })?;
 
     Ok(module)
 }
@@ -3,7 +3,10 @@ use jsonrpsee::{
     types::Params,
 };
 use serde::{Deserialize, Serialize};
-use solana_rpc_client_api::config::RpcSendTransactionConfig;
+use solana_rpc_client_api::config::{
+    RpcAccountInfoConfig, RpcSendTransactionConfig,
+    RpcSimulateTransactionConfig,
+};
 
 // -----------------
 // RawParams
@@ -42,3 +45,41 @@ impl ToRpcParams for SendTransactionParams {
         Ok(Some(raw_value))
     }
 }
+
+// -----------------
+// SimulateTransactionParams
+// -----------------
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimulateTransactionParams(
+    pub String,
+    pub RpcSimulateTransactionConfig,
+);
+
+impl ToRpcParams for SimulateTransactionParams {
+    fn to_rpc_params(
+        self,
+    ) -> Result<Option<Box<JsonRawValue>>, serde_json::Error> {
+        let raw_value =
+            JsonRawValue::from_string(serde_json::to_string(&self)?)?;
+        Ok(Some(raw_value))
+    }
+}
+
+// -----------------
+// GetMultipleAccountsParams
+// -----------------
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetMultipleAccountsParams(
+    pub Vec<String>,
+    #[serde(default)] pub Option<RpcAccountInfoConfig>,
+);
+
+impl ToRpcParams for GetMultipleAccountsParams {
+    fn to_rpc_params(
+        self,
+    ) -> Result<Option<Box<JsonRawValue>>, serde_json::Error> {
+        let raw_value =
+            JsonRawValue::from_string(serde_json::to_string(&self)?)?;
+        Ok(Some(raw_value))
+    }
+}
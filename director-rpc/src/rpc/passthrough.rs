@@ -1,11 +1,15 @@
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_guidepoint::RouteOverride;
 use jsonrpsee::{
-    core::{client::ClientT, ClientError, RegisterMethodError},
+    core::{ClientError, RegisterMethodError},
     types::{ErrorObjectOwned, Params},
     RpcModule,
 };
 use log::*;
 use serde::de::DeserializeOwned;
-use solana_account_decoder::{parse_token::UiTokenAmount, UiAccount};
+use solana_account_decoder::parse_token::UiTokenAmount;
 use solana_rpc_client_api::response::{
     OptionalContext, Response as RpcResponse, RpcAccountBalance,
     RpcBlockCommitment, RpcBlockProduction, RpcBlockhash,
@@ -24,6 +28,7 @@ use solana_transaction_status::{TransactionStatus, UiConfirmedBlock};
 
 use super::DirectorRpc;
 use crate::{
+    metrics::request_upstream,
     rpc::params::RawParams,
     utils::{server_error, ServerErrorCode},
 };
@@ -38,32 +43,44 @@ type BlockCommitmentArray = [u64; MAX_LOCKOUT_HISTORY + 1];
 // -----------------
 // register_passthrough_methods
 // -----------------
-async fn passthrough_impl<R: DeserializeOwned>(
+pub(super) async fn passthrough_impl<
+    R: DeserializeOwned,
+    T: AccountProvider,
+    U: DelegationRecordParser,
+>(
     method: &str,
     params: Params<'static>,
-    rpc: &DirectorRpc,
+    rpc: &DirectorRpc<T, U>,
 ) -> Result<R, ErrorObjectOwned> {
     let params = RawParams(params);
-    match rpc
-        .rpc_chain_client
-        .request::<R, RawParams>(method, params)
-        .await
+    // Methods we don't guide yet go to chain unless the client explicitly
+    // picked the backend
+    let route = rpc.route_override.unwrap_or(RouteOverride::Chain);
+    match request_upstream::<R, _>(
+        rpc.client_for_route(route),
+        route.as_str(),
+        method,
+        params,
+    )
+    .await
     {
         Ok(res) => Ok(res),
         Err(err) => match err {
             // Pass RPC JSON errors through directly
             ClientError::Call(err) => Err(err),
             _ => Err(server_error(
-                format!("Failed to forward to on-chain RPC: {err:?}"),
+                format!("Failed to forward to {route} RPC: {err:?}"),
                 ServerErrorCode::RpcClientError,
             )),
         },
     }
 }
 
-
-pub fn register_passthrough_methods(
-    module: &mut RpcModule<DirectorRpc>,
+pub fn register_passthrough_methods<
+    T: AccountProvider,
+    U: DelegationRecordParser,
+>(
+    module: &mut RpcModule<DirectorRpc<T, U>>,
 ) -> Result<(), RegisterMethodError> {
     macro_rules! passthrough {
         ($method:literal, $return_type:ty) => {
@@ -72,8 +89,10 @@ pub fn register_passthrough_methods(
                 |params, rpc| async move {
                     debug!("{}", $method);
                     trace!("{:#?}", params);
-                    passthrough_impl::<$return_type>($method, params, &rpc)
-                        .await
+                    passthrough_impl::<$return_type, _, _>(
+                        $method, params, &rpc,
+                    )
+                    .await
                 },
             )?;
         };
@@ -97,8 +116,7 @@ pub fn register_passthrough_methods(
     // - Both:  for requests that return an array of results first fill from ephem and try the
     //          remaining ones from chain
 
-    // TODO: guide TryEphem
-    passthrough!("getAccountInfo", RpcResponse<Option<UiAccount>>);
+    // NOTE: getAccountInfo and getMultipleAccounts are registered as guide methods
     // TODO: guide TryEphem
     passthrough!("getBalance", RpcResponse<u64>);
     passthrough!("getBlock", Option<UiConfirmedBlock>);
@@ -130,8 +148,6 @@ pub fn register_passthrough_methods(
     passthrough!("getMaxRetransmitSlot", Slot);
     passthrough!("getMaxShredInsertSlot", Slot);
     passthrough!("getMinimumBalanceForRentExemption", u64);
-    // TODO: guide TryEphem (if any account is missing get ALL from chain)
-    passthrough!("getMultipleAccounts", RpcResponse<Vec<Option<UiAccount>>>);
     // TODO: guide TryEphem (go to chain if program is not found on ephem)
     passthrough!("getProgramAccounts", OptionalContext<Vec<RpcKeyedAccount>>);
     // TODO: guide Ephem
@@ -0,0 +1,518 @@
+use std::{fmt, str::FromStr};
+
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
+use conjunto_guidepoint::RouteOverride;
+use conjunto_transwise::endpoint::{Endpoint, UnroutableReason};
+use jsonrpsee::{core::RpcResult, http_client::HttpClient};
+use log::*;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_rpc_client_api::{
+    config::{
+        RpcAccountInfoConfig, RpcSendTransactionConfig,
+        RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
+    },
+    response::{Response as RpcResponse, RpcSimulateTransactionResult},
+};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use solana_transaction_status::UiTransactionEncoding;
+
+use super::DirectorRpc;
+use crate::{
+    audit::{AuditedSimulation, RoutingRecord},
+    metrics::request_upstream,
+    rpc::params::{
+        GetMultipleAccountsParams, SendTransactionParams,
+        SimulateTransactionParams,
+    },
+    utils::{server_error_with_data, ServerErrorCode},
+};
+
+// -----------------
+// SimulationFallback
+// -----------------
+/// Determines if and where transactions that cannot be routed by looking at
+/// the accounts they write are simulated in order to find out which of those
+/// accounts are actually written.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum SimulationFallback {
+    /// Unroutable transactions are rejected right away
+    #[default]
+    Disabled,
+    /// Simulate against the ephemeral validator
+    Ephemeral,
+    /// Simulate against chain
+    Chain,
+    /// Simulate against the ephemeral validator and if that simulation fails
+    /// simulate against chain
+    EphemeralThenChain,
+}
+
+impl fmt::Display for SimulationFallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SimulationFallback::*;
+        match self {
+            Disabled => write!(f, "disabled"),
+            Ephemeral => write!(f, "ephemeral"),
+            Chain => write!(f, "chain"),
+            EphemeralThenChain => write!(f, "ephemeral-then-chain"),
+        }
+    }
+}
+
+impl FromStr for SimulationFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use SimulationFallback::*;
+        match s {
+            "disabled" => Ok(Disabled),
+            "ephemeral" => Ok(Ephemeral),
+            "chain" => Ok(Chain),
+            "ephemeral-then-chain" => Ok(EphemeralThenChain),
+            _ => Err(format!(
+                "Invalid simulation fallback '{s}', expected one of: disabled, ephemeral, chain, ephemeral-then-chain"
+            )),
+        }
+    }
+}
+
+impl SimulationFallback {
+    fn routes(&self) -> &'static [RouteOverride] {
+        use SimulationFallback::*;
+        match self {
+            Disabled => &[],
+            Ephemeral => &[RouteOverride::Ephemeral],
+            Chain => &[RouteOverride::Chain],
+            EphemeralThenChain => {
+                &[RouteOverride::Ephemeral, RouteOverride::Chain]
+            }
+        }
+    }
+}
+
+// -----------------
+// Diagnostics
+// -----------------
+/// Outcome of simulating an unroutable transaction against one backend
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationDiagnostic {
+    pub backend: String,
+    /// Set if the simulation could not be performed or the transaction failed
+    pub err: Option<String>,
+    pub logs: Option<Vec<String>>,
+    pub written_undelegated_pubkeys: Vec<String>,
+    pub written_delegated_pubkeys: Vec<String>,
+}
+
+impl SimulationDiagnostic {
+    fn failed(route: RouteOverride, err: String) -> Self {
+        Self {
+            backend: route.to_string(),
+            err: Some(err),
+            logs: None,
+            written_undelegated_pubkeys: vec![],
+            written_delegated_pubkeys: vec![],
+        }
+    }
+
+    /// The route the transaction can take if the simulation succeeded and
+    /// did not write both undelegated and delegated accounts
+    fn route(&self) -> Option<RouteOverride> {
+        if self.err.is_some() {
+            return None;
+        }
+        match (
+            self.written_undelegated_pubkeys.is_empty(),
+            self.written_delegated_pubkeys.is_empty(),
+        ) {
+            (false, false) => None,
+            (true, false) => Some(RouteOverride::Ephemeral),
+            // Same as when routing by writable accounts we default to chain
+            (false, true) | (true, true) => Some(RouteOverride::Chain),
+        }
+    }
+}
+
+impl From<&SimulationDiagnostic> for AuditedSimulation {
+    fn from(simulation: &SimulationDiagnostic) -> Self {
+        Self {
+            backend: simulation.backend.clone(),
+            err: simulation.err.clone(),
+            written_undelegated_pubkeys: simulation
+                .written_undelegated_pubkeys
+                .clone(),
+            written_delegated_pubkeys: simulation
+                .written_delegated_pubkeys
+                .clone(),
+        }
+    }
+}
+
+/// Error data for unroutable transactions, `code` is stable and identifies
+/// the [UnroutableReason] while `pubkeys` lists the offending accounts
+#[derive(Serialize)]
+struct UnroutableDiagnostic<'a> {
+    code: &'static str,
+    pubkeys: Vec<String>,
+    #[serde(flatten)]
+    endpoint: &'a Endpoint,
+}
+
+#[derive(Serialize)]
+struct UnroutableWithSimulations<'a> {
+    code: &'static str,
+    pubkeys: Vec<String>,
+    endpoint: &'a Endpoint,
+    simulations: Vec<SimulationDiagnostic>,
+}
+
+fn unroutable_code_and_pubkeys(
+    endpoint: &Endpoint,
+) -> (&'static str, Vec<String>, String) {
+    match endpoint.unroutable_reason() {
+        Some(reason) => (
+            reason.code(),
+            reason.pubkeys().iter().map(Pubkey::to_string).collect(),
+            format!("Transaction is unroutable: {reason}"),
+        ),
+        None => ("", vec![], "Transaction is unroutable".to_string()),
+    }
+}
+
+// -----------------
+// DirectorRpc
+// -----------------
+impl<T: AccountProvider, U: DelegationRecordParser> DirectorRpc<T, U> {
+    /// Rejects the unroutable transaction unless the simulation fallback is
+    /// enabled and a simulation shows that it only writes either delegated
+    /// or undelegated accounts, in which case it is forwarded accordingly.
+    pub(super) async fn send_unroutable_transaction(
+        &self,
+        data: String,
+        config: Option<RpcSendTransactionConfig>,
+        encoding: UiTransactionEncoding,
+        endpoint: Endpoint,
+        record: &mut Option<RoutingRecord>,
+    ) -> RpcResult<String> {
+        let (code, pubkeys, msg) = unroutable_code_and_pubkeys(&endpoint);
+        // Only simulating can tell which of the writable accounts are
+        // actually written, for other reasons it would not change the outcome
+        let simulation_applies = matches!(
+            endpoint.unroutable_reason(),
+            Some(UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable { .. })
+        );
+        if self.simulation_fallback == SimulationFallback::Disabled
+            || !simulation_applies
+        {
+            return Err(server_error_with_data(
+                msg,
+                ServerErrorCode::TransactionUnroutable,
+                UnroutableDiagnostic {
+                    code,
+                    pubkeys,
+                    endpoint: &endpoint,
+                },
+            ));
+        }
+
+        let mut simulations = vec![];
+        for route in self.simulation_fallback.routes() {
+            let simulation = self
+                .simulate_unroutable(*route, &data, encoding, &endpoint)
+                .await;
+            debug!("Simulation of unroutable transaction: {:?}", simulation);
+            let done = simulation.err.is_none();
+            simulations.push(simulation);
+            if done {
+                break;
+            }
+        }
+        if let Some(record) = record.as_mut() {
+            record.simulations =
+                simulations.iter().map(AuditedSimulation::from).collect();
+        }
+
+        match simulations.last().and_then(SimulationDiagnostic::route) {
+            Some(route) => {
+                info!("Routing unroutable transaction by simulation: {route}");
+                let validator_authority = match self
+                    .unroutable_validator_authority(route, &endpoint)
+                {
+                    Ok(validator_authority) => validator_authority,
+                    Err(err) => {
+                        return Err(server_error_with_data(
+                            format!("{msg}: {err}"),
+                            ServerErrorCode::TransactionUnroutable,
+                            UnroutableWithSimulations {
+                                code,
+                                pubkeys,
+                                endpoint: &endpoint,
+                                simulations,
+                            },
+                        ))
+                    }
+                };
+                if let Some(record) = record.as_mut() {
+                    record.endpoint = Some(route.as_str().to_string());
+                    record.validator_authority = validator_authority
+                        .map(|authority| authority.to_string());
+                }
+                request_upstream(
+                    self.client_for_unroutable(route, validator_authority),
+                    route.as_str(),
+                    "sendTransaction",
+                    SendTransactionParams(data, config),
+                )
+                .await
+                .map_err(|err| {
+                    server_error_with_data(
+                        format!("Failed to forward to {route} RPC: {err:?}"),
+                        ServerErrorCode::RpcClientError,
+                        simulations,
+                    )
+                })
+            }
+            None => Err(server_error_with_data(
+                msg,
+                ServerErrorCode::TransactionUnroutable,
+                UnroutableWithSimulations {
+                    code,
+                    pubkeys,
+                    endpoint: &endpoint,
+                    simulations,
+                },
+            )),
+        }
+    }
+
+    async fn simulate_unroutable(
+        &self,
+        route: RouteOverride,
+        data: &str,
+        encoding: UiTransactionEncoding,
+        endpoint: &Endpoint,
+    ) -> SimulationDiagnostic {
+        let Endpoint::Unroutable { reason, .. } = endpoint else {
+            return SimulationDiagnostic::failed(
+                route,
+                "Transaction is not unroutable".to_string(),
+            );
+        };
+        let UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+            writable_undelegated_pubkeys,
+            writable_delegated_pubkeys,
+        } = reason
+        else {
+            return SimulationDiagnostic::failed(
+                route,
+                format!("Simulation cannot resolve: {reason}"),
+            );
+        };
+
+        let client = match self.unroutable_validator_authority(route, endpoint)
+        {
+            Ok(validator_authority) => {
+                self.client_for_unroutable(route, validator_authority)
+            }
+            Err(err) => return SimulationDiagnostic::failed(route, err),
+        };
+
+        let addresses: Vec<String> = writable_undelegated_pubkeys
+            .iter()
+            .chain(writable_delegated_pubkeys)
+            .map(Pubkey::to_string)
+            .collect();
+
+        // Delegated accounts differ between chain and the ephemeral
+        // validator, thus the simulation is compared with the state of the
+        // accounts on the backend that runs it
+        let accounts_before: RpcResponse<Vec<Option<UiAccount>>> =
+            match request_upstream(
+                client,
+                route.as_str(),
+                "getMultipleAccounts",
+                GetMultipleAccountsParams(
+                    addresses.clone(),
+                    Some(RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    }),
+                ),
+            )
+            .await
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    return SimulationDiagnostic::failed(
+                        route,
+                        format!(
+                            "Failed to get accounts on {route} RPC: {err:?}"
+                        ),
+                    )
+                }
+            };
+
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            encoding: Some(encoding),
+            min_context_slot: Some(accounts_before.context.slot),
+            accounts: Some(RpcSimulateTransactionAccountsConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                addresses,
+            }),
+            ..Default::default()
+        };
+        let response: RpcResponse<RpcSimulateTransactionResult> =
+            match request_upstream(
+                client,
+                route.as_str(),
+                "simulateTransaction",
+                SimulateTransactionParams(data.to_string(), config),
+            )
+            .await
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    return SimulationDiagnostic::failed(
+                        route,
+                        format!("Failed to simulate on {route} RPC: {err:?}"),
+                    )
+                }
+            };
+
+        let RpcSimulateTransactionResult {
+            err,
+            logs,
+            accounts,
+            ..
+        } = response.value;
+        if let Some(err) = err {
+            return SimulationDiagnostic {
+                logs,
+                ..SimulationDiagnostic::failed(route, format!("{err:?}"))
+            };
+        }
+        let Some(accounts) = accounts else {
+            return SimulationDiagnostic {
+                logs,
+                ..SimulationDiagnostic::failed(
+                    route,
+                    "Simulation did not include accounts".to_string(),
+                )
+            };
+        };
+
+        // Accounts are returned in the order we requested them
+        let undelegated_len = writable_undelegated_pubkeys.len();
+        let (undelegated_before, delegated_before) =
+            split_at_most(&accounts_before.value, undelegated_len);
+        let (undelegated_after, delegated_after) =
+            split_at_most(&accounts, undelegated_len);
+        SimulationDiagnostic {
+            backend: route.to_string(),
+            err: None,
+            written_undelegated_pubkeys: written_pubkeys(
+                writable_undelegated_pubkeys,
+                undelegated_before,
+                undelegated_after,
+            ),
+            written_delegated_pubkeys: written_pubkeys(
+                writable_delegated_pubkeys,
+                delegated_before,
+                delegated_after,
+            ),
+            logs,
+        }
+    }
+
+    /// The ephemeral validator an unroutable transaction is simulated on and
+    /// forwarded to, the one its writable delegated accounts are delegated to.
+    /// `None` on the chain route or if any validator will do.
+    fn unroutable_validator_authority(
+        &self,
+        route: RouteOverride,
+        endpoint: &Endpoint,
+    ) -> Result<Option<Pubkey>, String> {
+        if route == RouteOverride::Chain {
+            return Ok(None);
+        }
+        match endpoint
+            .transaction_accounts_snapshot()
+            .writable_delegated_validator_authorities()
+            .as_slice()
+        {
+            [] => Ok(None),
+            [authority] => Ok(Some(*authority)),
+            authorities => Err(format!(
+                "Writable delegated accounts are delegated to multiple validators: {}",
+                authorities
+                    .iter()
+                    .map(Pubkey::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// The client of the backend an unroutable transaction is simulated on
+    /// and forwarded to
+    fn client_for_unroutable(
+        &self,
+        route: RouteOverride,
+        validator_authority: Option<Pubkey>,
+    ) -> &HttpClient {
+        match route {
+            RouteOverride::Chain => &self.rpc_chain_client,
+            RouteOverride::Ephemeral => {
+                self.ephem_client_for(validator_authority)
+            }
+        }
+    }
+}
+
+fn split_at_most(
+    accounts: &[Option<UiAccount>],
+    mid: usize,
+) -> (&[Option<UiAccount>], &[Option<UiAccount>]) {
+    accounts.split_at(mid.min(accounts.len()))
+}
+
+fn written_pubkeys(
+    pubkeys: &[Pubkey],
+    accounts_before: &[Option<UiAccount>],
+    accounts_after: &[Option<UiAccount>],
+) -> Vec<String> {
+    let decode = |account: &Option<UiAccount>| {
+        account.as_ref().and_then(UiAccount::decode::<Account>)
+    };
+    pubkeys
+        .iter()
+        .zip(accounts_before.iter().zip(accounts_after))
+        .filter(|(_, (before, after))| {
+            is_written(decode(before).as_ref(), decode(after).as_ref())
+        })
+        .map(|(pubkey, _)| pubkey.to_string())
+        .collect()
+}
+
+/// Compares the state of the account after the simulation with its state on
+/// the backend that simulated the transaction
+fn is_written(before: Option<&Account>, after: Option<&Account>) -> bool {
+    match (before, after) {
+        (None, None) => false,
+        (Some(before), Some(after)) => {
+            before.lamports != after.lamports
+                || before.owner != after.owner
+                || before.data != after.data
+        }
+        _ => true,
+    }
+}
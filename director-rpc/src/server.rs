@@ -0,0 +1,281 @@
+use std::{error::Error as StdError, net::SocketAddr};
+
+use conjunto_guidepoint::{
+    client_stream::{ClientStream, WebSocketUpgrade},
+    rate_limit::{api_key_from_query_and_header, API_KEY_HEADER},
+    shutdown::{
+        drain_connections, shutdown_requested, ShutdownReceiver,
+        ShutdownSummary,
+    },
+    RouteOverride, ROUTE_OVERRIDE_HEADER,
+};
+use conjunto_metrics::METRICS_CONTENT_TYPE;
+use hyper::{
+    server::conn::Http, service::service_fn, Body, Method, Request, Response,
+    StatusCode,
+};
+use jsonrpsee::server::{
+    stop_channel, RpcServiceBuilder, Server, StopHandle, TowerServiceBuilder,
+};
+use log::*;
+use serde::Serialize;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+use tower::{layer::util::Identity, Service};
+
+use crate::{
+    auth::AuthLayer, health::HealthMonitor, metrics::RequestMetricsLayer,
+    rate_limit::RateLimitLayer, rpc::DirectorRpcModules,
+    shutdown::InFlightRequests,
+};
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+type ServiceBuilder = TowerServiceBuilder<Identity, Identity>;
+
+/// Accepts connections until the server is shut down, then lets them finish
+/// their in-flight requests.
+/// We drive the jsonrpsee service ourselves instead of using its server in
+/// order to pick the RPC methods matching the route of each request.
+pub(crate) async fn serve_rpc(
+    listener: TcpListener,
+    rpc_modules: DirectorRpcModules,
+    mut shutdown_rx: ShutdownReceiver,
+) -> ShutdownSummary {
+    // Stops the health monitor once it is dropped with this task
+    let (stop_handle, _server_handle) = stop_channel();
+    tokio::spawn(rpc_modules.health.clone().run(stop_handle.clone()));
+    rpc_modules.auth.spawn_reloader();
+    if let Some(tls) = &rpc_modules.tls {
+        tls.spawn_reloader();
+    }
+
+    let service_builder = Server::builder().http_only().to_service_builder();
+    let in_flight = InFlightRequests::default();
+    let mut connections = JoinSet::new();
+    let drain_timeout = loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    connections.spawn(serve_connection(
+                        stream,
+                        addr,
+                        rpc_modules.clone(),
+                        service_builder.clone(),
+                        stop_handle.clone(),
+                        in_flight.clone(),
+                        shutdown_rx.clone(),
+                    ));
+                }
+                Err(err) => {
+                    error!("Failed to accept RPC connection: {:?}", err);
+                }
+            },
+            // Reaps the connections that were closed
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            drain_timeout = shutdown_requested(&mut shutdown_rx) => {
+                break drain_timeout;
+            }
+        }
+    };
+    drop(listener);
+
+    let in_flight_requests = in_flight.count();
+    info!(
+        "Shutting down RPC server, draining {} request(s) on {} connection(s)",
+        in_flight_requests,
+        connections.len()
+    );
+    drain_connections(connections, drain_timeout, in_flight_requests).await
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    rpc_modules: DirectorRpcModules,
+    service_builder: ServiceBuilder,
+    stop_handle: StopHandle,
+    in_flight: InFlightRequests,
+    mut shutdown_rx: ShutdownReceiver,
+) {
+    trace!("RPC connection from: {}", addr);
+    // Hyper only takes over shutting the connection down once the client
+    // completed the handshake and sent the head of its first request
+    let stream = tokio::select! {
+        stream = accept_stream(stream, addr, &rpc_modules) => stream,
+        _ = shutdown_requested(&mut shutdown_rx) => return,
+    };
+    let Some(stream) = stream else {
+        return;
+    };
+    let service = service_fn(move |req: Request<Body>| {
+        let rpc_modules = rpc_modules.clone();
+        let service_builder = service_builder.clone();
+        let stop_handle = stop_handle.clone();
+        let in_flight = in_flight.start();
+        async move {
+            let _in_flight = in_flight;
+            if let Some(res) = health_response(&req, &rpc_modules.health)
+                .or_else(|| metrics_response(&req))
+            {
+                return Ok::<_, BoxError>(res);
+            }
+            let route_override = match route_override_from_request(&req) {
+                Ok(route_override) => route_override,
+                Err(err) => {
+                    return Ok::<_, BoxError>(text_response(
+                        StatusCode::BAD_REQUEST,
+                        err,
+                    ))
+                }
+            };
+            if let Some(route) = route_override {
+                debug!("RPC request route override: {}", route);
+            }
+            let methods = rpc_modules.for_route(route_override);
+            let route = route_override.map_or("guided", |route| route.as_str());
+            let api_key = api_key_from_request(&req);
+            let client = rpc_modules.rate_limiter.client_key(
+                addr.ip(),
+                api_key.as_deref(),
+                &rpc_modules.auth,
+            );
+            // Rejected requests are counted by the metrics as well and
+            // unauthorized ones don't take from any budget
+            let rpc_middleware = RpcServiceBuilder::new()
+                .layer(RequestMetricsLayer::new(route, methods.clone()))
+                .layer(AuthLayer::new(rpc_modules.auth.clone(), api_key))
+                .layer(RateLimitLayer::new(
+                    rpc_modules.rate_limiter.clone(),
+                    client,
+                ));
+            let mut rpc_service = service_builder
+                .set_rpc_middleware(rpc_middleware)
+                .build(methods, stop_handle);
+            rpc_service.call(req).await
+        }
+    });
+    let connection = Http::new().serve_connection(stream, service);
+    tokio::pin!(connection);
+    let res = tokio::select! {
+        res = connection.as_mut() => res,
+        _ = shutdown_requested(&mut shutdown_rx) => {
+            // Finishes the requests in flight, then closes the connection
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(err) = res {
+        debug!("RPC connection from {} failed: {:?}", addr, err);
+    }
+}
+
+/// Terminates TLS and hands websocket upgrades to the pubsub server,
+/// returns the stream unless it was handed over or failed
+async fn accept_stream(
+    stream: TcpStream,
+    addr: SocketAddr,
+    rpc_modules: &DirectorRpcModules,
+) -> Option<ClientStream> {
+    let mut stream = match &rpc_modules.tls {
+        Some(tls) => match tls.accept(stream).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                debug!("TLS handshake with {} failed: {:?}", addr, err);
+                return None;
+            }
+        },
+        None => ClientStream::plain(stream),
+    };
+    // Only the first request of a connection can be an upgrade, clients
+    // open a new connection for their websocket anyway
+    if let Some(upgrades) = &rpc_modules.websocket_upgrades {
+        match stream.is_websocket_upgrade().await {
+            Ok(true) => {
+                trace!("Handing websocket upgrade of {} to pubsub", addr);
+                if upgrades.send(WebSocketUpgrade { stream, addr }).is_err() {
+                    debug!("Pubsub server stopped, dropping {}", addr);
+                }
+                return None;
+            }
+            Ok(false) => {}
+            Err(err) => {
+                debug!("Failed to read request of {}: {:?}", addr, err);
+                return None;
+            }
+        }
+    }
+    Some(stream)
+}
+
+fn route_override_from_request(
+    req: &Request<Body>,
+) -> Result<Option<RouteOverride>, String> {
+    let header = match req.headers().get(ROUTE_OVERRIDE_HEADER) {
+        Some(value) => Some(value.to_str().map_err(|err| {
+            format!("Invalid {} header: {:?}", ROUTE_OVERRIDE_HEADER, err)
+        })?),
+        None => None,
+    };
+    RouteOverride::try_from_path_and_header(req.uri().path(), header)
+}
+
+fn api_key_from_request(req: &Request<Body>) -> Option<String> {
+    let header = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    api_key_from_query_and_header(req.uri().query(), header)
+}
+
+/// Serves `/health` which always reports the health of the backends and
+/// `/ready` which fails unless they are all healthy
+fn health_response(
+    req: &Request<Body>,
+    health: &HealthMonitor,
+) -> Option<Response<Body>> {
+    let path = req.uri().path();
+    if req.method() != Method::GET || !matches!(path, "/health" | "/ready") {
+        return None;
+    }
+    let health = health.health();
+    let status = if path == "/ready" && !health.ready {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    Some(json_response(status, &health))
+}
+
+/// Serves `/metrics` in the Prometheus text format
+fn metrics_response(req: &Request<Body>) -> Option<Response<Body>> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        return None;
+    }
+    Some(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, METRICS_CONTENT_TYPE)
+            .body(Body::from(conjunto_metrics::encode()))
+            .expect("response with valid status and header"),
+    )
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_string(body).expect("serializable response body"),
+        ))
+        .expect("response with valid status and header")
+}
+
+fn text_response(status: StatusCode, msg: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(msg))
+        .expect("response with valid status and header")
+}
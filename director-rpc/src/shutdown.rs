@@ -0,0 +1,31 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+// -----------------
+// InFlightRequests
+// -----------------
+/// Counts the requests that are being served
+#[derive(Clone, Default)]
+pub(crate) struct InFlightRequests(Arc<AtomicUsize>);
+
+/// Counts as in flight until dropped
+pub(crate) struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightRequests {
+    pub(crate) fn start(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(self.0.clone())
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -10,6 +10,9 @@ pub enum ServerErrorCode {
     FailedToFetchEndpointInformation = 0,
     TransactionUnroutable = 1,
     RpcClientError = 2,
+    DelegatedAccountChainRead = 3,
+    RateLimitExceeded = 4,
+    Unauthorized = 5,
 }
 
 pub fn server_error(msg: String, code: ServerErrorCode) -> ErrorObjectOwned {
@@ -0,0 +1,142 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use common::MockBackends;
+use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_guidepoint::{auth::ApiKeyAuth, rate_limit::API_KEY_HEADER};
+use jsonrpsee::{
+    core::{client::ClientT, ClientError},
+    http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use serde_json::{json, Value};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+mod common;
+
+const KEYS: &str = r#"
+[[keys]]
+key = "secret"
+
+[[keys]]
+key = "slots"
+methods = ["getSlot"]
+"#;
+
+struct TestSetup {
+    _backends: MockBackends,
+    keys_file: PathBuf,
+    director_url: String,
+}
+
+impl TestSetup {
+    async fn start() -> Self {
+        let backends = MockBackends::start().await;
+        let keys_file = std::env::temp_dir()
+            .join(format!("conjunto-api-keys-{}.toml", Pubkey::new_unique()));
+        fs::write(&keys_file, KEYS).unwrap();
+        let config = DirectorConfig {
+            auth: ApiKeyAuth::from_file(
+                keys_file.clone(),
+                Duration::from_millis(20),
+            )
+            .unwrap(),
+            ..backends.config()
+        };
+        let (director_addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            _backends: backends,
+            keys_file,
+            director_url: format!("http://{}", director_addr),
+        }
+    }
+
+    fn client(&self, path: &str, api_key: Option<&str>) -> HttpClient {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(
+                API_KEY_HEADER,
+                HeaderValue::from_str(api_key).unwrap(),
+            );
+        }
+        HttpClientBuilder::default()
+            .set_headers(headers)
+            .build(format!("{}{}", self.director_url, path))
+            .unwrap()
+    }
+}
+
+fn auth_error(err: ClientError) -> Value {
+    match err {
+        ClientError::Call(err) => {
+            assert_eq!(err.code(), 5);
+            serde_json::from_str(err.data().unwrap().get()).unwrap()
+        }
+        err => panic!("Expected call error, got {:?}", err),
+    }
+}
+
+#[tokio::test]
+async fn test_requests_without_valid_api_key_are_rejected() {
+    let setup = TestSetup::start().await;
+
+    for (client, reason) in [
+        (setup.client("", None), "missingApiKey"),
+        (setup.client("", Some("unknown")), "invalidApiKey"),
+    ] {
+        let err = client
+            .request::<Slot, _>("getSlot", rpc_params![])
+            .await
+            .unwrap_err();
+        assert_eq!(auth_error(err)["reason"], reason);
+    }
+
+    let by_header = setup.client("", Some("secret"));
+    let by_query = setup.client("/?api-key=secret", None);
+    for client in [by_header, by_query] {
+        let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_methods_not_allowed_for_the_api_key_are_rejected() {
+    let setup = TestSetup::start().await;
+    let client = setup.client("", Some("slots"));
+
+    let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    let err = client
+        .request::<Value, _>(
+            "getAccountInfo",
+            rpc_params![Pubkey::new_unique().to_string()],
+        )
+        .await
+        .unwrap_err();
+
+    assert_eq!(auth_error(err), json!({ "reason": "methodNotAllowed" }));
+}
+
+#[tokio::test]
+async fn test_changes_of_the_keys_file_are_picked_up() {
+    let setup = TestSetup::start().await;
+    let client = setup.client("", Some("rotated"));
+
+    fs::write(&setup.keys_file, "[[keys]]\nkey = \"rotated\"").unwrap();
+
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while client
+            .request::<Slot, _>("getSlot", rpc_params![])
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("keys file was not reloaded in time");
+    let err = setup
+        .client("", Some("secret"))
+        .request::<Slot, _>("getSlot", rpc_params![])
+        .await
+        .unwrap_err();
+    assert_eq!(auth_error(err)["reason"], "invalidApiKey");
+}
@@ -0,0 +1,67 @@
+// Each test binary only uses part of the helpers
+#![allow(dead_code)]
+
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
+use conjunto_director_rpc::{
+    audit::AuditConfig,
+    health::HealthConfig,
+    rpc::{DelegatedChainReads, DirectorConfig, SimulationFallback},
+};
+use conjunto_guidepoint::{auth::ApiKeyAuth, rate_limit::RateLimitConfig};
+use conjunto_providers::rpc_provider_config::RpcProviderConfig;
+use conjunto_test_tools::{
+    mock_rpc_server::MockRpcServer, mock_websocket_server::MockWebsocketServer,
+};
+
+/// Mock chain and ephemeral validator backends the director is started
+/// against.
+pub struct MockBackends {
+    pub chain: MockRpcServer,
+    pub ephem: MockRpcServer,
+    pub chain_ws: MockWebsocketServer,
+    pub ephem_ws: MockWebsocketServer,
+}
+
+impl MockBackends {
+    pub async fn start() -> Self {
+        Self {
+            chain: MockRpcServer::start().await,
+            ephem: MockRpcServer::start().await,
+            chain_ws: MockWebsocketServer::start().await,
+            ephem_ws: MockWebsocketServer::start().await,
+        }
+    }
+
+    pub fn chain_cluster(&self) -> RpcCluster {
+        RpcCluster::Custom(self.chain.url(), self.chain_ws.ws_url())
+    }
+
+    pub fn ephem_cluster(&self) -> RpcCluster {
+        RpcCluster::Custom(self.ephem.url(), self.ephem_ws.ws_url())
+    }
+
+    /// Routes between the mock backends with all optional features disabled,
+    /// tests override the fields they exercise via `..backends.config()`
+    pub fn config(&self) -> DirectorConfig {
+        DirectorConfig {
+            chain_cluster: self.chain_cluster(),
+            ephem_rpc_provider_config: RpcProviderConfig::new(
+                self.ephem_cluster(),
+                None,
+            ),
+            simulation_fallback: SimulationFallback::Disabled,
+            ephem_validator_authority: None,
+            validator_registry: ValidatorRegistry::default(),
+            payer_escrow_min_lamports: None,
+            delegated_chain_reads: DelegatedChainReads::Annotate,
+            health: HealthConfig::default(),
+            audit: AuditConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: ApiKeyAuth::default(),
+            tls: None,
+            websocket_upgrades: None,
+        }
+    }
+}
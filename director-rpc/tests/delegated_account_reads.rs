@@ -0,0 +1,242 @@
+use std::mem::size_of;
+
+use common::MockBackends;
+use conjunto_director_rpc::{
+    rpc::{DelegatedChainReads, DirectorConfig},
+    start_rpc_server,
+};
+use conjunto_test_tools::accounts::{
+    account_owned_by_delegation_program, account_owned_by_system_program,
+    delegated_account_ids, DELEGATION_PROGRAM_ID,
+};
+use jsonrpsee::{
+    core::{client::ClientT, ClientError},
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+mod common;
+
+const DELEGATION_SLOT: u64 = 4;
+const COMMIT_FREQUENCY_MS: u64 = 30_000;
+
+struct TestSetup {
+    backends: MockBackends,
+    director_url: String,
+}
+
+impl TestSetup {
+    async fn start(delegated_chain_reads: DelegatedChainReads) -> Self {
+        let backends = MockBackends::start().await;
+        let config = DirectorConfig {
+            delegated_chain_reads,
+            ..backends.config()
+        };
+        let (addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            backends,
+            director_url: format!("http://{}", addr),
+        }
+    }
+
+    fn client(&self, path: &str) -> HttpClient {
+        HttpClientBuilder::default()
+            .build(format!("{}{}", self.director_url, path))
+            .unwrap()
+    }
+
+    /// Transwise resolves the chain state of accounts via the ephemeral
+    /// validator RPC, the account itself is read from chain
+    fn add_delegated_account(&self) -> Pubkey {
+        let (delegated_id, delegation_pda) = delegated_account_ids();
+        self.backends
+            .ephem
+            .add_account(delegated_id, account_owned_by_delegation_program());
+        self.backends
+            .ephem
+            .add_account(delegation_pda, delegation_record_account());
+        self.backends
+            .chain
+            .add_account(delegated_id, account_owned_by_delegation_program());
+        delegated_id
+    }
+
+    fn add_undelegated_account(&self) -> Pubkey {
+        let pubkey = Pubkey::new_unique();
+        self.backends
+            .ephem
+            .add_account(pubkey, account_owned_by_system_program());
+        self.backends
+            .chain
+            .add_account(pubkey, account_owned_by_system_program());
+        pubkey
+    }
+
+    async fn get_account_info(
+        &self,
+        path: &str,
+        pubkey: &Pubkey,
+    ) -> Result<Value, ClientError> {
+        self.client(path)
+            .request(
+                "getAccountInfo",
+                rpc_params![
+                    pubkey.to_string(),
+                    json!({ "encoding": "base64" })
+                ],
+            )
+            .await
+    }
+}
+
+fn delegation_record_account() -> Account {
+    let mut data = [0u8; size_of::<dlp::state::DelegationRecord>() + 8];
+    dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: DELEGATION_SLOT,
+        commit_frequency_ms: COMMIT_FREQUENCY_MS,
+        lamports: 500,
+    }
+    .to_bytes_with_discriminator(&mut data)
+    .unwrap();
+    Account {
+        owner: DELEGATION_PROGRAM_ID,
+        data: data.to_vec(),
+        ..Account::default()
+    }
+}
+
+fn error_code(err: ClientError) -> (i32, Option<Value>) {
+    match err {
+        ClientError::Call(err) => (
+            err.code(),
+            err.data()
+                .map(|data| serde_json::from_str(data.get()).unwrap()),
+        ),
+        err => panic!("Expected call error, got {:?}", err),
+    }
+}
+
+// -----------------
+// Annotate
+// -----------------
+#[tokio::test]
+async fn test_chain_read_of_delegated_account_is_annotated() {
+    let setup = TestSetup::start(DelegatedChainReads::Annotate).await;
+    let delegated_id = setup.add_delegated_account();
+    setup.backends.chain.set_slot(DELEGATION_SLOT + 10);
+
+    let res = setup.get_account_info("", &delegated_id).await.unwrap();
+    assert_eq!(res["context"]["slot"], json!(DELEGATION_SLOT + 10));
+    assert_eq!(
+        res["context"]["delegated"],
+        json!([{
+            "pubkey": delegated_id.to_string(),
+            "delegationSlot": DELEGATION_SLOT,
+            "commitFrequency": "30000ms",
+            "estimatedStalenessMs": 4_000,
+        }])
+    );
+    assert_eq!(
+        res["value"]["owner"],
+        json!(DELEGATION_PROGRAM_ID.to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_estimated_staleness_is_capped_by_commit_frequency() {
+    let setup = TestSetup::start(DelegatedChainReads::Annotate).await;
+    let delegated_id = setup.add_delegated_account();
+    setup.backends.chain.set_slot(DELEGATION_SLOT + 1_000);
+
+    let res = setup.get_account_info("", &delegated_id).await.unwrap();
+    assert_eq!(
+        res["context"]["delegated"][0]["estimatedStalenessMs"],
+        json!(COMMIT_FREQUENCY_MS)
+    );
+}
+
+#[tokio::test]
+async fn test_chain_read_of_undelegated_account_is_not_annotated() {
+    let setup = TestSetup::start(DelegatedChainReads::Annotate).await;
+    let pubkey = setup.add_undelegated_account();
+
+    let res = setup.get_account_info("", &pubkey).await.unwrap();
+    assert_eq!(res["context"], json!({ "slot": 0 }));
+}
+
+#[tokio::test]
+async fn test_get_multiple_accounts_annotates_delegated_accounts_only() {
+    let setup = TestSetup::start(DelegatedChainReads::Annotate).await;
+    let delegated_id = setup.add_delegated_account();
+    let undelegated_id = setup.add_undelegated_account();
+
+    let res: Value = setup
+        .client("")
+        .request(
+            "getMultipleAccounts",
+            rpc_params![
+                vec![undelegated_id.to_string(), delegated_id.to_string()],
+                json!({ "encoding": "base64" })
+            ],
+        )
+        .await
+        .unwrap();
+    let delegated = res["context"]["delegated"].as_array().unwrap();
+    assert_eq!(delegated.len(), 1);
+    assert_eq!(delegated[0]["pubkey"], json!(delegated_id.to_string()));
+    assert_eq!(res["value"].as_array().unwrap().len(), 2);
+}
+
+// -----------------
+// Refuse
+// -----------------
+#[tokio::test]
+async fn test_chain_read_of_delegated_account_is_refused() {
+    let setup = TestSetup::start(DelegatedChainReads::Refuse).await;
+    let delegated_id = setup.add_delegated_account();
+
+    let err = setup.get_account_info("", &delegated_id).await.unwrap_err();
+    let (code, data) = error_code(err);
+    assert_eq!(code, 3);
+    assert_eq!(
+        data.unwrap()["delegated"][0]["pubkey"],
+        json!(delegated_id.to_string())
+    );
+    assert!(!setup
+        .chain
+        .requested_methods()
+        .contains(&"getAccountInfo".to_string()));
+}
+
+#[tokio::test]
+async fn test_chain_read_of_undelegated_account_is_served_when_refusing() {
+    let setup = TestSetup::start(DelegatedChainReads::Refuse).await;
+    let pubkey = setup.add_undelegated_account();
+
+    let res = setup.get_account_info("", &pubkey).await.unwrap();
+    assert_eq!(
+        res["value"]["owner"],
+        json!(solana_sdk::system_program::id().to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_ephemeral_read_of_delegated_account_is_not_refused() {
+    let setup = TestSetup::start(DelegatedChainReads::Refuse).await;
+    let delegated_id = setup.add_delegated_account();
+
+    let res = setup
+        .get_account_info("/ephemeral", &delegated_id)
+        .await
+        .unwrap();
+    assert_eq!(res["context"], json!({ "slot": 0 }));
+    assert!(!setup
+        .chain
+        .requested_methods()
+        .contains(&"getAccountInfo".to_string()));
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use common::MockBackends;
+use conjunto_addresses::cluster::RpcCluster;
+use conjunto_director_rpc::{
+    health::HealthConfig, rpc::DirectorConfig, start_rpc_server,
+};
+use conjunto_providers::rpc_provider_config::RpcProviderConfig;
+use jsonrpsee::{
+    core::client::ClientT, http_client::HttpClientBuilder, rpc_params,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct TestSetup {
+    backends: MockBackends,
+    director_addr: String,
+}
+
+impl TestSetup {
+    async fn start() -> Self {
+        Self::start_with_ephem_ws_url(None).await
+    }
+
+    /// Starts the director against mock backends, if provided the ephemeral
+    /// validator websocket is expected at the given URL instead of the mock
+    async fn start_with_ephem_ws_url(ephem_ws_url: Option<&str>) -> Self {
+        let backends = MockBackends::start().await;
+        let ephem_ws_url = ephem_ws_url
+            .map(ToString::to_string)
+            .unwrap_or_else(|| backends.ephem_ws.ws_url());
+        let config = DirectorConfig {
+            ephem_rpc_provider_config: RpcProviderConfig::new(
+                RpcCluster::Custom(backends.ephem.url(), ephem_ws_url),
+                None,
+            ),
+            health: HealthConfig {
+                probe_interval: Duration::from_millis(20),
+                probe_timeout: Duration::from_millis(500),
+                max_slot_lag: 100,
+            },
+            ..backends.config()
+        };
+        let (director_addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            backends,
+            director_addr,
+        }
+    }
+
+    async fn get(&self, path: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(&self.director_addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    path
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .expect("response with status");
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    /// Polls `/ready` until the reported health matches
+    async fn wait_for_health(
+        &self,
+        matches: impl Fn(&Value) -> bool,
+    ) -> (u16, Value) {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let (status, health) = self.get("/ready").await;
+                if matches(&health) {
+                    return (status, health);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("health did not match in time")
+    }
+
+    /// Waits until both backends were probed at least once
+    async fn probed_health(&self) -> (u16, Value) {
+        self.wait_for_health(|health| {
+            !health["chain"]["probedAtMs"].is_null()
+                && !health["ephemeral"]["probedAtMs"].is_null()
+        })
+        .await
+    }
+}
+
+#[tokio::test]
+async fn test_ready_when_both_backends_are_healthy() {
+    let setup = TestSetup::start().await;
+
+    let (status, _) = setup.probed_health().await;
+    assert_eq!(status, 200);
+
+    let (status, health) = setup.get("/health").await;
+    assert_eq!(status, 200);
+    assert_eq!(health["ready"], json!(true));
+    assert_eq!(health["chain"]["url"], json!(setup.backends.chain.url()));
+    assert_eq!(health["chain"]["http"]["ok"], json!(true));
+    assert_eq!(health["chain"]["ws"]["ok"], json!(true));
+    assert_eq!(health["chain"]["slotLag"], json!(0));
+    assert!(health["chain"]["http"]["latencyMs"].is_u64());
+    assert_eq!(
+        health["ephemeral"]["url"],
+        json!(setup.backends.ephem.url())
+    );
+}
+
+#[tokio::test]
+async fn test_not_ready_when_ephemeral_websocket_is_unreachable() {
+    let setup =
+        TestSetup::start_with_ephem_ws_url(Some("ws://127.0.0.1:0")).await;
+
+    let (status, health) = setup.probed_health().await;
+    assert_eq!(status, 503);
+    assert_eq!(health["ready"], json!(false));
+    assert_eq!(health["ephemeral"]["http"]["ok"], json!(true));
+    assert_eq!(health["ephemeral"]["ws"]["ok"], json!(false));
+    assert!(health["ephemeral"]["ws"]["error"].is_string());
+
+    // The health itself is served regardless
+    let (status, _) = setup.get("/health").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test]
+async fn test_not_ready_when_chain_lags_behind() {
+    let setup = TestSetup::start().await;
+    setup.backends.chain.set_slots_behind(500);
+
+    let (status, health) = setup
+        .wait_for_health(|health| health["chain"]["slotLag"] == json!(500))
+        .await;
+    assert_eq!(status, 503);
+    assert_eq!(health["chain"]["http"]["ok"], json!(true));
+    assert_eq!(health["ephemeral"]["slotLag"], json!(0));
+}
+
+#[tokio::test]
+async fn test_get_director_health() {
+    let setup = TestSetup::start().await;
+    setup.backends.chain.set_slot(42);
+    setup
+        .wait_for_health(|health| health["chain"]["slot"] == json!(42))
+        .await;
+
+    let client = HttpClientBuilder::default()
+        .build(format!("http://{}", setup.director_addr))
+        .unwrap();
+    let health: Value = client
+        .request("getDirectorHealth", rpc_params![])
+        .await
+        .unwrap();
+    assert_eq!(health["ready"], json!(true));
+    assert_eq!(health["chain"]["slot"], json!(42));
+    assert!(!setup
+        .backends
+        .chain
+        .requested_methods()
+        .contains(&"getDirectorHealth".to_string()));
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use common::MockBackends;
+use conjunto_director_rpc::{
+    health::HealthConfig, rpc::DirectorConfig, start_rpc_server,
+};
+use jsonrpsee::{
+    core::client::ClientT, http_client::HttpClientBuilder, rpc_params,
+};
+use solana_sdk::clock::Slot;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+mod common;
+
+async fn get_metrics(director_addr: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(director_addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_string(), body.to_string())
+}
+
+#[tokio::test]
+async fn test_metrics_count_requests_and_upstream_latency() {
+    let backends = MockBackends::start().await;
+    let config = DirectorConfig {
+        health: HealthConfig {
+            probe_interval: Duration::from_secs(60),
+            ..Default::default()
+        },
+        ..backends.config()
+    };
+    let (director_addr, _) =
+        start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+
+    let client = HttpClientBuilder::default()
+        .build(format!("http://{}", director_addr))
+        .unwrap();
+    let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    let _ = client
+        .request::<Slot, _>("notAnRpcMethod", rpc_params![])
+        .await
+        .unwrap_err();
+
+    let (head, metrics) = get_metrics(&director_addr).await;
+    assert!(head.starts_with("HTTP/1.1 200"));
+    assert!(head.contains("text/plain; version=0.0.4"));
+    assert!(metrics.contains(
+        r#"conjunto_rpc_requests_total{method="getSlot",result="ok",route="guided"}"#
+    ));
+    assert!(metrics.contains(
+        r#"conjunto_rpc_requests_total{method="unknown",result="error",route="guided"}"#
+    ));
+    assert!(!metrics.contains("notAnRpcMethod"));
+    assert!(metrics.contains(
+        r#"conjunto_upstream_request_duration_seconds_count{backend="chain",method="getSlot"}"#
+    ));
+}
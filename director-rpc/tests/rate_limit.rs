@@ -0,0 +1,191 @@
+use std::{fs, time::Duration};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use common::MockBackends;
+use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_guidepoint::{
+    auth::ApiKeyAuth,
+    rate_limit::{Quota, RateLimitConfig, RateLimitKey, API_KEY_HEADER},
+};
+use jsonrpsee::{
+    core::{client::ClientT, ClientError},
+    http_client::{HeaderMap, HeaderValue, HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use serde_json::{json, Value};
+use solana_sdk::{
+    clock::Slot,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+
+mod common;
+
+struct TestSetup {
+    _backends: MockBackends,
+    director_url: String,
+}
+
+impl TestSetup {
+    async fn start() -> Self {
+        Self::start_with_auth(ApiKeyAuth::default()).await
+    }
+
+    /// Starts the director keying clients by API key, which only applies to
+    /// keys `auth` knows
+    async fn start_with_auth(auth: ApiKeyAuth) -> Self {
+        let backends = MockBackends::start().await;
+        let config = DirectorConfig {
+            // Refills slow enough to not interfere with the tests
+            rate_limit: RateLimitConfig {
+                key: RateLimitKey::ApiKey,
+                requests: Some(Quota {
+                    per_second: 1,
+                    burst: 2,
+                }),
+                send_transactions: Some(Quota {
+                    per_second: 1,
+                    burst: 1,
+                }),
+                ..Default::default()
+            },
+            auth,
+            ..backends.config()
+        };
+        let (director_addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            _backends: backends,
+            director_url: format!("http://{}", director_addr),
+        }
+    }
+
+    fn client(&self, path: &str, api_key: Option<&str>) -> HttpClient {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(
+                API_KEY_HEADER,
+                HeaderValue::from_str(api_key).unwrap(),
+            );
+        }
+        HttpClientBuilder::default()
+            .set_headers(headers)
+            .build(format!("{}{}", self.director_url, path))
+            .unwrap()
+    }
+}
+
+fn send_transaction_params() -> (String, Value) {
+    let payer = Keypair::new();
+    let ix = Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[],
+        vec![AccountMeta::new(Pubkey::new_unique(), false)],
+    );
+    let tx = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+    let data = BASE64_STANDARD
+        .encode(bincode::serialize(&VersionedTransaction::from(tx)).unwrap());
+    (data, json!({ "encoding": "base64" }))
+}
+
+fn rate_limit_error(err: ClientError) -> Value {
+    match err {
+        ClientError::Call(err) => {
+            assert_eq!(err.code(), 4);
+            serde_json::from_str(err.data().unwrap().get()).unwrap()
+        }
+        err => panic!("Expected call error, got {:?}", err),
+    }
+}
+
+#[tokio::test]
+async fn test_requests_exceeding_the_budget_are_rejected() {
+    let setup = TestSetup::start().await;
+    let client = setup.client("", None);
+
+    for _ in 0..2 {
+        let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    }
+    let err = client
+        .request::<Slot, _>("getSlot", rpc_params![])
+        .await
+        .unwrap_err();
+
+    let data = rate_limit_error(err);
+    assert_eq!(data["limit"], "requests");
+    assert!(data["retryAfterMs"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_send_transaction_has_its_own_budget() {
+    let setup = TestSetup::start().await;
+    let client = setup.client("/chain", None);
+    for _ in 0..2 {
+        let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    }
+
+    let (data, config) = send_transaction_params();
+    let _: String = client
+        .request("sendTransaction", rpc_params![data, config])
+        .await
+        .unwrap();
+    let (data, config) = send_transaction_params();
+    let err = client
+        .request::<String, _>("sendTransaction", rpc_params![data, config])
+        .await
+        .unwrap_err();
+
+    assert_eq!(rate_limit_error(err)["limit"], "sendTransactions");
+}
+
+#[tokio::test]
+async fn test_clients_with_api_keys_have_their_own_budget() {
+    let keys_file = std::env::temp_dir()
+        .join(format!("conjunto-api-keys-{}.toml", Pubkey::new_unique()));
+    fs::write(
+        &keys_file,
+        "[[keys]]\nkey = \"first-key\"\n\n[[keys]]\nkey = \"second-key\"",
+    )
+    .unwrap();
+    let auth =
+        ApiKeyAuth::from_file(&keys_file, Duration::from_secs(1)).unwrap();
+    let setup = TestSetup::start_with_auth(auth).await;
+
+    let by_header = setup.client("", Some("first-key"));
+    let by_query = setup.client("/?api-key=second-key", None);
+    for client in [&by_header, &by_query] {
+        for _ in 0..2 {
+            let _: Slot =
+                client.request("getSlot", rpc_params![]).await.unwrap();
+        }
+    }
+
+    for client in [&by_header, &by_query] {
+        let err = client
+            .request::<Slot, _>("getSlot", rpc_params![])
+            .await
+            .unwrap_err();
+        assert_eq!(rate_limit_error(err)["limit"], "requests");
+    }
+    fs::remove_file(&keys_file).unwrap();
+}
+
+#[tokio::test]
+async fn test_unauthenticated_api_keys_share_the_budget_of_their_ip() {
+    let setup = TestSetup::start().await;
+    let first = setup.client("", Some("first-key"));
+    let second = setup.client("", Some("second-key"));
+    for client in [&first, &second] {
+        let _: Slot = client.request("getSlot", rpc_params![]).await.unwrap();
+    }
+
+    let err = setup
+        .client("", Some("third-key"))
+        .request::<Slot, _>("getSlot", rpc_params![])
+        .await
+        .unwrap_err();
+
+    assert_eq!(rate_limit_error(err)["limit"], "requests");
+}
@@ -0,0 +1,780 @@
+use std::{fs, path::Path, time::Duration};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use common::MockBackends;
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
+use conjunto_director_rpc::{
+    audit::{AuditConfig, AuditSink, AuditedSimulation, RoutingRecord},
+    rpc::{DirectorConfig, SimulationFallback},
+    start_rpc_server,
+};
+use conjunto_lockbox::account_chain_snapshot::AccountChainSnapshot;
+use conjunto_test_tools::{
+    accounts::{
+        account_owned_by_delegation_program, account_with_data,
+        delegated_account_ids, DELEGATION_PROGRAM_ID,
+    },
+    mock_rpc_server::MockRpcServer,
+};
+use jsonrpsee::{
+    core::{client::ClientT, ClientError},
+    http_client::{HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use serde_json::{json, Value};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+
+mod common;
+
+struct TestSetup {
+    backends: MockBackends,
+    director_url: String,
+}
+
+impl TestSetup {
+    async fn start() -> Self {
+        Self::start_with_simulation_fallback(SimulationFallback::Disabled).await
+    }
+
+    async fn start_with_simulation_fallback(
+        simulation_fallback: SimulationFallback,
+    ) -> Self {
+        Self::start_with_config(|config| {
+            config.simulation_fallback = simulation_fallback
+        })
+        .await
+    }
+
+    async fn start_with_config(
+        configure: impl FnOnce(&mut DirectorConfig),
+    ) -> Self {
+        let backends = MockBackends::start().await;
+        backends
+            .chain
+            .set_send_transaction_result(Ok(chain_signature()));
+        backends
+            .ephem
+            .set_send_transaction_result(Ok(ephem_signature()));
+
+        let mut config = backends.config();
+        configure(&mut config);
+        let (addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            backends,
+            director_url: format!("http://{}", addr),
+        }
+    }
+
+    /// Client for the director whose requests go to the given URL path
+    fn client(&self, path: &str) -> HttpClient {
+        HttpClientBuilder::default()
+            .build(format!("{}{}", self.director_url, path))
+            .unwrap()
+    }
+
+    /// Transwise resolves the chain state of accounts via the ephemeral
+    /// validator RPC
+    fn add_account(&self, pubkey: Pubkey, account: Account) {
+        self.backends.ephem.add_account(pubkey, account);
+    }
+
+    fn add_delegated_account(&self) -> Pubkey {
+        self.add_delegated_account_to(Pubkey::new_unique())
+    }
+
+    /// Adds an account delegated to the validator with the given authority
+    fn add_delegated_account_to(&self, authority: Pubkey) -> Pubkey {
+        let (delegated_id, delegation_pda) = delegated_account_ids();
+        self.add_account(delegated_id, account_owned_by_delegation_program());
+        self.add_account(delegation_pda, delegation_record_account(authority));
+        delegated_id
+    }
+
+    async fn send_transaction(
+        &self,
+        tx: Transaction,
+    ) -> Result<String, ClientError> {
+        self.send_transaction_via_path("", tx).await
+    }
+
+    async fn send_transaction_via_path(
+        &self,
+        path: &str,
+        tx: Transaction,
+    ) -> Result<String, ClientError> {
+        self.send_transaction_with_config(path, tx, json!({})).await
+    }
+
+    async fn send_transaction_with_config(
+        &self,
+        path: &str,
+        tx: Transaction,
+        mut config: Value,
+    ) -> Result<String, ClientError> {
+        let tx = VersionedTransaction::from(tx);
+        let data = BASE64_STANDARD.encode(bincode::serialize(&tx).unwrap());
+        config["encoding"] = json!("base64");
+        self.client(path)
+            .request("sendTransaction", rpc_params![data, config])
+            .await
+    }
+}
+
+fn chain_signature() -> Signature {
+    Signature::from([1; 64])
+}
+
+fn ephem_signature() -> Signature {
+    Signature::from([2; 64])
+}
+
+fn delegation_record_account(authority: Pubkey) -> Account {
+    let mut data = [0u8; size_of::<dlp::state::DelegationRecord>() + 8];
+    dlp::state::DelegationRecord {
+        authority,
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    }
+    .to_bytes_with_discriminator(&mut data)
+    .unwrap();
+    Account {
+        owner: DELEGATION_PROGRAM_ID,
+        data: data.to_vec(),
+        ..Account::default()
+    }
+}
+
+fn transaction_writing(payer: &Keypair, writable: &[Pubkey]) -> Transaction {
+    let ix = Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[],
+        writable
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false))
+            .collect(),
+    );
+    Transaction::new_with_payer(&[ix], Some(&payer.pubkey()))
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_undelegated_goes_to_chain() {
+    let setup = TestSetup::start().await;
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+
+    let tx = transaction_writing(&Keypair::new(), &[undelegated_id]);
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, chain_signature().to_string());
+    assert_eq!(setup.backends.chain.sent_transactions().len(), 1);
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_delegated_goes_to_ephemeral() {
+    let setup = TestSetup::start().await;
+    let delegated_id = setup.add_delegated_account();
+
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, ephem_signature().to_string());
+    assert_eq!(setup.backends.ephem.sent_transactions().len(), 1);
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_delegated_and_undelegated_is_unroutable()
+{
+    let setup = TestSetup::start().await;
+    let delegated_id = setup.add_delegated_account();
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+
+    let tx =
+        transaction_writing(&Keypair::new(), &[delegated_id, undelegated_id]);
+    let err = setup.send_transaction(tx).await.unwrap_err();
+
+    match err {
+        ClientError::Call(err) => {
+            assert_eq!(err.code(), 1);
+            assert!(err.message().starts_with("Transaction is unroutable"));
+            let data: Value =
+                serde_json::from_str(err.data().unwrap().get()).unwrap();
+            assert!(data.get("Unroutable").is_some());
+            assert_eq!(data["code"], "WRITABLE_UNDELEGATED_AND_DELEGATED");
+            assert_eq!(
+                data["pubkeys"],
+                json!([undelegated_id.to_string(), delegated_id.to_string()])
+            );
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_delegated_to_other_validator_is_unroutable(
+) {
+    let setup = TestSetup::start_with_config(|config| {
+        config.simulation_fallback = SimulationFallback::EphemeralThenChain;
+        config.ephem_validator_authority = Some(Pubkey::new_unique());
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account();
+
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let err = setup.send_transaction(tx).await.unwrap_err();
+
+    match err {
+        ClientError::Call(err) => {
+            assert_eq!(err.code(), 1);
+            let data: Value =
+                serde_json::from_str(err.data().unwrap().get()).unwrap();
+            assert_eq!(data["code"], "DELEGATED_TO_DIFFERENT_VALIDATOR");
+            assert_eq!(data["pubkeys"], json!([delegated_id.to_string()]));
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    // Simulating cannot help with this reason
+    assert!(!setup
+        .backends
+        .ephem
+        .requested_methods()
+        .contains(&"simulateTransaction".to_string()));
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_with_payer_escrow_check() {
+    let setup = TestSetup::start_with_config(|config| {
+        config.payer_escrow_min_lamports = Some(1_000);
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account();
+    let payer = Keypair::new();
+    let escrow_id =
+        AccountChainSnapshot::ephemeral_balance_pda(&payer.pubkey());
+
+    let tx = transaction_writing(&payer, &[delegated_id]);
+    let err = setup.send_transaction(tx.clone()).await.unwrap_err();
+    match err {
+        ClientError::Call(err) => {
+            let data: Value =
+                serde_json::from_str(err.data().unwrap().get()).unwrap();
+            assert_eq!(data["code"], "PAYER_NOT_ESCROWED");
+            assert_eq!(data["pubkeys"], json!([payer.pubkey().to_string()]));
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+
+    setup.add_account(
+        escrow_id,
+        Account {
+            lamports: 1_000,
+            ..account_owned_by_delegation_program()
+        },
+    );
+    let signature = setup.send_transaction(tx).await.unwrap();
+    assert_eq!(signature, ephem_signature().to_string());
+}
+
+#[tokio::test]
+async fn test_send_transaction_goes_to_registered_validator() {
+    let registered = MockRpcServer::start().await;
+    registered.set_send_transaction_result(Ok(Signature::from([3; 64])));
+    let registered_authority = Pubkey::new_unique();
+    let registered_url = registered.url();
+    let setup = TestSetup::start_with_config(|config| {
+        config.validator_registry = ValidatorRegistry::default()
+            .with_validator(
+                registered_authority,
+                RpcCluster::Custom(
+                    registered_url,
+                    "ws://127.0.0.1:0".to_string(),
+                ),
+            );
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account_to(registered_authority);
+
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, Signature::from([3; 64]).to_string());
+    assert_eq!(registered.sent_transactions().len(), 1);
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_default_validator_goes_to_default() {
+    let default_authority = Pubkey::new_unique();
+    let setup = TestSetup::start_with_config(|config| {
+        config.ephem_validator_authority = Some(default_authority);
+        config.validator_registry = ValidatorRegistry::default()
+            .with_validator(Pubkey::new_unique(), RpcCluster::Development);
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account_to(default_authority);
+
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, ephem_signature().to_string());
+    assert_eq!(setup.backends.ephem.sent_transactions().len(), 1);
+}
+
+#[tokio::test]
+async fn test_send_transaction_writing_unregistered_validator_is_unroutable() {
+    // The registered validators constrain the routing even without a default
+    // authority
+    let setup = TestSetup::start_with_config(|config| {
+        config.validator_registry = ValidatorRegistry::default()
+            .with_validator(Pubkey::new_unique(), RpcCluster::Development);
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account();
+
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let err = setup.send_transaction(tx).await.unwrap_err();
+
+    match err {
+        ClientError::Call(err) => {
+            let data: Value =
+                serde_json::from_str(err.data().unwrap().get()).unwrap();
+            assert_eq!(data["code"], "DELEGATED_TO_DIFFERENT_VALIDATOR");
+            assert_eq!(data["pubkeys"], json!([delegated_id.to_string()]));
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_send_transaction_backend_failure_is_forwarded() {
+    let setup = TestSetup::start().await;
+    setup
+        .backends
+        .chain
+        .set_send_transaction_result(Err("Blockhash not found".to_string()));
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+
+    let tx = transaction_writing(&Keypair::new(), &[undelegated_id]);
+    let err = setup.send_transaction(tx).await.unwrap_err();
+
+    match err {
+        ClientError::Call(err) => {
+            assert!(err.message().contains("Blockhash not found"));
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert_eq!(setup.backends.chain.sent_transactions().len(), 1);
+}
+
+#[tokio::test]
+async fn test_send_transaction_route_override_skips_guiding() {
+    let setup = TestSetup::start().await;
+    let delegated_id = setup.add_delegated_account();
+
+    // Guiding would pick ephemeral, but the client picked chain
+    let tx = transaction_writing(&Keypair::new(), &[delegated_id]);
+    let signature =
+        setup.send_transaction_via_path("/chain", tx).await.unwrap();
+
+    assert_eq!(signature, chain_signature().to_string());
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+    assert!(!setup
+        .backends
+        .ephem
+        .requested_methods()
+        .contains(&"getMultipleAccounts".to_string()));
+}
+
+#[tokio::test]
+async fn test_send_transaction_honors_min_context_slot() {
+    let setup = TestSetup::start().await;
+    setup.backends.ephem.set_slot(10);
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+
+    // The account state is not as fresh as the client expects
+    let tx = transaction_writing(&Keypair::new(), &[undelegated_id]);
+    let err = setup
+        .send_transaction_with_config("", tx, json!({ "minContextSlot": 20 }))
+        .await
+        .unwrap_err();
+    match err {
+        // FailedToFetchEndpointInformation
+        ClientError::Call(err) => assert_eq!(err.code(), 0),
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+
+    // The account state is fresh enough
+    let tx = transaction_writing(&Keypair::new(), &[undelegated_id]);
+    let signature = setup
+        .send_transaction_with_config("", tx, json!({ "minContextSlot": 5 }))
+        .await
+        .unwrap();
+    assert_eq!(signature, chain_signature().to_string());
+}
+
+// -----------------
+// Audit Log
+// -----------------
+/// Waits until the audit log writer wrote the expected number of records
+async fn read_audit_records(path: &Path, count: usize) -> Vec<RoutingRecord> {
+    tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let records = fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<_>>();
+            if records.len() >= count {
+                return records;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("audit records were not written in time")
+}
+
+#[tokio::test]
+async fn test_send_transaction_routing_is_audited() {
+    let path = std::env::temp_dir()
+        .join(format!("conjunto-audit-{}.jsonl", Pubkey::new_unique()));
+    let sink = AuditSink::File(path.clone());
+    let setup = TestSetup::start_with_config(|config| {
+        config.audit = AuditConfig {
+            sink: Some(sink),
+            sample_every: 2,
+            redact_account_data: true,
+        }
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account();
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+
+    let payer = Keypair::new();
+    let tx = transaction_writing(&payer, &[delegated_id]);
+    let signature = tx.signatures[0].to_string();
+    setup.send_transaction(tx).await.unwrap();
+    // Not sampled
+    let tx = transaction_writing(&payer, &[undelegated_id]);
+    setup.send_transaction(tx).await.unwrap();
+    let tx = transaction_writing(&payer, &[delegated_id, undelegated_id]);
+    setup.send_transaction(tx).await.unwrap_err();
+
+    let records = read_audit_records(&path, 2).await;
+    fs::remove_file(&path).unwrap();
+    assert_eq!(records.len(), 2);
+
+    let routed = &records[0];
+    assert_eq!(routed.method, "sendTransaction");
+    assert_eq!(routed.signature, Some(signature));
+    assert_eq!(routed.endpoint, Some("ephemeral".to_string()));
+    assert_eq!(routed.error, None);
+    let delegated = routed
+        .accounts
+        .iter()
+        .find(|acc| acc.pubkey == delegated_id.to_string())
+        .unwrap();
+    assert!(delegated.writable);
+    assert!(delegated.chain_state.is_delegated());
+    assert!(routed
+        .accounts
+        .iter()
+        .any(|acc| acc.pubkey == payer.pubkey().to_string()));
+
+    let unroutable = &records[1];
+    assert_eq!(unroutable.endpoint, Some("unroutable".to_string()));
+    assert!(unroutable.unroutable_reason.is_some());
+    assert!(unroutable.error.is_some());
+    assert_eq!(unroutable.accounts.len(), routed.accounts.len() + 1);
+    let undelegated = unroutable
+        .accounts
+        .iter()
+        .find(|acc| acc.pubkey == undelegated_id.to_string())
+        .unwrap();
+    // Only the length of the account data is recorded
+    assert_eq!(undelegated.data_len, account_with_data().data.len());
+    assert!(undelegated.chain_state.account().unwrap().data.is_empty());
+}
+
+// -----------------
+// Simulation Fallback
+// -----------------
+async fn setup_unroutable(
+    simulation_fallback: SimulationFallback,
+) -> (TestSetup, Pubkey, Pubkey, Transaction) {
+    let setup =
+        TestSetup::start_with_simulation_fallback(simulation_fallback).await;
+    let delegated_id = setup.add_delegated_account();
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+    let tx =
+        transaction_writing(&Keypair::new(), &[delegated_id, undelegated_id]);
+    (setup, delegated_id, undelegated_id, tx)
+}
+
+fn written(account: Account) -> Account {
+    Account {
+        lamports: account.lamports + 1,
+        ..account
+    }
+}
+
+#[tokio::test]
+async fn test_simulation_writing_only_delegated_goes_to_ephemeral() {
+    let (setup, delegated_id, _, tx) =
+        setup_unroutable(SimulationFallback::Ephemeral).await;
+    setup.backends.ephem.add_simulated_account(
+        delegated_id,
+        written(account_owned_by_delegation_program()),
+    );
+
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, ephem_signature().to_string());
+    assert_eq!(setup.backends.ephem.sent_transactions().len(), 1);
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_simulation_writing_only_undelegated_goes_to_chain() {
+    let (setup, _, undelegated_id, tx) =
+        setup_unroutable(SimulationFallback::Ephemeral).await;
+    setup
+        .backends
+        .ephem
+        .add_simulated_account(undelegated_id, written(account_with_data()));
+
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, chain_signature().to_string());
+    assert_eq!(setup.backends.chain.sent_transactions().len(), 1);
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_simulation_writing_both_is_unroutable_with_diagnostic() {
+    let (setup, delegated_id, undelegated_id, tx) =
+        setup_unroutable(SimulationFallback::Ephemeral).await;
+    setup.backends.ephem.add_simulated_account(
+        delegated_id,
+        written(account_owned_by_delegation_program()),
+    );
+    setup
+        .backends
+        .ephem
+        .add_simulated_account(undelegated_id, written(account_with_data()));
+
+    let err = setup.send_transaction(tx).await.unwrap_err();
+
+    match err {
+        ClientError::Call(err) => {
+            assert_eq!(err.message(), "Transaction is unroutable");
+            let data: Value =
+                serde_json::from_str(err.data().unwrap().get()).unwrap();
+            assert!(data["endpoint"].get("Unroutable").is_some());
+            assert_eq!(
+                data["simulations"],
+                json!([{
+                    "backend": "ephemeral",
+                    "err": null,
+                    "logs": [],
+                    "writtenUndelegatedPubkeys": [undelegated_id.to_string()],
+                    "writtenDelegatedPubkeys": [delegated_id.to_string()],
+                }])
+            );
+        }
+        err => panic!("Unexpected error: {:?}", err),
+    }
+    assert!(setup.backends.chain.sent_transactions().is_empty());
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_simulation_failure_falls_back_to_chain_simulation() {
+    let (setup, delegated_id, undelegated_id, tx) =
+        setup_unroutable(SimulationFallback::EphemeralThenChain).await;
+    setup
+        .backends
+        .ephem
+        .set_simulation_err(Some(json!("AccountNotFound")));
+    setup
+        .backends
+        .chain
+        .add_account(delegated_id, account_owned_by_delegation_program());
+    setup
+        .backends
+        .chain
+        .add_account(undelegated_id, account_with_data());
+    setup
+        .backends
+        .chain
+        .add_simulated_account(undelegated_id, written(account_with_data()));
+
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, chain_signature().to_string());
+    assert!(setup
+        .backends
+        .ephem
+        .requested_methods()
+        .contains(&"simulateTransaction".to_string()));
+    assert!(setup
+        .backends
+        .chain
+        .requested_methods()
+        .contains(&"simulateTransaction".to_string()));
+}
+
+#[tokio::test]
+async fn test_route_found_by_simulation_is_audited() {
+    let path = std::env::temp_dir()
+        .join(format!("conjunto-audit-{}.jsonl", Pubkey::new_unique()));
+    let sink = AuditSink::File(path.clone());
+    let setup = TestSetup::start_with_config(|config| {
+        config.simulation_fallback = SimulationFallback::EphemeralThenChain;
+        config.audit = AuditConfig {
+            sink: Some(sink),
+            ..AuditConfig::default()
+        };
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account();
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+    setup
+        .backends
+        .ephem
+        .set_simulation_err(Some(json!("AccountNotFound")));
+    setup
+        .backends
+        .chain
+        .add_account(delegated_id, account_owned_by_delegation_program());
+    setup
+        .backends
+        .chain
+        .add_account(undelegated_id, account_with_data());
+    setup
+        .backends
+        .chain
+        .add_simulated_account(undelegated_id, written(account_with_data()));
+
+    let tx =
+        transaction_writing(&Keypair::new(), &[delegated_id, undelegated_id]);
+    setup.send_transaction(tx).await.unwrap();
+
+    let records = read_audit_records(&path, 1).await;
+    fs::remove_file(&path).unwrap();
+    let record = &records[0];
+    assert_eq!(record.endpoint, Some("chain".to_string()));
+    assert!(record.unroutable_reason.is_some());
+    assert_eq!(record.error, None);
+    assert_eq!(record.simulations.len(), 2);
+    assert_eq!(record.simulations[0].backend, "ephemeral");
+    assert!(record.simulations[0].err.is_some());
+    assert_eq!(
+        record.simulations[1],
+        AuditedSimulation {
+            backend: "chain".to_string(),
+            err: None,
+            written_undelegated_pubkeys: vec![undelegated_id.to_string()],
+            written_delegated_pubkeys: vec![],
+        }
+    );
+}
+
+/// Like [setup_unroutable] with the delegated account delegated to a
+/// registered validator which holds the same accounts as chain
+async fn setup_unroutable_on_registered_validator(
+) -> (TestSetup, MockRpcServer, Pubkey, Pubkey, Transaction) {
+    let registered = MockRpcServer::start().await;
+    registered.set_send_transaction_result(Ok(Signature::from([3; 64])));
+    let registered_authority = Pubkey::new_unique();
+    let registered_url = registered.url();
+    let setup = TestSetup::start_with_config(|config| {
+        config.simulation_fallback = SimulationFallback::Ephemeral;
+        config.validator_registry = ValidatorRegistry::default()
+            .with_validator(
+                registered_authority,
+                RpcCluster::Custom(
+                    registered_url,
+                    "ws://127.0.0.1:0".to_string(),
+                ),
+            );
+    })
+    .await;
+    let delegated_id = setup.add_delegated_account_to(registered_authority);
+    let undelegated_id = Pubkey::new_unique();
+    setup.add_account(undelegated_id, account_with_data());
+    registered.add_account(delegated_id, account_owned_by_delegation_program());
+    registered.add_account(undelegated_id, account_with_data());
+    let tx =
+        transaction_writing(&Keypair::new(), &[delegated_id, undelegated_id]);
+    (setup, registered, delegated_id, undelegated_id, tx)
+}
+
+#[tokio::test]
+async fn test_simulation_runs_on_the_validator_of_the_delegated_accounts() {
+    let (setup, registered, delegated_id, _, tx) =
+        setup_unroutable_on_registered_validator().await;
+    registered.add_simulated_account(
+        delegated_id,
+        written(account_owned_by_delegation_program()),
+    );
+
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, Signature::from([3; 64]).to_string());
+    assert_eq!(registered.sent_transactions().len(), 1);
+    assert!(!setup
+        .backends
+        .ephem
+        .requested_methods()
+        .contains(&"simulateTransaction".to_string()));
+    assert!(setup.backends.ephem.sent_transactions().is_empty());
+}
+
+#[tokio::test]
+async fn test_simulation_is_compared_with_the_state_of_the_validator() {
+    let (setup, registered, delegated_id, undelegated_id, tx) =
+        setup_unroutable_on_registered_validator().await;
+    // The validator holds the delegated state which differs from the
+    // account owned by the delegation program on chain
+    registered.add_account(
+        delegated_id,
+        Account {
+            owner: Pubkey::new_unique(),
+            ..account_with_data()
+        },
+    );
+    registered
+        .add_simulated_account(undelegated_id, written(account_with_data()));
+
+    let signature = setup.send_transaction(tx).await.unwrap();
+
+    assert_eq!(signature, chain_signature().to_string());
+    assert_eq!(setup.backends.chain.sent_transactions().len(), 1);
+    assert!(registered.sent_transactions().is_empty());
+}
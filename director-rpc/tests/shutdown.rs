@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use common::MockBackends;
+use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_guidepoint::{
+    client_stream::websocket_upgrade_channel, shutdown::ShutdownSummary,
+};
+use jsonrpsee::{
+    core::client::ClientT, http_client::HttpClientBuilder, rpc_params,
+};
+use serde_json::json;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+use tokio::net::TcpStream;
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[tokio::test]
+async fn test_shutdown_drains_in_flight_transactions() {
+    let backends = MockBackends::start().await;
+    let signature = Signature::from([1; 64]);
+    backends.chain.set_send_transaction_result(Ok(signature));
+    backends
+        .chain
+        .set_send_transaction_delay(Duration::from_millis(300));
+    let config = backends.config();
+    let (addr, handle) =
+        start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+
+    let payer = Keypair::new();
+    let ix = Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[],
+        vec![AccountMeta::new(Pubkey::new_unique(), false)],
+    );
+    let tx = Transaction::new_with_payer(&[ix], Some(&payer.pubkey()));
+    let data = BASE64_STANDARD
+        .encode(bincode::serialize(&VersionedTransaction::from(tx)).unwrap());
+    let client = HttpClientBuilder::default()
+        .build(format!("http://{}/chain", addr))
+        .unwrap();
+    let in_flight = tokio::spawn(async move {
+        client
+            .request::<String, _>(
+                "sendTransaction",
+                rpc_params![data, json!({ "encoding": "base64" })],
+            )
+            .await
+    });
+    tokio::time::timeout(TIMEOUT, async {
+        while backends.chain.sent_transactions().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("transaction was not forwarded in time");
+
+    let summary = handle.shutdown(TIMEOUT).await;
+
+    assert_eq!(
+        summary,
+        ShutdownSummary {
+            open_connections: 1,
+            in_flight_requests: 1,
+            drained_connections: 1,
+            aborted_connections: 0,
+        }
+    );
+    assert_eq!(in_flight.await.unwrap().unwrap(), signature.to_string());
+    assert!(TcpStream::connect(&addr).await.is_err());
+}
+
+#[tokio::test]
+async fn test_shutdown_closes_connections_without_a_request() {
+    let backends = MockBackends::start().await;
+    backends.chain.set_slot(42);
+    let (upgrades_tx, _upgrades_rx) = websocket_upgrade_channel();
+    let config = DirectorConfig {
+        websocket_upgrades: Some(upgrades_tx),
+        ..backends.config()
+    };
+    let (addr, handle) =
+        start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+
+    // Never sends the head of a request that would tell whether it is a
+    // websocket upgrade
+    let _idle = TcpStream::connect(&addr).await.unwrap();
+    // Connections are accepted in order, so the idle one is served once
+    // this request is answered
+    let client = HttpClientBuilder::default()
+        .build(format!("http://{}/chain", addr))
+        .unwrap();
+    let slot: u64 = client.request("getSlot", rpc_params![]).await.unwrap();
+    assert_eq!(slot, 42);
+
+    let summary = handle.shutdown(TIMEOUT).await;
+
+    assert_eq!(
+        summary,
+        ShutdownSummary {
+            open_connections: 2,
+            in_flight_requests: 0,
+            drained_connections: 2,
+            aborted_connections: 0,
+        }
+    );
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use common::MockBackends;
+use conjunto_addresses::validator_registry::ValidatorRegistry;
+use conjunto_director_pubsub::{
+    director::{DirectorPubsub, DirectorPubsubConfig},
+    start_pubsub_server_for_upgrades_with_director,
+};
+use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_guidepoint::{
+    auth::ApiKeyAuth, client_stream::websocket_upgrade_channel,
+    rate_limit::RateLimitConfig, tls::TlsTermination,
+};
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    signature_status_provider_stub::SignatureStatusProviderStub,
+    tls::TestCertificate,
+};
+use futures_util::{SinkExt, StreamExt};
+use jsonrpsee::{
+    core::client::ClientT, http_client::HttpClientBuilder, rpc_params,
+};
+use serde_json::{json, Value};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    client_async, connect_async, tungstenite::Message, WebSocketStream,
+};
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct TestSetup {
+    backends: MockBackends,
+    director_addr: String,
+}
+
+impl TestSetup {
+    /// Starts the RPC server handing its websocket upgrades to the pubsub
+    /// server, both terminating TLS with `cert` if provided
+    async fn start(cert: Option<&TestCertificate>) -> Self {
+        let backends = MockBackends::start().await;
+        backends.chain.set_slot(42);
+        backends.ephem.set_slot(42);
+        let tls = cert.map(|cert| {
+            TlsTermination::from_files(
+                &cert.cert_file,
+                &cert.key_file,
+                Duration::from_secs(1),
+            )
+            .unwrap()
+        });
+        let (upgrades_tx, upgrades_rx) = websocket_upgrade_channel();
+
+        let config = DirectorConfig {
+            tls: tls.clone(),
+            websocket_upgrades: Some(upgrades_tx),
+            ..backends.config()
+        };
+        let pubsub_config = DirectorPubsubConfig {
+            chain_cluster: config.chain_cluster.clone(),
+            ephem_rpc_provider_config: config.ephem_rpc_provider_config.clone(),
+            validator_registry: ValidatorRegistry::default(),
+            rate_limit: RateLimitConfig::default(),
+            auth: ApiKeyAuth::default(),
+            tls,
+        };
+        let (director_addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+
+        let director = DirectorPubsub::with_providers(
+            pubsub_config,
+            AccountProviderStub::default(),
+            SignatureStatusProviderStub::default(),
+        );
+        // Keeps running until the test ends since dropping the handle
+        // doesn't stop the server
+        start_pubsub_server_for_upgrades_with_director(director, upgrades_rx);
+
+        Self {
+            backends,
+            director_addr,
+        }
+    }
+}
+
+/// Subscribes via chain and returns the subscription id the client got
+async fn account_subscribe<S: AsyncRead + AsyncWrite + Unpin>(
+    client: &mut WebSocketStream<S>,
+) -> Value {
+    let msg = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "accountSubscribe",
+        "params": [Pubkey::new_unique().to_string()],
+    });
+    client.send(Message::Text(msg.to_string())).await.unwrap();
+    let Some(Ok(Message::Text(response))) =
+        tokio::time::timeout(TIMEOUT, client.next()).await.unwrap()
+    else {
+        panic!("Expected a text message");
+    };
+    serde_json::from_str::<Value>(&response).unwrap()["result"].clone()
+}
+
+#[tokio::test]
+async fn test_rpc_requests_and_websockets_share_the_port() {
+    let setup = TestSetup::start(None).await;
+    setup
+        .backends
+        .chain_ws
+        .respond_to("accountSubscribe", json!(7));
+
+    let rpc_client = HttpClientBuilder::default()
+        .build(format!("http://{}", setup.director_addr))
+        .unwrap();
+    let slot: Slot =
+        rpc_client.request("getSlot", rpc_params![]).await.unwrap();
+    let (mut ws_client, _) =
+        connect_async(format!("ws://{}/chain", setup.director_addr))
+            .await
+            .unwrap();
+
+    assert_eq!(slot, 42);
+    assert_eq!(account_subscribe(&mut ws_client).await, json!(7));
+}
+
+#[tokio::test]
+async fn test_websockets_share_the_port_over_tls() {
+    let cert = TestCertificate::generate();
+    let setup = TestSetup::start(Some(&cert)).await;
+    setup
+        .backends
+        .chain_ws
+        .respond_to("accountSubscribe", json!(7));
+
+    let stream = cert.connect(&setup.director_addr).await.unwrap();
+    let (mut ws_client, _) =
+        client_async(format!("wss://{}/chain", setup.director_addr), stream)
+            .await
+            .unwrap();
+
+    assert_eq!(account_subscribe(&mut ws_client).await, json!(7));
+}
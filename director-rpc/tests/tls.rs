@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use common::MockBackends;
+use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_guidepoint::tls::TlsTermination;
+use conjunto_test_tools::tls::TestCertificate;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct TestSetup {
+    _backends: MockBackends,
+    cert: TestCertificate,
+    director_addr: String,
+}
+
+impl TestSetup {
+    async fn start() -> Self {
+        let backends = MockBackends::start().await;
+        backends.chain.set_slot(42);
+        backends.ephem.set_slot(42);
+        let cert = TestCertificate::generate();
+        let tls = TlsTermination::from_files(
+            &cert.cert_file,
+            &cert.key_file,
+            Duration::from_millis(20),
+        )
+        .unwrap();
+        let config = DirectorConfig {
+            tls: Some(tls),
+            ..backends.config()
+        };
+        let (director_addr, _) =
+            start_rpc_server(config, Some("127.0.0.1:0")).await.unwrap();
+        Self {
+            _backends: backends,
+            cert,
+            director_addr,
+        }
+    }
+
+    async fn get_slot_over_tls(&self) -> std::io::Result<Value> {
+        let stream = self.cert.connect(&self.director_addr).await?;
+        Ok(get_slot(stream).await)
+    }
+}
+
+/// Sends a `getSlot` request over the stream and returns the response
+async fn get_slot(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> Value {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "getSlot",
+    })
+    .to_string();
+    stream
+        .write_all(
+            format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_requests_are_served_over_tls() {
+    let setup = TestSetup::start().await;
+
+    let response = setup.get_slot_over_tls().await.unwrap();
+
+    assert_eq!(response["result"], json!(42));
+}
+
+#[tokio::test]
+async fn test_plain_http_connections_are_dropped() {
+    let setup = TestSetup::start().await;
+    let mut stream = TcpStream::connect(&setup.director_addr).await.unwrap();
+
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+
+    // The request is taken for a malformed TLS handshake
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    assert!(!String::from_utf8_lossy(&response).contains("HTTP/1.1"));
+}
+
+#[tokio::test]
+async fn test_renewed_certificate_is_picked_up() {
+    let mut setup = TestSetup::start().await;
+    setup.cert.renew();
+
+    tokio::time::timeout(TIMEOUT, async {
+        while setup.get_slot_over_tls().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("certificate was not reloaded in time");
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use conjunto_addresses::cluster::RpcCluster;
+use conjunto_director_rpc::rpc::{DelegatedChainReads, SimulationFallback};
+use solana_sdk::commitment_config::CommitmentLevel;
+
+/// Routes RPC and pubsub requests between chain and ephemeral validators.
+///
+/// Settings are taken from the config file if provided, flags and their env
+/// vars override them.
+#[derive(Debug, Parser)]
+#[command(name = "conjunto-director", version)]
+pub struct Cli {
+    /// Config file in TOML (.toml) or YAML (.yaml, .yml) format
+    #[arg(short, long, env = "CONJUNTO_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Prints the effective config as TOML and exits
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Chain RPC, a moniker [mainnet, testnet, devnet, localhost], an HTTP
+    /// URL the websocket URL is derived from or an `http,ws` URL pair
+    #[arg(long, env = "CONJUNTO_CHAIN")]
+    pub chain: Option<RpcCluster>,
+
+    /// Default ephemeral validator RPC, same format as `--chain`, i.e.
+    /// `magicblock-devnet` or `http://localhost:7799`
+    #[arg(long, env = "CONJUNTO_EPHEM")]
+    pub ephem: Option<RpcCluster>,
+
+    /// Commitment at which the ephemeral validator is queried
+    /// [processed, confirmed, finalized]
+    #[arg(long, env = "CONJUNTO_COMMITMENT")]
+    pub commitment: Option<CommitmentLevel>,
+
+    /// Address the RPC server binds to
+    #[arg(long, env = "CONJUNTO_RPC_ADDR")]
+    pub rpc_addr: Option<String>,
+
+    /// Address the pubsub server binds to
+    #[arg(long, env = "CONJUNTO_PUBSUB_ADDR")]
+    pub pubsub_addr: Option<String>,
+
+    /// Serves websockets on the RPC address as well, `--pubsub-addr` is
+    /// ignored then
+    #[arg(long, env = "CONJUNTO_SINGLE_PORT")]
+    pub single_port: bool,
+
+    /// Log filter, i.e. `info` or `conjunto_director_rpc=debug`
+    #[arg(long, env = "RUST_LOG")]
+    pub log_level: Option<String>,
+
+    /// How long in-flight requests are given to finish on shutdown
+    #[arg(long, env = "CONJUNTO_SHUTDOWN_TIMEOUT_MS")]
+    pub shutdown_timeout_ms: Option<u64>,
+
+    /// Where unroutable transactions are simulated
+    /// [disabled, ephemeral, chain, ephemeral-then-chain]
+    #[arg(long, env = "CONJUNTO_SIMULATION_FALLBACK")]
+    pub simulation_fallback: Option<SimulationFallback>,
+
+    /// Authority of the default ephemeral validator, transactions writing
+    /// accounts delegated to other validators are rejected
+    #[arg(long, env = "CONJUNTO_EPHEM_VALIDATOR_AUTHORITY")]
+    pub ephem_validator_authority: Option<String>,
+
+    /// Lamports the escrow of a payer needs to hold for its transactions to
+    /// be routed to the ephemeral validator
+    #[arg(long, env = "CONJUNTO_PAYER_ESCROW_MIN_LAMPORTS")]
+    pub payer_escrow_min_lamports: Option<u64>,
+
+    /// Disables checking the escrow of payers
+    #[arg(long, conflicts_with = "payer_escrow_min_lamports")]
+    pub no_payer_escrow_check: bool,
+
+    /// How chain reads of delegated accounts are handled [annotate, refuse]
+    #[arg(long, env = "CONJUNTO_DELEGATED_CHAIN_READS")]
+    pub delegated_chain_reads: Option<DelegatedChainReads>,
+
+    /// Records routing decisions as JSON lines to `stdout` or the given file
+    #[arg(long, env = "CONJUNTO_AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    /// File with the API keys clients need to provide, reloaded on changes
+    #[arg(long, env = "CONJUNTO_API_KEYS_FILE")]
+    pub api_keys_file: Option<String>,
+
+    /// PEM file with the TLS certificate chain, requires `--tls-key-file`
+    #[arg(long, env = "CONJUNTO_TLS_CERT_FILE")]
+    pub tls_cert_file: Option<String>,
+
+    /// PEM file with the private key of the TLS certificate
+    #[arg(long, env = "CONJUNTO_TLS_KEY_FILE")]
+    pub tls_key_file: Option<String>,
+}
@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+pub type DirectorResult<T> = Result<T, DirectorError>;
+
+#[derive(Debug, Error)]
+pub enum DirectorError {
+    #[error("StdIoError: {0}")]
+    StdIoError(#[from] std::io::Error),
+    #[error("TomlDeError: {0}")]
+    TomlDeError(#[from] toml::de::Error),
+    #[error("TomlSerError: {0}")]
+    TomlSerError(#[from] toml::ser::Error),
+    #[error("YamlError: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("DirectorRpcError: {0}")]
+    DirectorRpcError(#[from] conjunto_director_rpc::errors::DirectorRpcError),
+    #[error("DirectorPubsubError: {0}")]
+    DirectorPubsubError(
+        #[from] conjunto_director_pubsub::errors::DirectorPubsubError,
+    ),
+
+    #[error("Failed to read config file '{0}': {1}")]
+    ConfigFile(String, #[source] std::io::Error),
+    #[error(
+        "Unsupported config file '{0}', expected a .toml, .yaml or .yml file"
+    )]
+    UnsupportedConfigFile(String),
+    #[error("Invalid config:\n{}", .0.iter().map(|err| format!("  - {err}")).collect::<Vec<_>>().join("\n"))]
+    InvalidConfig(Vec<String>),
+}
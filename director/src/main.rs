@@ -1,34 +1,91 @@
+use std::process;
+
+use clap::Parser;
+use cli::Cli;
 use conjunto_director_pubsub::{
-    director::DirectorPubsubConfig, start_pubsub_server,
+    start_pubsub_server, start_pubsub_server_for_upgrades,
 };
-use conjunto_director_rpc::{rpc::DirectorConfig, start_rpc_server};
+use conjunto_director_rpc::start_rpc_server;
 use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_signature_status_provider::RpcSignatureStatusProvider,
 };
+use errors::DirectorResult;
 use log::*;
+use settings::DirectorSettings;
+
+mod cli;
+mod errors;
+mod settings;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> DirectorResult<()> {
+    let settings = DirectorSettings::from_cli(&cli)?;
+    // Validate before printing so the printed config is known to be usable
+    let configs = settings.try_into_configs()?;
+    if cli.print_config {
+        print!("{}", settings.to_toml()?);
+        return Ok(());
+    }
+
+    env_logger::Builder::new()
+        .parse_filters(&settings.log_level)
+        .init();
 
     let (rpc_addr, rpc_handle) =
-        start_rpc_server(DirectorConfig::devnet(), None)
-            .await
-            .unwrap();
-
-    let (pubsub_addr, pubsub_handle) =
-        start_pubsub_server::<RpcAccountProvider, RpcSignatureStatusProvider>(
-            DirectorPubsubConfig::devnet(),
-            None,
-        )
-        .await
-        .unwrap();
+        start_rpc_server(configs.rpc, Some(&configs.rpc_addr)).await?;
+
+    let (pubsub_addr, pubsub_handle) = match configs.websocket_upgrades {
+        Some(upgrades) => (
+            rpc_addr.clone(),
+            start_pubsub_server_for_upgrades::<
+                RpcAccountProvider,
+                RpcSignatureStatusProvider,
+            >(configs.pubsub, upgrades),
+        ),
+        None => start_pubsub_server::<
+            RpcAccountProvider,
+            RpcSignatureStatusProvider,
+        >(configs.pubsub, Some(&configs.pubsub_addr))
+        .await?,
+    };
     info!("RPC Server running on: {}", rpc_addr);
     info!("Pubsub Server running on: {}", pubsub_addr);
 
-    let (_, res) = tokio::join!(rpc_handle.stopped(), pubsub_handle);
-    if let Err(err) = res {
-        error!("Error: {:?}", err);
+    shutdown_signal().await?;
+    info!(
+        "Shutting down, waiting up to {:?} for connections to finish",
+        configs.shutdown_timeout
+    );
+    let (rpc_summary, pubsub_summary) = tokio::join!(
+        rpc_handle.shutdown(configs.shutdown_timeout),
+        pubsub_handle.shutdown(configs.shutdown_timeout)
+    );
+    info!("RPC Server shut down: {:?}", rpc_summary);
+    info!("Pubsub Server shut down: {:?}", pubsub_summary);
+    Ok(())
+}
+
+/// Resolves on Ctrl-C and on unix also on SIGTERM
+async fn shutdown_signal() -> DirectorResult<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = sigterm.recv() => {}
+        }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
 }
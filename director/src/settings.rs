@@ -0,0 +1,933 @@
+use std::{fs, net::SocketAddr, path::Path, str::FromStr, time::Duration};
+
+use conjunto_addresses::{
+    cluster::RpcCluster, validator_registry::ValidatorRegistry,
+};
+use conjunto_director_pubsub::{
+    director::DirectorPubsubConfig, DEFAULT_DIRECTOR_PUBSUB_URL,
+};
+use conjunto_director_rpc::{
+    audit::{AuditConfig, AuditSink},
+    health::HealthConfig,
+    rpc::{DelegatedChainReads, DirectorConfig, SimulationFallback},
+    DEFAULT_DIRECTOR_RPC_URL,
+};
+use conjunto_guidepoint::{
+    auth::{ApiKeyAuth, DEFAULT_KEYS_RELOAD_INTERVAL},
+    client_stream::{websocket_upgrade_channel, WebSocketUpgradeReceiver},
+    rate_limit::{Quota, RateLimitConfig, RateLimitKey},
+    tls::{TlsTermination, DEFAULT_TLS_RELOAD_INTERVAL},
+};
+use conjunto_providers::rpc_provider_config::RpcProviderConfig;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{commitment_config::CommitmentLevel, pubkey::Pubkey};
+
+use crate::{
+    cli::Cli,
+    errors::{DirectorError, DirectorResult},
+};
+
+// -----------------
+// DirectorSettings
+// -----------------
+/// All settings of the director as they can be provided via a config file.
+/// Defaults to routing between devnet and the magicblock devnet validator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DirectorSettings {
+    /// Moniker, HTTP URL or `http,ws` URL pair of the chain
+    pub chain: RpcCluster,
+    /// Moniker, HTTP URL or `http,ws` URL pair of the default ephemeral
+    /// validator
+    pub ephem: RpcCluster,
+    /// Commitment at which the ephemeral validator is queried
+    pub commitment: Option<CommitmentLevel>,
+    pub rpc_addr: String,
+    pub pubsub_addr: String,
+    /// Serves RPC requests and websocket upgrades on `rpc-addr`, the
+    /// `pubsub-addr` isn't bound then
+    pub single_port: bool,
+    /// Log filter in the format of `RUST_LOG`
+    pub log_level: String,
+    /// How long in-flight requests and websocket clients get to finish when
+    /// the director is shut down
+    pub shutdown_timeout_ms: u64,
+    pub routing: RoutingSettings,
+    pub health: HealthSettings,
+    pub audit: AuditSettings,
+    pub rate_limit: RateLimitSettings,
+    pub auth: AuthSettings,
+    pub tls: TlsSettings,
+}
+
+impl Default for DirectorSettings {
+    fn default() -> Self {
+        Self {
+            chain: RpcCluster::Devnet,
+            ephem: RpcCluster::magicblock_devnet(),
+            commitment: None,
+            rpc_addr: DEFAULT_DIRECTOR_RPC_URL.to_string(),
+            pubsub_addr: DEFAULT_DIRECTOR_PUBSUB_URL.to_string(),
+            single_port: false,
+            log_level: "info".to_string(),
+            shutdown_timeout_ms: 10_000,
+            routing: RoutingSettings::default(),
+            health: HealthSettings::default(),
+            audit: AuditSettings::default(),
+            rate_limit: RateLimitSettings::default(),
+            auth: AuthSettings::default(),
+            tls: TlsSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RoutingSettings {
+    pub simulation_fallback: SimulationFallback,
+    pub ephem_validator_authority: Option<String>,
+    pub payer_escrow_min_lamports: Option<u64>,
+    pub delegated_chain_reads: DelegatedChainReads,
+    /// Ephemeral validators besides the default one
+    pub validators: Vec<ValidatorSettings>,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        Self {
+            simulation_fallback: SimulationFallback::default(),
+            ephem_validator_authority: None,
+            payer_escrow_min_lamports: None,
+            delegated_chain_reads: DelegatedChainReads::default(),
+            validators: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ValidatorSettings {
+    pub authority: String,
+    pub cluster: RpcCluster,
+}
+
+/// How the backends are probed for the health endpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HealthSettings {
+    pub probe_interval_ms: u64,
+    pub probe_timeout_ms: u64,
+    pub max_slot_lag: u64,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        let config = HealthConfig::default();
+        Self {
+            probe_interval_ms: config.probe_interval.as_millis() as u64,
+            probe_timeout_ms: config.probe_timeout.as_millis() as u64,
+            max_slot_lag: config.max_slot_lag,
+        }
+    }
+}
+
+/// Where and how the routing decisions are recorded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AuditSettings {
+    /// `stdout` or a file the records are appended to, disabled if not set
+    pub sink: Option<String>,
+    pub sample_every: u64,
+    pub redact_account_data: bool,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        let config = AuditConfig::default();
+        Self {
+            sink: config.sink.map(|sink| sink.to_string()),
+            sample_every: config.sample_every,
+            redact_account_data: config.redact_account_data,
+        }
+    }
+}
+
+/// How much each client may use the director, limits that aren't set don't
+/// apply
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RateLimitSettings {
+    /// `ip` or `api-key` which falls back to the IP for clients without a
+    /// valid key, `api-key` requires `auth.keys-file`
+    pub key: String,
+    pub requests_per_second: Option<u32>,
+    /// Defaults to `requests-per-second`
+    pub requests_burst: Option<u32>,
+    pub send_transactions_per_second: Option<u32>,
+    /// Defaults to `send-transactions-per-second`
+    pub send_transactions_burst: Option<u32>,
+    /// Websocket connections per client
+    pub max_connections: Option<u32>,
+    /// Subscriptions per client across all its websocket connections
+    pub max_subscriptions: Option<u32>,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        let config = RateLimitConfig::default();
+        Self {
+            key: config.key.to_string(),
+            requests_per_second: None,
+            requests_burst: None,
+            send_transactions_per_second: None,
+            send_transactions_burst: None,
+            max_connections: config.max_connections,
+            max_subscriptions: config.max_subscriptions,
+        }
+    }
+}
+
+/// Which API keys clients need to provide, everyone is let in if no keys
+/// file is set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AuthSettings {
+    pub keys_file: Option<String>,
+    /// How often the keys file is checked for changes
+    pub reload_interval_ms: u64,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            keys_file: None,
+            reload_interval_ms: DEFAULT_KEYS_RELOAD_INTERVAL.as_millis() as u64,
+        }
+    }
+}
+
+/// Certificate both servers terminate TLS with, they serve plain HTTP and
+/// websockets if no files are set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TlsSettings {
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    /// How often the certificate and key files are checked for changes
+    pub reload_interval_ms: u64,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            cert_file: None,
+            key_file: None,
+            reload_interval_ms: DEFAULT_TLS_RELOAD_INTERVAL.as_millis() as u64,
+        }
+    }
+}
+
+impl DirectorSettings {
+    /// Loads the settings from the config file if provided and applies the
+    /// flags and env vars on top of them
+    pub fn from_cli(cli: &Cli) -> DirectorResult<Self> {
+        let mut settings = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        settings.apply_overrides(cli);
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &Path) -> DirectorResult<Self> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            DirectorError::ConfigFile(path.display().to_string(), err)
+        })?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(toml::from_str(&content)?),
+            Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&content)?),
+            _ => Err(DirectorError::UnsupportedConfigFile(
+                path.display().to_string(),
+            )),
+        }
+    }
+
+    fn apply_overrides(&mut self, cli: &Cli) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+        set(&mut self.chain, &cli.chain);
+        set(&mut self.ephem, &cli.ephem);
+        if cli.commitment.is_some() {
+            self.commitment = cli.commitment;
+        }
+        set(&mut self.rpc_addr, &cli.rpc_addr);
+        set(&mut self.pubsub_addr, &cli.pubsub_addr);
+        set(&mut self.log_level, &cli.log_level);
+        set(&mut self.shutdown_timeout_ms, &cli.shutdown_timeout_ms);
+        if cli.single_port {
+            self.single_port = true;
+        }
+
+        let routing = &mut self.routing;
+        set(&mut routing.simulation_fallback, &cli.simulation_fallback);
+        if cli.ephem_validator_authority.is_some() {
+            routing
+                .ephem_validator_authority
+                .clone_from(&cli.ephem_validator_authority);
+        }
+        if cli.payer_escrow_min_lamports.is_some() {
+            routing.payer_escrow_min_lamports = cli.payer_escrow_min_lamports;
+        }
+        if cli.no_payer_escrow_check {
+            routing.payer_escrow_min_lamports = None;
+        }
+        set(
+            &mut routing.delegated_chain_reads,
+            &cli.delegated_chain_reads,
+        );
+
+        if cli.audit_log.is_some() {
+            self.audit.sink.clone_from(&cli.audit_log);
+        }
+        if cli.api_keys_file.is_some() {
+            self.auth.keys_file.clone_from(&cli.api_keys_file);
+        }
+        if cli.tls_cert_file.is_some() {
+            self.tls.cert_file.clone_from(&cli.tls_cert_file);
+        }
+        if cli.tls_key_file.is_some() {
+            self.tls.key_file.clone_from(&cli.tls_key_file);
+        }
+    }
+
+    pub fn to_toml(&self) -> DirectorResult<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Validates the settings and converts them into the configs of the
+    /// servers, all problems found are reported at once
+    pub fn try_into_configs(&self) -> DirectorResult<DirectorConfigs> {
+        let mut errors = vec![];
+
+        let rpc_addr = socket_addr("rpc-addr", &self.rpc_addr, &mut errors);
+        let pubsub_addr =
+            socket_addr("pubsub-addr", &self.pubsub_addr, &mut errors);
+        if !self.single_port && rpc_addr.is_some() && rpc_addr == pubsub_addr {
+            errors.push(format!(
+                "rpc-addr and pubsub-addr are both '{}'",
+                self.rpc_addr
+            ));
+        }
+
+        let routing = &self.routing;
+        let ephem_validator_authority = routing
+            .ephem_validator_authority
+            .as_ref()
+            .and_then(|authority| {
+                pubkey("ephem-validator-authority", authority, &mut errors)
+            });
+        let mut validator_registry = ValidatorRegistry::default();
+        for validator in &routing.validators {
+            let authority = pubkey(
+                "validators.authority",
+                &validator.authority,
+                &mut errors,
+            );
+            if let Some(authority) = authority {
+                if validator_registry.get(&authority).is_some() {
+                    errors.push(format!(
+                        "validator {} is configured more than once",
+                        authority
+                    ));
+                }
+                validator_registry.insert(authority, validator.cluster.clone());
+            }
+        }
+        // Transactions are only routed to registered validators otherwise
+        if !routing.validators.is_empty()
+            && routing.ephem_validator_authority.is_none()
+        {
+            errors.push(
+                "validators require ephem-validator-authority to be set"
+                    .to_string(),
+            );
+        }
+
+        let health = &self.health;
+        if health.probe_interval_ms == 0 || health.probe_timeout_ms == 0 {
+            errors.push(
+                "health probe-interval-ms and probe-timeout-ms need to be \
+                 greater than 0"
+                    .to_string(),
+            );
+        }
+
+        let audit = &self.audit;
+        let audit_sink = audit.sink.as_ref().and_then(|sink| {
+            AuditSink::from_str(sink)
+                .map_err(|err| errors.push(format!("audit sink: {}", err)))
+                .ok()
+        });
+        if audit.sample_every == 0 {
+            errors.push("audit sample-every needs to be greater than 0".into());
+        }
+
+        let rate_limit = &self.rate_limit;
+        let rate_limit_key = RateLimitKey::from_str(&rate_limit.key)
+            .unwrap_or_else(|err| {
+                errors.push(format!("rate-limit key: {}", err));
+                RateLimitKey::default()
+            });
+        // Without authentication any made up key would get a budget of its own
+        if rate_limit_key == RateLimitKey::ApiKey
+            && self.auth.keys_file.is_none()
+        {
+            errors.push(
+                "rate-limit key 'api-key' requires auth keys-file to be set"
+                    .into(),
+            );
+        }
+        let rate_limit = RateLimitConfig {
+            key: rate_limit_key,
+            requests: quota(
+                "requests",
+                rate_limit.requests_per_second,
+                rate_limit.requests_burst,
+                &mut errors,
+            ),
+            send_transactions: quota(
+                "send-transactions",
+                rate_limit.send_transactions_per_second,
+                rate_limit.send_transactions_burst,
+                &mut errors,
+            ),
+            max_connections: rate_limit.max_connections,
+            max_subscriptions: rate_limit.max_subscriptions,
+        };
+
+        let auth = &self.auth;
+        if auth.reload_interval_ms == 0 {
+            errors.push(
+                "auth reload-interval-ms needs to be greater than 0".into(),
+            );
+        }
+        let auth = match &auth.keys_file {
+            Some(path) => ApiKeyAuth::from_file(
+                path,
+                Duration::from_millis(auth.reload_interval_ms),
+            )
+            .unwrap_or_else(|err| {
+                errors.push(format!("auth keys-file: {}", err));
+                ApiKeyAuth::default()
+            }),
+            None => ApiKeyAuth::default(),
+        };
+
+        let tls = &self.tls;
+        if tls.reload_interval_ms == 0 {
+            errors.push(
+                "tls reload-interval-ms needs to be greater than 0".into(),
+            );
+        }
+        let tls = match (&tls.cert_file, &tls.key_file) {
+            (Some(cert_file), Some(key_file)) => TlsTermination::from_files(
+                cert_file,
+                key_file,
+                Duration::from_millis(tls.reload_interval_ms),
+            )
+            .map_err(|err| errors.push(format!("tls: {}", err)))
+            .ok(),
+            (None, None) => None,
+            _ => {
+                errors.push(
+                    "tls cert-file and key-file need to be set together".into(),
+                );
+                None
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(DirectorError::InvalidConfig(errors));
+        }
+        let chain_cluster = self.chain.clone();
+        let ephem_rpc_provider_config =
+            RpcProviderConfig::new(self.ephem.clone(), self.commitment);
+        let (upgrades_tx, upgrades_rx) = if self.single_port {
+            let (tx, rx) = websocket_upgrade_channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        Ok(DirectorConfigs {
+            rpc: DirectorConfig {
+                ephem_rpc_provider_config: ephem_rpc_provider_config.clone(),
+                chain_cluster: chain_cluster.clone(),
+                simulation_fallback: routing.simulation_fallback,
+                ephem_validator_authority,
+                validator_registry: validator_registry.clone(),
+                payer_escrow_min_lamports: routing.payer_escrow_min_lamports,
+                delegated_chain_reads: routing.delegated_chain_reads,
+                health: HealthConfig {
+                    probe_interval: Duration::from_millis(
+                        health.probe_interval_ms,
+                    ),
+                    probe_timeout: Duration::from_millis(
+                        health.probe_timeout_ms,
+                    ),
+                    max_slot_lag: health.max_slot_lag,
+                },
+                audit: AuditConfig {
+                    sink: audit_sink,
+                    sample_every: audit.sample_every,
+                    redact_account_data: audit.redact_account_data,
+                },
+                rate_limit: rate_limit.clone(),
+                auth: auth.clone(),
+                tls: tls.clone(),
+                websocket_upgrades: upgrades_tx,
+            },
+            pubsub: DirectorPubsubConfig {
+                chain_cluster,
+                ephem_rpc_provider_config,
+                validator_registry,
+                rate_limit,
+                auth,
+                tls,
+            },
+            rpc_addr: self.rpc_addr.clone(),
+            pubsub_addr: self.pubsub_addr.clone(),
+            websocket_upgrades: upgrades_rx,
+            shutdown_timeout: Duration::from_millis(self.shutdown_timeout_ms),
+        })
+    }
+}
+
+/// The validated configs the servers are started with
+pub struct DirectorConfigs {
+    pub rpc: DirectorConfig,
+    pub pubsub: DirectorPubsubConfig,
+    pub rpc_addr: String,
+    pub pubsub_addr: String,
+    /// Set in single-port mode, the pubsub server then serves the websocket
+    /// upgrades the RPC server hands over instead of binding `pubsub_addr`
+    pub websocket_upgrades: Option<WebSocketUpgradeReceiver>,
+    pub shutdown_timeout: Duration,
+}
+
+// -----------------
+// Validation
+// -----------------
+fn socket_addr(
+    name: &str,
+    addr: &str,
+    errors: &mut Vec<String>,
+) -> Option<SocketAddr> {
+    SocketAddr::from_str(addr)
+        .map_err(|err| {
+            errors.push(format!("{} '{}' is invalid: {}", name, addr, err))
+        })
+        .ok()
+}
+
+fn quota(
+    name: &str,
+    per_second: Option<u32>,
+    burst: Option<u32>,
+    errors: &mut Vec<String>,
+) -> Option<Quota> {
+    match (per_second, burst) {
+        (None, None) => None,
+        (None, Some(_)) => {
+            errors.push(format!(
+                "rate-limit {0}-burst requires {0}-per-second",
+                name
+            ));
+            None
+        }
+        (Some(0), _) | (_, Some(0)) => {
+            errors.push(format!(
+                "rate-limit {0}-per-second and {0}-burst need to be greater \
+                 than 0",
+                name
+            ));
+            None
+        }
+        (Some(per_second), burst) => Some(Quota {
+            per_second,
+            burst: burst.unwrap_or(per_second),
+        }),
+    }
+}
+
+fn pubkey(
+    name: &str,
+    pubkey: &str,
+    errors: &mut Vec<String>,
+) -> Option<Pubkey> {
+    Pubkey::from_str(pubkey)
+        .map_err(|err| {
+            errors.push(format!("{} '{}' is invalid: {}", name, pubkey, err))
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use conjunto_addresses::cluster::DEVNET;
+    use conjunto_test_tools::tls::TestCertificate;
+
+    use super::*;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::parse_from([&["conjunto-director"][..], args].concat())
+    }
+
+    #[test]
+    fn test_default_settings_are_valid() {
+        let configs = DirectorSettings::default().try_into_configs().unwrap();
+        assert_eq!(configs.rpc_addr, DEFAULT_DIRECTOR_RPC_URL);
+        assert_eq!(configs.pubsub_addr, DEFAULT_DIRECTOR_PUBSUB_URL);
+        assert_eq!(configs.rpc.chain_cluster.url(), DEVNET);
+        assert_eq!(configs.rpc.payer_escrow_min_lamports, None);
+    }
+
+    #[test]
+    fn test_toml_settings() {
+        let default_authority = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let settings: DirectorSettings = toml::from_str(&format!(
+            r#"
+            chain = "localhost"
+            commitment = "confirmed"
+
+            [routing]
+            simulation-fallback = "ephemeral-then-chain"
+            ephem-validator-authority = "{default_authority}"
+            delegated-chain-reads = "refuse"
+
+            [[routing.validators]]
+            authority = "{authority}"
+            cluster = "http://localhost:7799"
+            "#
+        ))
+        .unwrap();
+        assert_eq!(settings.chain, RpcCluster::Development);
+        assert_eq!(settings.ephem, RpcCluster::magicblock_devnet());
+        assert_eq!(settings.commitment, Some(CommitmentLevel::Confirmed));
+
+        let configs = settings.try_into_configs().unwrap();
+        assert_eq!(
+            configs.rpc.simulation_fallback,
+            SimulationFallback::EphemeralThenChain
+        );
+        assert_eq!(
+            configs.rpc.delegated_chain_reads,
+            DelegatedChainReads::Refuse
+        );
+        assert_eq!(
+            configs.pubsub.validator_registry.get(&authority),
+            Some(&RpcCluster::Custom(
+                "http://localhost:7799".to_string(),
+                "ws://localhost:7800".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_validators_require_ephem_validator_authority() {
+        let mut settings = DirectorSettings {
+            routing: RoutingSettings {
+                validators: vec![ValidatorSettings {
+                    authority: Pubkey::new_unique().to_string(),
+                    cluster: RpcCluster::Development,
+                }],
+                ..RoutingSettings::default()
+            },
+            ..DirectorSettings::default()
+        };
+        let Err(DirectorError::InvalidConfig(errors)) =
+            settings.clone().try_into_configs()
+        else {
+            panic!("expected invalid config");
+        };
+        assert_eq!(
+            errors,
+            vec!["validators require ephem-validator-authority to be set"]
+        );
+
+        settings.routing.ephem_validator_authority =
+            Some(Pubkey::new_unique().to_string());
+        assert!(settings.try_into_configs().is_ok());
+    }
+
+    #[test]
+    fn test_yaml_settings() {
+        let settings: DirectorSettings = serde_yaml::from_str(
+            r#"
+            ephem: http://localhost:7799,ws://localhost:7900
+            routing:
+              payer-escrow-min-lamports: 1000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings.ephem,
+            RpcCluster::Custom(
+                "http://localhost:7799".to_string(),
+                "ws://localhost:7900".to_string()
+            )
+        );
+        assert_eq!(settings.routing.payer_escrow_min_lamports, Some(1000));
+    }
+
+    #[test]
+    fn test_missing_config_file_names_the_path() {
+        let path = Path::new("/nonexistent/conjunto.toml");
+        let err = DirectorSettings::from_file(path).unwrap_err();
+        assert!(matches!(err, DirectorError::ConfigFile(_, _)));
+        assert!(err.to_string().starts_with(
+            "Failed to read config file '/nonexistent/conjunto.toml': "
+        ));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let res = toml::from_str::<DirectorSettings>("chain-url = \"devnet\"");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_invalid_clusters_are_rejected() {
+        for settings in [
+            "chain = \"ws://localhost:8899\"",
+            "ephem = \"http://localhost:7799,not a url\"",
+            "chain = \"http://a b\"",
+            "[[routing.validators]]\nauthority = \"nope\"\ncluster = \"nowhere\"",
+        ] {
+            let res = toml::from_str::<DirectorSettings>(settings);
+            assert!(res.is_err(), "{}", settings);
+        }
+    }
+
+    #[test]
+    fn test_flags_override_settings() {
+        let cli = cli(&[
+            "--chain",
+            "http://localhost:8899",
+            "--rpc-addr",
+            "0.0.0.0:9000",
+            "--simulation-fallback",
+            "chain",
+            "--no-payer-escrow-check",
+            "--audit-log",
+            "stdout",
+        ]);
+        let settings = DirectorSettings::from_cli(&cli).unwrap();
+        assert_eq!(settings.chain.url(), "http://localhost:8899");
+        assert_eq!(settings.chain.ws_url(), "ws://localhost:8900");
+        assert_eq!(settings.rpc_addr, "0.0.0.0:9000");
+        assert_eq!(
+            settings.routing.simulation_fallback,
+            SimulationFallback::Chain
+        );
+        assert_eq!(settings.routing.payer_escrow_min_lamports, None);
+        assert_eq!(
+            settings.try_into_configs().unwrap().rpc.audit.sink,
+            Some(AuditSink::Stdout)
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings_report_all_errors() {
+        let settings = DirectorSettings {
+            pubsub_addr: DEFAULT_DIRECTOR_RPC_URL.to_string(),
+            routing: RoutingSettings {
+                ephem_validator_authority: Some("nope".to_string()),
+                ..RoutingSettings::default()
+            },
+            health: HealthSettings {
+                probe_interval_ms: 0,
+                ..HealthSettings::default()
+            },
+            audit: AuditSettings {
+                sample_every: 0,
+                ..AuditSettings::default()
+            },
+            rate_limit: RateLimitSettings {
+                key: "token".to_string(),
+                requests_burst: Some(10),
+                send_transactions_per_second: Some(0),
+                ..RateLimitSettings::default()
+            },
+            auth: AuthSettings {
+                keys_file: Some("/nonexistent/api-keys.toml".to_string()),
+                reload_interval_ms: 0,
+            },
+            tls: TlsSettings {
+                cert_file: Some("/nonexistent/cert.pem".to_string()),
+                key_file: None,
+                reload_interval_ms: 0,
+            },
+            ..DirectorSettings::default()
+        };
+        let Err(DirectorError::InvalidConfig(errors)) =
+            settings.try_into_configs()
+        else {
+            panic!("expected invalid config");
+        };
+        assert_eq!(errors.len(), 11, "{:#?}", errors);
+    }
+
+    #[test]
+    fn test_auth_settings() {
+        let path = std::env::temp_dir()
+            .join(format!("conjunto-api-keys-{}.toml", Pubkey::new_unique()));
+        fs::write(&path, "[[keys]]\nkey = \"secret\"").unwrap();
+        let cli = cli(&["--api-keys-file", path.to_str().unwrap()]);
+
+        let configs = DirectorSettings::from_cli(&cli)
+            .unwrap()
+            .try_into_configs()
+            .unwrap();
+
+        assert!(configs.rpc.auth.is_enabled());
+        assert_eq!(configs.pubsub.auth.authenticate(Some("secret")), Ok(()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_tls_settings() {
+        let cert = TestCertificate::generate();
+        let cli = cli(&[
+            "--tls-cert-file",
+            cert.cert_file.to_str().unwrap(),
+            "--tls-key-file",
+            cert.key_file.to_str().unwrap(),
+        ]);
+
+        let configs = DirectorSettings::from_cli(&cli)
+            .unwrap()
+            .try_into_configs()
+            .unwrap();
+
+        assert!(configs.rpc.tls.is_some());
+        assert!(configs.pubsub.tls.is_some());
+
+        // The key file doesn't contain a certificate
+        let key_file = cert.key_file.to_str().unwrap().to_string();
+        let settings = DirectorSettings {
+            tls: TlsSettings {
+                cert_file: Some(key_file.clone()),
+                key_file: Some(key_file),
+                ..TlsSettings::default()
+            },
+            ..DirectorSettings::default()
+        };
+        let Err(DirectorError::InvalidConfig(errors)) =
+            settings.try_into_configs()
+        else {
+            panic!("expected invalid config");
+        };
+        assert_eq!(errors.len(), 1, "{:#?}", errors);
+        assert!(errors[0].starts_with("tls: No certificate found"));
+    }
+
+    #[test]
+    fn test_single_port_settings() {
+        let settings = DirectorSettings {
+            pubsub_addr: DEFAULT_DIRECTOR_RPC_URL.to_string(),
+            ..DirectorSettings::default()
+        };
+        assert!(settings.try_into_configs().is_err());
+
+        let cli = cli(&["--single-port"]);
+        let settings = DirectorSettings {
+            pubsub_addr: DEFAULT_DIRECTOR_RPC_URL.to_string(),
+            ..DirectorSettings::from_cli(&cli).unwrap()
+        };
+        let configs = settings.try_into_configs().unwrap();
+        assert!(configs.rpc.websocket_upgrades.is_some());
+        assert!(configs.websocket_upgrades.is_some());
+
+        let configs = DirectorSettings::default().try_into_configs().unwrap();
+        assert!(configs.rpc.websocket_upgrades.is_none());
+        assert!(configs.websocket_upgrades.is_none());
+    }
+
+    #[test]
+    fn test_rate_limit_settings() {
+        let path = std::env::temp_dir()
+            .join(format!("conjunto-api-keys-{}.toml", Pubkey::new_unique()));
+        fs::write(&path, "[[keys]]\nkey = \"secret\"").unwrap();
+        let mut settings: DirectorSettings = toml::from_str(
+            r#"
+            [rate-limit]
+            key = "api-key"
+            requests-per-second = 50
+            send-transactions-per-second = 5
+            send-transactions-burst = 20
+            max-subscriptions = 100
+            "#,
+        )
+        .unwrap();
+
+        // API keys can only be told apart once they are authenticated
+        let Err(DirectorError::InvalidConfig(errors)) =
+            settings.try_into_configs()
+        else {
+            panic!("expected invalid config");
+        };
+        assert_eq!(
+            errors,
+            vec!["rate-limit key 'api-key' requires auth keys-file to be set"]
+        );
+
+        settings.auth.keys_file = Some(path.to_str().unwrap().to_string());
+        let configs = settings.try_into_configs().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            configs.rpc.rate_limit,
+            RateLimitConfig {
+                key: RateLimitKey::ApiKey,
+                requests: Some(Quota {
+                    per_second: 50,
+                    burst: 50,
+                }),
+                send_transactions: Some(Quota {
+                    per_second: 5,
+                    burst: 20,
+                }),
+                max_connections: None,
+                max_subscriptions: Some(100),
+            }
+        );
+        assert_eq!(configs.pubsub.rate_limit, configs.rpc.rate_limit);
+    }
+
+    #[test]
+    fn test_print_config_roundtrips() {
+        let settings = DirectorSettings {
+            commitment: Some(CommitmentLevel::Finalized),
+            routing: RoutingSettings {
+                validators: vec![ValidatorSettings {
+                    authority: Pubkey::new_unique().to_string(),
+                    cluster: "http://localhost:7799,ws://localhost:7900"
+                        .parse()
+                        .unwrap(),
+                }],
+                ..RoutingSettings::default()
+            },
+            ..DirectorSettings::default()
+        };
+        let printed = settings.to_toml().unwrap();
+        assert_eq!(
+            toml::from_str::<DirectorSettings>(&printed).unwrap(),
+            settings
+        );
+    }
+}
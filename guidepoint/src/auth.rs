@@ -0,0 +1,408 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::reload;
+
+/// Prefix of the websocket subprotocol via which clients can provide their
+/// API key, i.e. `api-key.<key>`, since browsers can't set headers on
+/// websocket requests
+pub const API_KEY_SUBPROTOCOL_PREFIX: &str = "api-key.";
+
+/// How often the keys file is checked for changes unless configured
+/// otherwise
+pub const DEFAULT_KEYS_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Finds the subprotocol carrying the API key among the ones the client
+/// listed in the `Sec-WebSocket-Protocol` header
+pub fn api_key_subprotocol(protocols: &str) -> Option<&str> {
+    protocols.split(',').map(str::trim).find(|protocol| {
+        protocol
+            .strip_prefix(API_KEY_SUBPROTOCOL_PREFIX)
+            .is_some_and(|key| !key.is_empty())
+    })
+}
+
+// -----------------
+// AuthError
+// -----------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    MissingApiKey,
+    InvalidApiKey,
+    MethodNotAllowed { method: String },
+}
+
+impl AuthError {
+    /// Stable name of the reason the client was rejected
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthError::MissingApiKey => "missingApiKey",
+            AuthError::InvalidApiKey => "invalidApiKey",
+            AuthError::MethodNotAllowed { .. } => "methodNotAllowed",
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::MissingApiKey => write!(f, "Missing API key"),
+            AuthError::InvalidApiKey => write!(f, "Invalid API key"),
+            AuthError::MethodNotAllowed { method } => {
+                write!(f, "Method {} is not allowed for this API key", method)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ApiKeysError {
+    #[error("Failed to read API keys file '{0}': {1}")]
+    Io(String, #[source] io::Error),
+    #[error("Invalid API keys file '{0}': {1}")]
+    Toml(String, #[source] toml::de::Error),
+    #[error("Invalid API keys file '{0}': {1}")]
+    Invalid(String, String),
+}
+
+// -----------------
+// ApiKeysFile
+// -----------------
+/// The format of the keys file, i.e.
+///
+/// ```toml
+/// [[keys]]
+/// key = "partner-secret"
+/// # All methods are allowed if left out
+/// methods = ["getAccountInfo", "sendTransaction", "accountSubscribe"]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApiKeyEntry {
+    key: String,
+    methods: Option<Vec<String>>,
+}
+
+/// The RPC and subscription methods a key may use, `None` allows all of
+/// them.
+/// Unsubscribing is always allowed since it only ends what the client was
+/// allowed to start, so are the `ping` messages some websocket clients
+/// send to keep their connection alive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ApiKeyAccess {
+    methods: Option<HashSet<String>>,
+}
+
+impl ApiKeyAccess {
+    fn allows(&self, method: &str) -> bool {
+        match &self.methods {
+            Some(methods) => {
+                methods.contains(method)
+                    || method.ends_with("Unsubscribe")
+                    || matches!(method, "ping" | "pong")
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ApiKeys {
+    by_key: HashMap<String, ApiKeyAccess>,
+    /// When the file was modified at the time it was loaded
+    modified: Option<SystemTime>,
+}
+
+impl ApiKeys {
+    fn load(path: &Path) -> Result<Self, ApiKeysError> {
+        let display = || path.display().to_string();
+        let modified = reload::modified(path).ok();
+        let content = fs::read_to_string(path)
+            .map_err(|err| ApiKeysError::Io(display(), err))?;
+        let file: ApiKeysFile = toml::from_str(&content)
+            .map_err(|err| ApiKeysError::Toml(display(), err))?;
+
+        let mut by_key = HashMap::new();
+        for (idx, entry) in file.keys.into_iter().enumerate() {
+            let invalid = |msg: String| ApiKeysError::Invalid(display(), msg);
+            if entry.key.trim().is_empty() {
+                return Err(invalid(format!("key {} is empty", idx)));
+            }
+            if matches!(&entry.methods, Some(methods) if methods.is_empty()) {
+                return Err(invalid(format!(
+                    "key {} allows no methods, leave out methods to allow all",
+                    idx
+                )));
+            }
+            let access = ApiKeyAccess {
+                methods: entry
+                    .methods
+                    .map(|methods| methods.into_iter().collect::<HashSet<_>>()),
+            };
+            if by_key.insert(entry.key, access).is_some() {
+                return Err(invalid(format!("key {} is listed twice", idx)));
+            }
+        }
+        Ok(Self { by_key, modified })
+    }
+}
+
+// -----------------
+// ApiKeyAuth
+// -----------------
+/// Checks the API keys clients provide against the keys file.
+/// The file is reloaded once it changes if [ApiKeyAuth::spawn_reloader]
+/// was called, a file that became invalid keeps the previous keys in use.
+/// The default doesn't require any key.
+#[derive(Clone, Default)]
+pub struct ApiKeyAuth {
+    inner: Option<Arc<ApiKeyAuthInner>>,
+}
+
+struct ApiKeyAuthInner {
+    path: PathBuf,
+    reload_interval: Duration,
+    keys: RwLock<ApiKeys>,
+    reloader_spawned: AtomicBool,
+}
+
+impl ApiKeyAuth {
+    /// Requires clients to provide one of the keys in the file at `path`
+    /// which is checked for changes every `reload_interval`
+    pub fn from_file(
+        path: impl Into<PathBuf>,
+        reload_interval: Duration,
+    ) -> Result<Self, ApiKeysError> {
+        let path = path.into();
+        let keys = ApiKeys::load(&path)?;
+        debug!(
+            "Loaded {} API key(s) from {}",
+            keys.by_key.len(),
+            path.display()
+        );
+        Ok(Self {
+            inner: Some(Arc::new(ApiKeyAuthInner {
+                path,
+                reload_interval,
+                keys: RwLock::new(keys),
+                reloader_spawned: AtomicBool::new(false),
+            })),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Checks that the client provided a known key
+    pub fn authenticate(&self, api_key: Option<&str>) -> Result<(), AuthError> {
+        self.check(api_key, |_| Ok(()))
+    }
+
+    /// Checks that the client provided a known key which allows `method`
+    pub fn authorize(
+        &self,
+        api_key: Option<&str>,
+        method: &str,
+    ) -> Result<(), AuthError> {
+        self.check(api_key, |access| {
+            if access.allows(method) {
+                Ok(())
+            } else {
+                Err(AuthError::MethodNotAllowed {
+                    method: method.to_string(),
+                })
+            }
+        })
+    }
+
+    fn check(
+        &self,
+        api_key: Option<&str>,
+        check_access: impl FnOnce(&ApiKeyAccess) -> Result<(), AuthError>,
+    ) -> Result<(), AuthError> {
+        let Some(inner) = &self.inner else {
+            return Ok(());
+        };
+        let api_key = api_key.ok_or(AuthError::MissingApiKey)?;
+        let keys = inner.keys.read().expect("poisoned lock");
+        match keys.by_key.get(api_key) {
+            Some(access) => check_access(access),
+            None => Err(AuthError::InvalidApiKey),
+        }
+    }
+
+    /// Loads the keys file again if it was modified since it was last
+    /// loaded, returns whether it was
+    pub fn reload(&self) -> Result<bool, ApiKeysError> {
+        match &self.inner {
+            Some(inner) => inner.reload(),
+            None => Ok(false),
+        }
+    }
+
+    /// Starts reloading the keys file in the background until all clones of
+    /// this auth are dropped.
+    /// Servers sharing the same auth can all call this since only the first
+    /// call spawns the reloader.
+    pub fn spawn_reloader(&self) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        if inner.reloader_spawned.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        reload::spawn_reloader(
+            Arc::downgrade(inner),
+            inner.reload_interval,
+            "API keys",
+            ApiKeyAuthInner::reload,
+        );
+    }
+}
+
+impl ApiKeyAuthInner {
+    fn reload(&self) -> Result<bool, ApiKeysError> {
+        let modified = reload::modified(&self.path).map_err(|err| {
+            ApiKeysError::Io(self.path.display().to_string(), err)
+        })?;
+        if self.keys.read().expect("poisoned lock").modified == Some(modified) {
+            return Ok(false);
+        }
+        let keys = ApiKeys::load(&self.path)?;
+        info!(
+            "Reloaded {} API key(s) from {}",
+            keys.by_key.len(),
+            self.path.display()
+        );
+        *self.keys.write().expect("poisoned lock") = keys;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_test_tools::files::replace_file;
+
+    use super::*;
+
+    fn keys_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "conjunto-api-keys-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_default_auth_allows_everyone() {
+        let auth = ApiKeyAuth::default();
+        assert!(!auth.is_enabled());
+        assert_eq!(auth.authenticate(None), Ok(()));
+        assert_eq!(auth.authorize(None, "sendTransaction"), Ok(()));
+    }
+
+    #[test]
+    fn test_keys_allow_their_methods() {
+        let path = keys_file(
+            "methods",
+            r#"
+            [[keys]]
+            key = "all"
+
+            [[keys]]
+            key = "reads"
+            methods = ["getAccountInfo", "accountSubscribe"]
+            "#,
+        );
+        let auth =
+            ApiKeyAuth::from_file(&path, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(auth.authenticate(None), Err(AuthError::MissingApiKey));
+        assert_eq!(
+            auth.authenticate(Some("unknown")),
+            Err(AuthError::InvalidApiKey)
+        );
+        assert_eq!(auth.authorize(Some("all"), "sendTransaction"), Ok(()));
+        assert_eq!(auth.authorize(Some("reads"), "getAccountInfo"), Ok(()));
+        assert_eq!(auth.authorize(Some("reads"), "accountUnsubscribe"), Ok(()));
+        assert_eq!(auth.authorize(Some("reads"), "ping"), Ok(()));
+        assert_eq!(
+            auth.authorize(Some("reads"), "sendTransaction"),
+            Err(AuthError::MethodNotAllowed {
+                method: "sendTransaction".to_string()
+            })
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_keys_files_are_rejected() {
+        for (name, content) in [
+            ("empty-key", "[[keys]]\nkey = ' '"),
+            ("no-methods", "[[keys]]\nkey = 'a'\nmethods = []"),
+            ("duplicate", "[[keys]]\nkey = 'a'\n[[keys]]\nkey = 'a'"),
+            ("unknown-field", "[[keys]]\nkey = 'a'\nname = 'partner'"),
+        ] {
+            let path = keys_file(name, content);
+            assert!(
+                ApiKeyAuth::from_file(&path, Duration::from_secs(1)).is_err(),
+                "{} should be rejected",
+                name
+            );
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reload_picks_up_changes_and_keeps_keys_of_invalid_files() {
+        let path = keys_file("reload", "[[keys]]\nkey = 'old'");
+        let auth =
+            ApiKeyAuth::from_file(&path, Duration::from_secs(1)).unwrap();
+        assert!(!auth.reload().unwrap());
+
+        replace_file(&path, "[[keys]]\nkey = 'new'");
+        assert!(auth.reload().unwrap());
+        assert_eq!(auth.authenticate(Some("new")), Ok(()));
+        assert_eq!(
+            auth.authenticate(Some("old")),
+            Err(AuthError::InvalidApiKey)
+        );
+
+        replace_file(&path, "[[keys]]\nkey =");
+        assert!(auth.reload().is_err());
+        assert_eq!(auth.authenticate(Some("new")), Ok(()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_api_key_subprotocol() {
+        assert_eq!(
+            api_key_subprotocol("graphql-ws, api-key.secret"),
+            Some("api-key.secret")
+        );
+        assert_eq!(api_key_subprotocol("api-key."), None);
+        assert_eq!(api_key_subprotocol("graphql-ws"), None);
+    }
+}
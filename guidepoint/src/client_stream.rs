@@ -0,0 +1,273 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use tokio_rustls::server::TlsStream;
+
+/// Request heads larger than this aren't inspected, the server reading the
+/// request rejects them anyway
+const MAX_REQUEST_HEAD_LEN: usize = 16 * 1024;
+const MAX_REQUEST_HEADERS: usize = 100;
+
+/// How long clients get to send the head of their first request before
+/// their connection is dropped
+const REQUEST_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+// -----------------
+// ClientStream
+// -----------------
+/// The connection of a client, with TLS already terminated if the server
+/// is configured for it
+pub struct ClientStream {
+    inner: Inner,
+    /// Bytes read to inspect the request head, they are replayed before
+    /// anything else is read from the connection
+    read_ahead: Vec<u8>,
+    read_ahead_pos: usize,
+}
+
+enum Inner {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl ClientStream {
+    pub fn plain(stream: TcpStream) -> Self {
+        Self::new(Inner::Plain(stream))
+    }
+
+    pub(crate) fn tls(stream: TlsStream<TcpStream>) -> Self {
+        Self::new(Inner::Tls(Box::new(stream)))
+    }
+
+    fn new(inner: Inner) -> Self {
+        Self {
+            inner,
+            read_ahead: Vec::new(),
+            read_ahead_pos: 0,
+        }
+    }
+
+    /// Reads the head of the first request to tell if it asks for a
+    /// websocket upgrade, needs to be called before anything else is read.
+    /// The head is still read by whoever reads the stream afterwards.
+    /// Requests that can't be parsed aren't upgrades, it is left to the
+    /// server reading them to respond.
+    pub async fn is_websocket_upgrade(&mut self) -> io::Result<bool> {
+        tokio::time::timeout(REQUEST_HEAD_TIMEOUT, self.read_request_head())
+            .await
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "request head")
+            })?
+    }
+
+    async fn read_request_head(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 1024];
+        loop {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_REQUEST_HEADERS];
+            let mut request = httparse::Request::new(&mut headers);
+            match request.parse(&self.read_ahead) {
+                Ok(httparse::Status::Complete(_)) => {
+                    return Ok(is_websocket_upgrade(&request))
+                }
+                Ok(httparse::Status::Partial) => {}
+                Err(_) => return Ok(false),
+            }
+            if self.read_ahead.len() >= MAX_REQUEST_HEAD_LEN {
+                return Ok(false);
+            }
+            let len = self.inner.read(&mut chunk).await?;
+            if len == 0 {
+                return Ok(false);
+            }
+            self.read_ahead.extend_from_slice(&chunk[..len]);
+        }
+    }
+}
+
+fn is_websocket_upgrade(request: &httparse::Request) -> bool {
+    request.method == Some("GET")
+        && request.headers.iter().any(|header| {
+            header.name.eq_ignore_ascii_case("upgrade")
+                && std::str::from_utf8(header.value).is_ok_and(|value| {
+                    value.split(',').any(|protocol| {
+                        protocol.trim().eq_ignore_ascii_case("websocket")
+                    })
+                })
+        })
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read_ahead_pos < this.read_ahead.len() {
+            let remaining = &this.read_ahead[this.read_ahead_pos..];
+            let len = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..len]);
+            this.read_ahead_pos += len;
+            if this.read_ahead_pos == this.read_ahead.len() {
+                this.read_ahead = Vec::new();
+                this.read_ahead_pos = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl AsyncRead for Inner {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Inner::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Inner {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Inner::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Inner::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Inner::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Inner::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+// -----------------
+// WebSocketUpgrade
+// -----------------
+/// A websocket upgrade request the RPC server received when serving RPC and
+/// pubsub on a single port, the request head wasn't consumed yet
+pub struct WebSocketUpgrade {
+    pub stream: ClientStream,
+    pub addr: SocketAddr,
+}
+
+pub type WebSocketUpgradeSender = UnboundedSender<WebSocketUpgrade>;
+pub type WebSocketUpgradeReceiver = UnboundedReceiver<WebSocketUpgrade>;
+
+/// Channel the RPC server hands websocket upgrades to the pubsub server with
+pub fn websocket_upgrade_channel(
+) -> (WebSocketUpgradeSender, WebSocketUpgradeReceiver) {
+    unbounded_channel()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    use super::*;
+
+    /// Sends `request` from a client and returns the server side of the
+    /// connection
+    async fn connection_with_request(request: &'static str) -> ClientStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            // Split up to make sure partial heads are read to the end
+            for part in request.as_bytes().chunks(16) {
+                client.write_all(part).await.unwrap();
+                client.flush().await.unwrap();
+            }
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        ClientStream::plain(stream)
+    }
+
+    #[tokio::test]
+    async fn test_websocket_upgrades_are_detected_and_replayed() {
+        let request = "GET /chain HTTP/1.1\r\nHost: localhost\r\n\
+            Connection: Upgrade\r\nUpgrade: WebSocket\r\n\r\n";
+        let mut stream = connection_with_request(request).await;
+
+        assert!(stream.is_websocket_upgrade().await.unwrap());
+
+        let mut read = String::new();
+        stream.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, request);
+    }
+
+    #[tokio::test]
+    async fn test_other_requests_are_not_upgrades() {
+        for request in [
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+            "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "not http at all\r\n\r\n",
+            "GET / HTTP/1.1\r\n",
+        ] {
+            let mut stream = connection_with_request(request).await;
+
+            assert!(!stream.is_websocket_upgrade().await.unwrap());
+
+            let mut read = String::new();
+            stream.read_to_string(&mut read).await.unwrap();
+            assert_eq!(read, request);
+        }
+    }
+}
@@ -1,2 +1,10 @@
+pub mod auth;
+pub mod client_stream;
 mod guide_strategy_resolver;
+pub mod rate_limit;
+mod reload;
+mod route_override;
+pub mod shutdown;
+pub mod tls;
 pub use guide_strategy_resolver::GuideStrategyResolver;
+pub use route_override::{RouteOverride, ROUTE_OVERRIDE_HEADER};
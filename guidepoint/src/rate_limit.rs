@@ -0,0 +1,615 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::auth::ApiKeyAuth;
+
+/// Header via which clients can provide their API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Query parameter via which clients can provide their API key, i.e. when
+/// their websocket library doesn't support setting headers
+pub const API_KEY_QUERY_PARAM: &str = "api-key";
+
+/// Idle clients are forgotten every time this many requests were checked
+const PRUNE_EVERY: u64 = 1024;
+
+// -----------------
+// RateLimitConfig
+// -----------------
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// What clients are told apart by
+    pub key: RateLimitKey,
+    /// Budget of each client for RPC requests except `sendTransaction`,
+    /// `None` doesn't limit them
+    pub requests: Option<Quota>,
+    /// Budget of each client for `sendTransaction` requests, `None` doesn't
+    /// limit them
+    pub send_transactions: Option<Quota>,
+    /// Websocket connections each client may have open at once
+    pub max_connections: Option<u32>,
+    /// Subscriptions each client may have across all its websocket
+    /// connections
+    pub max_subscriptions: Option<u32>,
+}
+
+impl RateLimitConfig {
+    fn tracks_connections(&self) -> bool {
+        self.max_connections.is_some() || self.max_subscriptions.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Clients are limited by their IP address
+    #[default]
+    Ip,
+    /// Clients that provide an API key the [ApiKeyAuth] knows are limited by
+    /// it, all others by their IP address
+    ApiKey,
+}
+
+impl fmt::Display for RateLimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimitKey::Ip => write!(f, "ip"),
+            RateLimitKey::ApiKey => write!(f, "api-key"),
+        }
+    }
+}
+
+impl FromStr for RateLimitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip" => Ok(RateLimitKey::Ip),
+            "api-key" => Ok(RateLimitKey::ApiKey),
+            _ => Err(format!(
+                "Invalid rate limit key '{}', expected 'ip' or 'api-key'",
+                s
+            )),
+        }
+    }
+}
+
+/// Allows `per_second` requests per second on average and up to `burst`
+/// requests at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+// -----------------
+// ClientKey
+// -----------------
+/// Identifies a client whose usage is limited
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    Ip(IpAddr),
+    ApiKey(String),
+}
+
+impl fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientKey::Ip(ip) => write!(f, "{}", ip),
+            // Only a prefix so that logs don't leak the keys
+            ClientKey::ApiKey(key) => {
+                let prefix: String = key.chars().take(4).collect();
+                write!(f, "api key {}...", prefix)
+            }
+        }
+    }
+}
+
+/// Resolves the API key the client provided via the [API_KEY_QUERY_PARAM]
+/// or the [API_KEY_HEADER], the query takes precedence
+pub fn api_key_from_query_and_header(
+    query: Option<&str>,
+    header: Option<&str>,
+) -> Option<String> {
+    query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == API_KEY_QUERY_PARAM)
+        .map(|(_, value)| value)
+        .into_iter()
+        .chain(header)
+        .map(str::trim)
+        .find(|key| !key.is_empty())
+        .map(str::to_string)
+}
+
+// -----------------
+// RateLimitExceeded
+// -----------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitExceeded {
+    Requests { retry_after: Duration },
+    SendTransactions { retry_after: Duration },
+    Connections { max: u32 },
+    Subscriptions { max: u32 },
+}
+
+impl RateLimitExceeded {
+    /// Stable name of the limit that was exceeded
+    pub fn limit(&self) -> &'static str {
+        match self {
+            RateLimitExceeded::Requests { .. } => "requests",
+            RateLimitExceeded::SendTransactions { .. } => "sendTransactions",
+            RateLimitExceeded::Connections { .. } => "connections",
+            RateLimitExceeded::Subscriptions { .. } => "subscriptions",
+        }
+    }
+
+    /// How long the client needs to wait until its request would be allowed,
+    /// `None` for limits that only free up once connections or
+    /// subscriptions end
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RateLimitExceeded::Requests { retry_after }
+            | RateLimitExceeded::SendTransactions { retry_after } => {
+                Some(*retry_after)
+            }
+            RateLimitExceeded::Connections { .. }
+            | RateLimitExceeded::Subscriptions { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for RateLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimitExceeded::Requests { retry_after } => write!(
+                f,
+                "Request rate limit exceeded, retry after {}ms",
+                retry_after.as_millis()
+            ),
+            RateLimitExceeded::SendTransactions { retry_after } => write!(
+                f,
+                "Transaction rate limit exceeded, retry after {}ms",
+                retry_after.as_millis()
+            ),
+            RateLimitExceeded::Connections { max } => {
+                write!(f, "Connection limit of {} per client exceeded", max)
+            }
+            RateLimitExceeded::Subscriptions { max } => {
+                write!(f, "Subscription limit of {} per client exceeded", max)
+            }
+        }
+    }
+}
+
+// -----------------
+// RateLimiter
+// -----------------
+/// Tracks the usage of each client and checks it against the
+/// [RateLimitConfig].
+/// Request budgets are token buckets which refill continuously.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    inner: Arc<RateLimiterInner>,
+}
+
+#[derive(Default)]
+struct RateLimiterInner {
+    config: RateLimitConfig,
+    clients: Mutex<HashMap<ClientKey, ClientUsage>>,
+    checks: AtomicU64,
+}
+
+#[derive(Default)]
+struct ClientUsage {
+    requests: Option<TokenBucket>,
+    send_transactions: Option<TokenBucket>,
+    connections: u32,
+    subscriptions: u32,
+}
+
+impl ClientUsage {
+    /// Forgetting an idle client doesn't change what it is allowed to do
+    fn is_idle(&self, config: &RateLimitConfig, now: Instant) -> bool {
+        let is_full =
+            |bucket: &Option<TokenBucket>, quota: Option<Quota>| match (
+                bucket, quota,
+            ) {
+                (Some(bucket), Some(quota)) => bucket.is_full(quota, now),
+                _ => true,
+            };
+        self.connections == 0
+            && self.subscriptions == 0
+            && is_full(&self.requests, config.requests)
+            && is_full(&self.send_transactions, config.send_transactions)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            inner: Arc::new(RateLimiterInner {
+                config,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.inner.config
+    }
+
+    /// The key the client is limited by.
+    /// API keys are only used once `auth` authenticated them, otherwise
+    /// clients could escape their limits by making up a new key for each
+    /// request.
+    pub fn client_key(
+        &self,
+        ip: IpAddr,
+        api_key: Option<&str>,
+        auth: &ApiKeyAuth,
+    ) -> ClientKey {
+        match (self.inner.config.key, api_key) {
+            (RateLimitKey::ApiKey, Some(api_key))
+                if auth.is_enabled()
+                    && auth.authenticate(Some(api_key)).is_ok() =>
+            {
+                ClientKey::ApiKey(api_key.to_string())
+            }
+            _ => ClientKey::Ip(ip),
+        }
+    }
+
+    /// Takes one request of the client's budget for the RPC `method`,
+    /// `sendTransaction` has a budget of its own
+    pub fn check_request(
+        &self,
+        client: &ClientKey,
+        method: &str,
+    ) -> Result<(), RateLimitExceeded> {
+        self.check_request_at(client, method, Instant::now())
+    }
+
+    fn check_request_at(
+        &self,
+        client: &ClientKey,
+        method: &str,
+        now: Instant,
+    ) -> Result<(), RateLimitExceeded> {
+        let config = &self.inner.config;
+        let is_send_transaction = method == "sendTransaction";
+        let quota = if is_send_transaction {
+            config.send_transactions
+        } else {
+            config.requests
+        };
+        let Some(quota) = quota else {
+            return Ok(());
+        };
+
+        let mut clients = self.inner.clients.lock().expect("poisoned lock");
+        let checks = self.inner.checks.fetch_add(1, Ordering::Relaxed);
+        if checks % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            clients.retain(|_, usage| !usage.is_idle(config, now));
+        }
+        let usage = clients.entry(client.clone()).or_default();
+        let bucket = if is_send_transaction {
+            &mut usage.send_transactions
+        } else {
+            &mut usage.requests
+        };
+        bucket
+            .get_or_insert_with(|| TokenBucket::full(quota, now))
+            .take(quota, now)
+            .map_err(|retry_after| {
+                if is_send_transaction {
+                    RateLimitExceeded::SendTransactions { retry_after }
+                } else {
+                    RateLimitExceeded::Requests { retry_after }
+                }
+            })
+    }
+
+    /// Counts a websocket connection of the client until the returned
+    /// [ClientConnection] is dropped
+    pub fn open_connection(
+        &self,
+        client: ClientKey,
+    ) -> Result<ClientConnection, RateLimitExceeded> {
+        let config = &self.inner.config;
+        if config.tracks_connections() {
+            let mut clients = self.inner.clients.lock().expect("poisoned lock");
+            let usage = clients.entry(client.clone()).or_default();
+            if let Some(max) = config.max_connections {
+                if usage.connections >= max {
+                    return Err(RateLimitExceeded::Connections { max });
+                }
+            }
+            usage.connections += 1;
+        }
+        Ok(ClientConnection {
+            limiter: self.clone(),
+            client,
+            subscriptions: 0,
+        })
+    }
+}
+
+// -----------------
+// ClientConnection
+// -----------------
+/// A websocket connection of a client which holds on to its subscriptions
+/// until it is dropped
+pub struct ClientConnection {
+    limiter: RateLimiter,
+    client: ClientKey,
+    subscriptions: u32,
+}
+
+impl ClientConnection {
+    pub fn client(&self) -> &ClientKey {
+        &self.client
+    }
+
+    /// Adjusts the subscriptions of the connection by `delta` which is
+    /// negative when subscriptions end.
+    /// Fails without changing anything if the client would exceed its
+    /// subscriptions.
+    pub fn add_subscriptions(
+        &mut self,
+        delta: i64,
+    ) -> Result<(), RateLimitExceeded> {
+        let config = &self.limiter.inner.config;
+        if delta == 0 || !config.tracks_connections() {
+            return Ok(());
+        }
+        let mut clients =
+            self.limiter.inner.clients.lock().expect("poisoned lock");
+        let usage = clients.entry(self.client.clone()).or_default();
+        if delta > 0 {
+            let added = delta as u32;
+            if let Some(max) = config.max_subscriptions {
+                if usage.subscriptions + added > max {
+                    return Err(RateLimitExceeded::Subscriptions { max });
+                }
+            }
+            usage.subscriptions += added;
+            self.subscriptions += added;
+        } else {
+            let removed = (delta.unsigned_abs() as u32).min(self.subscriptions);
+            usage.subscriptions -= removed;
+            self.subscriptions -= removed;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        if !self.limiter.inner.config.tracks_connections() {
+            return;
+        }
+        let mut clients = match self.limiter.inner.clients.lock() {
+            Ok(clients) => clients,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(usage) = clients.get_mut(&self.client) {
+            usage.connections = usage.connections.saturating_sub(1);
+            usage.subscriptions =
+                usage.subscriptions.saturating_sub(self.subscriptions);
+        }
+    }
+}
+
+// -----------------
+// TokenBucket
+// -----------------
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(quota: Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refilled(&self, quota: Quota, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated_at);
+        (self.tokens + elapsed.as_secs_f64() * quota.per_second as f64)
+            .min(quota.burst as f64)
+    }
+
+    fn is_full(&self, quota: Quota, now: Instant) -> bool {
+        self.refilled(quota, now) >= quota.burst as f64
+    }
+
+    /// Takes a token or returns how long it takes until one is available
+    fn take(&mut self, quota: Quota, now: Instant) -> Result<(), Duration> {
+        self.tokens = self.refilled(quota, now);
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        if quota.per_second == 0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / quota.per_second as f64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn ip(last: u8) -> ClientKey {
+        ClientKey::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+    }
+
+    #[test]
+    fn test_requests_are_limited_per_client() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests: Some(Quota {
+                per_second: 1,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.check_request_at(&ip(1), "getSlot", now).is_ok());
+        assert!(limiter.check_request_at(&ip(1), "getSlot", now).is_ok());
+        assert_eq!(
+            limiter.check_request_at(&ip(1), "getSlot", now),
+            Err(RateLimitExceeded::Requests {
+                retry_after: Duration::from_secs(1)
+            })
+        );
+        assert!(limiter.check_request_at(&ip(2), "getSlot", now).is_ok());
+
+        let later = now + Duration::from_millis(1500);
+        assert!(limiter.check_request_at(&ip(1), "getSlot", later).is_ok());
+        assert!(limiter.check_request_at(&ip(1), "getSlot", later).is_err());
+    }
+
+    #[test]
+    fn test_send_transaction_has_its_own_budget() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests: Some(Quota {
+                per_second: 10,
+                burst: 1,
+            }),
+            send_transactions: Some(Quota {
+                per_second: 10,
+                burst: 1,
+            }),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let client = ip(1);
+        assert!(limiter.check_request_at(&client, "getSlot", now).is_ok());
+        assert!(limiter
+            .check_request_at(&client, "sendTransaction", now)
+            .is_ok());
+        assert!(matches!(
+            limiter.check_request_at(&client, "sendTransaction", now),
+            Err(RateLimitExceeded::SendTransactions { .. })
+        ));
+
+        let unlimited = RateLimiter::default();
+        for _ in 0..100 {
+            assert!(unlimited
+                .check_request(&client, "sendTransaction")
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_connections_and_subscriptions_end_with_connection() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_connections: Some(2),
+            max_subscriptions: Some(3),
+            ..Default::default()
+        });
+        let mut first = limiter.open_connection(ip(1)).unwrap();
+        let mut second = limiter.open_connection(ip(1)).unwrap();
+        assert_eq!(
+            limiter.open_connection(ip(1)).err(),
+            Some(RateLimitExceeded::Connections { max: 2 })
+        );
+        assert!(limiter.open_connection(ip(2)).is_ok());
+
+        first.add_subscriptions(2).unwrap();
+        assert_eq!(
+            second.add_subscriptions(2),
+            Err(RateLimitExceeded::Subscriptions { max: 3 })
+        );
+        second.add_subscriptions(1).unwrap();
+        // Unsubscribing more than the connection subscribed frees only its own
+        second.add_subscriptions(-5).unwrap();
+        second.add_subscriptions(1).unwrap();
+
+        drop(first);
+        let mut third = limiter.open_connection(ip(1)).unwrap();
+        third.add_subscriptions(2).unwrap();
+        assert!(third.add_subscriptions(1).is_err());
+    }
+
+    #[test]
+    fn test_client_key() {
+        let path = std::env::temp_dir().join(format!(
+            "conjunto-rate-limit-keys-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, "[[keys]]\nkey = \"key\"").unwrap();
+        let auth =
+            ApiKeyAuth::from_file(&path, Duration::from_secs(1)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let by_ip = RateLimiter::new(RateLimitConfig::default());
+        assert_eq!(
+            by_ip.client_key(addr, Some("key"), &auth),
+            ClientKey::Ip(addr)
+        );
+
+        let by_api_key = RateLimiter::new(RateLimitConfig {
+            key: RateLimitKey::ApiKey,
+            ..Default::default()
+        });
+        assert_eq!(
+            by_api_key.client_key(addr, Some("key"), &auth),
+            ClientKey::ApiKey("key".to_string())
+        );
+        assert_eq!(
+            by_api_key.client_key(addr, None, &auth),
+            ClientKey::Ip(addr)
+        );
+        // Made up keys don't get a budget of their own
+        assert_eq!(
+            by_api_key.client_key(addr, Some("unknown"), &auth),
+            ClientKey::Ip(addr)
+        );
+        assert_eq!(
+            by_api_key.client_key(addr, Some("key"), &ApiKeyAuth::default()),
+            ClientKey::Ip(addr)
+        );
+    }
+
+    #[test]
+    fn test_api_key_from_query_and_header() {
+        assert_eq!(
+            api_key_from_query_and_header(Some("a=1&api-key=abc"), Some("x")),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            api_key_from_query_and_header(Some("a=1"), Some(" xyz ")),
+            Some("xyz".to_string())
+        );
+        assert_eq!(api_key_from_query_and_header(Some("api-key="), None), None);
+        assert_eq!(api_key_from_query_and_header(None, None), None);
+    }
+
+    #[test]
+    fn test_rate_limit_key_from_str() {
+        assert_eq!("ip".parse(), Ok(RateLimitKey::Ip));
+        assert_eq!("api-key".parse(), Ok(RateLimitKey::ApiKey));
+        assert!("token".parse::<RateLimitKey>().is_err());
+    }
+}
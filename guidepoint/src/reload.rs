@@ -0,0 +1,43 @@
+use std::{
+    fmt, fs, io,
+    path::Path,
+    sync::Weak,
+    time::{Duration, SystemTime},
+};
+
+use log::*;
+
+/// When the file at `path` was last modified
+pub(crate) fn modified(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified())
+}
+
+/// Calls `reload` every `reload_interval` until the `target` is dropped.
+/// If reloading fails the target keeps what it loaded before, `what` names
+/// that in the warning.
+pub(crate) fn spawn_reloader<T, E>(
+    target: Weak<T>,
+    reload_interval: Duration,
+    what: &'static str,
+    reload: fn(&T) -> Result<bool, E>,
+) where
+    T: Send + Sync + 'static,
+    E: fmt::Display + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(reload_interval);
+        interval
+            .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately and the target was just loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let Some(target) = target.upgrade() else {
+                return;
+            };
+            if let Err(err) = reload(&target) {
+                warn!("Keeping the previous {}: {}", what, err);
+            }
+        }
+    });
+}
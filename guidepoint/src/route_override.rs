@@ -0,0 +1,113 @@
+use std::{fmt, str::FromStr};
+
+use conjunto_core::RequestEndpoint;
+
+/// Header via which clients can explicitly pick the backend of a request
+pub const ROUTE_OVERRIDE_HEADER: &str = "x-conjunto-route";
+
+/// Backend explicitly picked by the client which bypasses all guiding
+/// decisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOverride {
+    Chain,
+    Ephemeral,
+}
+
+impl RouteOverride {
+    /// Resolves the backend the client picked via the URL path (`/chain` or
+    /// `/ephemeral`) or the [ROUTE_OVERRIDE_HEADER].
+    /// The path takes precedence over the header, any other path results in
+    /// the request being guided as usual.
+    /// An invalid header value is an error since the client clearly intended
+    /// to pick a backend.
+    pub fn try_from_path_and_header(
+        path: &str,
+        header: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        if let Ok(route) = path.trim_matches('/').parse() {
+            return Ok(Some(route));
+        }
+        header.map(str::parse).transpose()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteOverride::Chain => "chain",
+            RouteOverride::Ephemeral => "ephemeral",
+        }
+    }
+}
+
+impl FromStr for RouteOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "chain" => Ok(RouteOverride::Chain),
+            "ephemeral" => Ok(RouteOverride::Ephemeral),
+            _ => Err(format!(
+                "Invalid route '{}', expected 'chain' or 'ephemeral'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RouteOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<RouteOverride> for RequestEndpoint {
+    fn from(route: RouteOverride) -> Self {
+        match route {
+            RouteOverride::Chain => RequestEndpoint::Chain,
+            RouteOverride::Ephemeral => RequestEndpoint::Ephemeral,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_override_from_path() {
+        assert_eq!(
+            RouteOverride::try_from_path_and_header("/chain", None),
+            Ok(Some(RouteOverride::Chain))
+        );
+        assert_eq!(
+            RouteOverride::try_from_path_and_header("/ephemeral/", None),
+            Ok(Some(RouteOverride::Ephemeral))
+        );
+        assert_eq!(
+            RouteOverride::try_from_path_and_header("/", None),
+            Ok(None)
+        );
+        assert_eq!(
+            RouteOverride::try_from_path_and_header("/unknown", None),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_route_override_from_header() {
+        assert_eq!(
+            RouteOverride::try_from_path_and_header("/", Some("Ephemeral")),
+            Ok(Some(RouteOverride::Ephemeral))
+        );
+        // Path takes precedence
+        assert_eq!(
+            RouteOverride::try_from_path_and_header(
+                "/chain",
+                Some("ephemeral")
+            ),
+            Ok(Some(RouteOverride::Chain))
+        );
+        assert!(
+            RouteOverride::try_from_path_and_header("/", Some("moon")).is_err()
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use log::*;
+use tokio::{
+    sync::watch,
+    task::{JoinHandle, JoinSet},
+};
+
+/// Carries the drain timeout once the server is shut down
+pub type ShutdownSender = watch::Sender<Option<Duration>>;
+pub type ShutdownReceiver = watch::Receiver<Option<Duration>>;
+
+pub fn shutdown_channel() -> (ShutdownSender, ShutdownReceiver) {
+    watch::channel(None)
+}
+
+// -----------------
+// ServerHandle
+// -----------------
+/// Shuts a server down.
+/// Dropping the handle leaves the server running until the process exits.
+pub struct ServerHandle {
+    name: &'static str,
+    shutdown_tx: ShutdownSender,
+    task: JoinHandle<ShutdownSummary>,
+}
+
+/// What happened to the connections and requests when the server shut down
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Connections that were open when the shutdown started
+    pub open_connections: usize,
+    /// Requests that were being served when the shutdown started, i.e.
+    /// transactions being forwarded to a backend. Only counted by the RPC
+    /// server, websocket clients are sent a close frame regardless.
+    pub in_flight_requests: usize,
+    /// Connections that were closed within the drain timeout
+    pub drained_connections: usize,
+    /// Connections that were still open when the drain timeout passed
+    pub aborted_connections: usize,
+}
+
+impl ServerHandle {
+    pub fn new(
+        name: &'static str,
+        shutdown_tx: ShutdownSender,
+        task: JoinHandle<ShutdownSummary>,
+    ) -> Self {
+        Self {
+            name,
+            shutdown_tx,
+            task,
+        }
+    }
+
+    /// Stops accepting connections and lets the server close the open ones.
+    /// Connections still open after `drain_timeout` are aborted.
+    pub async fn shutdown(self, drain_timeout: Duration) -> ShutdownSummary {
+        // Fails only if the server stopped already which the task tells
+        let _ = self.shutdown_tx.send(Some(drain_timeout));
+        match self.task.await {
+            Ok(summary) => summary,
+            Err(err) => {
+                error!("{} server failed: {:?}", self.name, err);
+                ShutdownSummary::default()
+            }
+        }
+    }
+}
+
+/// Resolves with the drain timeout once the server is shut down, never if
+/// the handle was dropped without shutting it down
+pub async fn shutdown_requested(
+    shutdown_rx: &mut ShutdownReceiver,
+) -> Duration {
+    loop {
+        if let Some(drain_timeout) = *shutdown_rx.borrow_and_update() {
+            return drain_timeout;
+        }
+        if shutdown_rx.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Waits for the connections to be closed, aborting the ones that take
+/// longer than the `drain_timeout`
+pub async fn drain_connections(
+    mut connections: JoinSet<()>,
+    drain_timeout: Duration,
+    in_flight_requests: usize,
+) -> ShutdownSummary {
+    let open_connections = connections.len();
+    let mut drained_connections = 0;
+    let _ = tokio::time::timeout(drain_timeout, async {
+        while connections.join_next().await.is_some() {
+            drained_connections += 1;
+        }
+    })
+    .await;
+    let aborted_connections = connections.len();
+    connections.shutdown().await;
+    ShutdownSummary {
+        open_connections,
+        in_flight_requests,
+        drained_connections,
+        aborted_connections,
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::*;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{crypto::ring, ServerConfig},
+    TlsAcceptor,
+};
+
+use crate::{client_stream::ClientStream, reload};
+
+/// How long clients get to complete the TLS handshake before their
+/// connection is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the certificate and key files are checked for changes unless
+/// configured otherwise
+pub const DEFAULT_TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read '{0}': {1}")]
+    Io(String, #[source] io::Error),
+    #[error("No certificate found in '{0}'")]
+    NoCertificates(String),
+    #[error("No private key found in '{0}'")]
+    NoPrivateKey(String),
+    #[error("Invalid certificate or private key: {0}")]
+    Rustls(#[from] tokio_rustls::rustls::Error),
+}
+
+// -----------------
+// TlsTermination
+// -----------------
+/// Terminates TLS for the connections of clients with the certificate and
+/// private key loaded from PEM files.
+/// The files are reloaded once either changes if
+/// [TlsTermination::spawn_reloader] was called, connections that are
+/// already established keep the certificate they were accepted with.
+#[derive(Clone)]
+pub struct TlsTermination {
+    inner: Arc<TlsTerminationInner>,
+}
+
+struct TlsTerminationInner {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    reload_interval: Duration,
+    acceptor: RwLock<LoadedAcceptor>,
+    reloader_spawned: AtomicBool,
+}
+
+struct LoadedAcceptor {
+    acceptor: TlsAcceptor,
+    /// When the certificate and key files were modified at the time they
+    /// were loaded
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl LoadedAcceptor {
+    fn load(cert_file: &Path, key_file: &Path) -> Result<Self, TlsError> {
+        let modified = (
+            reload::modified(cert_file).ok(),
+            reload::modified(key_file).ok(),
+        );
+        let certs = rustls_pemfile::certs(&mut open(cert_file)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| io_error(cert_file, err))?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificates(display(cert_file)));
+        }
+        let key = rustls_pemfile::private_key(&mut open(key_file)?)
+            .map_err(|err| io_error(key_file, err))?
+            .ok_or_else(|| TlsError::NoPrivateKey(display(key_file)))?;
+
+        // The provider is picked explicitly since other dependencies may
+        // enable other providers which would make the default ambiguous
+        let mut config = ServerConfig::builder_with_provider(Arc::new(
+            ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+        // Both servers only speak HTTP/1.1, websockets included
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            modified,
+        })
+    }
+}
+
+impl TlsTermination {
+    /// Loads the certificate chain and private key from the PEM files which
+    /// are checked for changes every `reload_interval`
+    pub fn from_files(
+        cert_file: impl Into<PathBuf>,
+        key_file: impl Into<PathBuf>,
+        reload_interval: Duration,
+    ) -> Result<Self, TlsError> {
+        let cert_file = cert_file.into();
+        let key_file = key_file.into();
+        let acceptor = LoadedAcceptor::load(&cert_file, &key_file)?;
+        Ok(Self {
+            inner: Arc::new(TlsTerminationInner {
+                cert_file,
+                key_file,
+                reload_interval,
+                acceptor: RwLock::new(acceptor),
+                reloader_spawned: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Completes the TLS handshake of a client with the certificate that is
+    /// currently loaded
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<ClientStream> {
+        let acceptor = self
+            .inner
+            .acceptor
+            .read()
+            .expect("poisoned lock")
+            .acceptor
+            .clone();
+        let stream = tokio::time::timeout(
+            TLS_HANDSHAKE_TIMEOUT,
+            acceptor.accept(stream),
+        )
+        .await
+        .map_err(|_| {
+            io::Error::new(io::ErrorKind::TimedOut, "TLS handshake")
+        })??;
+        Ok(ClientStream::tls(stream))
+    }
+
+    /// Loads the certificate and key files again if either was modified
+    /// since they were last loaded, returns whether they were
+    pub fn reload(&self) -> Result<bool, TlsError> {
+        self.inner.reload()
+    }
+
+    /// Starts reloading the certificate and key files in the background
+    /// until all clones of this termination are dropped.
+    /// Servers sharing the same termination can all call this since only the
+    /// first call spawns the reloader.
+    pub fn spawn_reloader(&self) {
+        let inner = &self.inner;
+        if inner.reloader_spawned.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        reload::spawn_reloader(
+            Arc::downgrade(inner),
+            inner.reload_interval,
+            "TLS certificate",
+            TlsTerminationInner::reload,
+        );
+    }
+}
+
+impl TlsTerminationInner {
+    fn reload(&self) -> Result<bool, TlsError> {
+        let modified = (
+            Some(
+                reload::modified(&self.cert_file)
+                    .map_err(|err| io_error(&self.cert_file, err))?,
+            ),
+            Some(
+                reload::modified(&self.key_file)
+                    .map_err(|err| io_error(&self.key_file, err))?,
+            ),
+        );
+        if self.acceptor.read().expect("poisoned lock").modified == modified {
+            return Ok(false);
+        }
+        let acceptor = LoadedAcceptor::load(&self.cert_file, &self.key_file)?;
+        info!("Reloaded TLS certificate from {}", self.cert_file.display());
+        *self.acceptor.write().expect("poisoned lock") = acceptor;
+        Ok(true)
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| io_error(path, err))
+}
+
+fn io_error(path: &Path, err: io::Error) -> TlsError {
+    TlsError::Io(display(path), err)
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use conjunto_test_tools::{files::replace_file, tls::TestCertificate};
+
+    use super::*;
+
+    #[test]
+    fn test_invalid_files_are_rejected() {
+        let cert = TestCertificate::generate();
+        let (cert_file, key_file) = (&cert.cert_file, &cert.key_file);
+        let interval = Duration::from_secs(1);
+
+        assert!(matches!(
+            TlsTermination::from_files(key_file, key_file, interval),
+            Err(TlsError::NoCertificates(_))
+        ));
+        assert!(matches!(
+            TlsTermination::from_files(cert_file, cert_file, interval),
+            Err(TlsError::NoPrivateKey(_))
+        ));
+        assert!(matches!(
+            TlsTermination::from_files("/nonexistent.pem", key_file, interval),
+            Err(TlsError::Io(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_reload_picks_up_changes_and_keeps_cert_of_invalid_files() {
+        let mut cert = TestCertificate::generate();
+        let tls = TlsTermination::from_files(
+            &cert.cert_file,
+            &cert.key_file,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert!(!tls.reload().unwrap());
+
+        cert.renew();
+        assert!(tls.reload().unwrap());
+
+        replace_file(&cert.key_file, "not a key");
+        assert!(matches!(tls.reload(), Err(TlsError::NoPrivateKey(_))));
+    }
+}
@@ -1,4 +1,7 @@
-use dlp::pda::ephemeral_balance_pda_from_payer;
+use dlp::pda::{
+    delegation_record_pda_from_delegated_account,
+    ephemeral_balance_pda_from_payer,
+};
 use serde::{Deserialize, Serialize};
 use solana_sdk::{clock::Slot, pubkey::Pubkey};
 
@@ -15,6 +18,12 @@ impl AccountChainSnapshot {
     pub fn ephemeral_balance_pda(pubkey: &Pubkey) -> Pubkey {
         ephemeral_balance_pda_from_payer(pubkey, 0)
     }
+    pub fn delegation_record_pda(pubkey: &Pubkey) -> Pubkey {
+        delegation_record_pda_from_delegated_account(pubkey)
+    }
+    pub fn delegation_record_pda_owner() -> Pubkey {
+        dlp::ID
+    }
     pub fn ephemeral_balance_pda_owner() -> Pubkey {
         dlp::ID
     }
@@ -1,18 +1,24 @@
 use conjunto_core::{
     delegation_inconsistency::DelegationInconsistency,
-    delegation_record_parser::DelegationRecordParser, AccountProvider,
+    delegation_record_parser::DelegationRecordParser, errors::CoreError,
+    AccountProvider,
 };
 use dlp::{consts::DELEGATION_PROGRAM_ID, pda};
 use solana_sdk::{
-    account::Account, clock::Slot, pubkey::Pubkey, system_program,
+    account::Account, clock::Slot, commitment_config::CommitmentLevel,
+    pubkey::Pubkey, system_program,
 };
 
 use crate::{
     account_chain_snapshot::AccountChainSnapshot,
     account_chain_state::AccountChainState,
+    ephemeral_balance_snapshot::EphemeralBalanceSnapshot,
     errors::{LockboxError, LockboxResult},
 };
 
+/// Most accounts a single `getMultipleAccounts` request may ask for
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
 pub struct AccountChainSnapshotProvider<
     T: AccountProvider,
     U: DelegationRecordParser,
@@ -36,34 +42,148 @@ impl<T: AccountProvider, U: DelegationRecordParser>
         pubkey: &Pubkey,
         min_context_slot: Option<Slot>,
     ) -> LockboxResult<AccountChainSnapshot> {
-        let delegation_pda =
-            pda::delegation_record_pda_from_delegated_account(pubkey);
+        self.try_fetch_chain_snapshot_of_pubkey_with_commitment(
+            pubkey,
+            min_context_slot,
+            None,
+        )
+        .await
+    }
+
+    /// Same as [Self::try_fetch_chain_snapshot_of_pubkey], but reads the
+    /// chain state at the provided commitment instead of the one the
+    /// account provider was configured with.
+    pub async fn try_fetch_chain_snapshot_of_pubkey_with_commitment(
+        &self,
+        pubkey: &Pubkey,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> LockboxResult<AccountChainSnapshot> {
+        let mut snapshots = self
+            .try_fetch_chain_snapshots_of_chunk(
+                &[*pubkey],
+                min_context_slot,
+                commitment,
+            )
+            .await?;
+        Ok(snapshots.swap_remove(0))
+    }
+
+    /// Same as [Self::try_fetch_chain_snapshot_of_pubkey] for several
+    /// accounts at once.
+    /// The accounts and their delegation records are fetched together, in
+    /// as few requests as the limit of `getMultipleAccounts` allows.
+    pub async fn try_fetch_chain_snapshots_of_pubkeys(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+    ) -> LockboxResult<Vec<AccountChainSnapshot>> {
+        let mut snapshots = Vec::with_capacity(pubkeys.len());
+        // Each account is fetched together with its delegation record
+        for chunk in pubkeys.chunks(MAX_MULTIPLE_ACCOUNTS / 2) {
+            snapshots.extend(
+                self.try_fetch_chain_snapshots_of_chunk(
+                    chunk,
+                    min_context_slot,
+                    None,
+                )
+                .await?,
+            );
+        }
+        Ok(snapshots)
+    }
+
+    async fn try_fetch_chain_snapshots_of_chunk(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> LockboxResult<Vec<AccountChainSnapshot>> {
+        let fetched_pubkeys = pubkeys
+            .iter()
+            .flat_map(|pubkey| {
+                [
+                    *pubkey,
+                    pda::delegation_record_pda_from_delegated_account(pubkey),
+                ]
+            })
+            .collect::<Vec<_>>();
         // Fetch the current chain state for revelant accounts (all at once)
+        let (at_slot, fetched_accounts) = self
+            .account_provider
+            .get_multiple_accounts_with_commitment(
+                &fetched_pubkeys,
+                min_context_slot,
+                commitment,
+            )
+            .await?;
+        // If something went wrong in the fetch we stop, we should receive
+        // an account and its delegation record for each pubkey every time
+        if fetched_accounts.len() != fetched_pubkeys.len() {
+            return Err(LockboxError::InvalidFetch {
+                fetched_pubkeys,
+                fetched_accounts,
+            });
+        }
+        let mut fetched_accounts = fetched_accounts.into_iter();
+        Ok(pubkeys
+            .iter()
+            .map(|pubkey| {
+                // Extract the accounts we just fetched
+                let account = fetched_accounts.next().flatten();
+                let delegation_record_account =
+                    fetched_accounts.next().flatten();
+                // Parse the result into an AccountChainState
+                let chain_state = self
+                    .try_into_chain_state_from_fetched_accounts(
+                        pubkey,
+                        account,
+                        delegation_record_account,
+                    );
+                // Build the AccountChainSnapshot
+                AccountChainSnapshot {
+                    pubkey: *pubkey,
+                    at_slot,
+                    chain_state,
+                }
+            })
+            .collect())
+    }
+
+    /// Fetches the escrow the payer pays its fees from inside the ephemeral
+    /// validator, the escrow only counts if it's owned by the delegation
+    /// program
+    pub async fn try_fetch_ephemeral_balance_of_payer(
+        &self,
+        payer: &Pubkey,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> LockboxResult<EphemeralBalanceSnapshot> {
+        let ephemeral_balance_pda =
+            AccountChainSnapshot::ephemeral_balance_pda(payer);
         let (at_slot, mut fetched_accounts) = self
             .account_provider
-            .get_multiple_accounts(&[*pubkey, delegation_pda], min_context_slot)
+            .get_multiple_accounts_with_commitment(
+                &[ephemeral_balance_pda],
+                min_context_slot,
+                commitment,
+            )
             .await?;
-        // If something went wrong in the fetch we stop, we should receive 2 accounts exactly every time
-        if fetched_accounts.len() != 2 {
+        if fetched_accounts.len() != 1 {
             return Err(LockboxError::InvalidFetch {
-                fetched_pubkeys: vec![*pubkey, delegation_pda],
+                fetched_pubkeys: vec![ephemeral_balance_pda],
                 fetched_accounts,
             });
         }
-        // Extract the accounts we just fetched
-        let account = fetched_accounts.swap_remove(0);
-        let delegation_record_account = fetched_accounts.swap_remove(0);
-        // Parse the result into an AccountChainState
-        let chain_state = self.try_into_chain_state_from_fetched_accounts(
-            pubkey,
-            account,
-            delegation_record_account,
-        );
-        // Build the AccountChainSnapshot
-        Ok(AccountChainSnapshot {
-            pubkey: *pubkey,
+        let lamports = fetched_accounts
+            .swap_remove(0)
+            .filter(is_owned_by_delegation_program)
+            .map(|account| account.lamports);
+        Ok(EphemeralBalanceSnapshot {
+            payer: *payer,
+            pubkey: ephemeral_balance_pda,
             at_slot,
-            chain_state,
+            lamports,
         })
     }
 
@@ -136,6 +256,15 @@ impl<T: AccountProvider, U: DelegationRecordParser>
             .delegation_record_parser
             .try_parse(&delegation_record_account.data)
         {
+            Err(err @ CoreError::UnknownDelegationRecordVersion(_)) => {
+                AccountChainState::Undelegated {
+                    account,
+                    delegation_inconsistency:
+                        DelegationInconsistency::DelegationRecordVersionUnknown(
+                            err.to_string(),
+                        ),
+                }
+            }
             Err(err) => AccountChainState::Undelegated {
                 account,
                 delegation_inconsistency:
@@ -1,39 +1,163 @@
+use std::mem::size_of;
+
 use conjunto_core::{
     delegation_record::{CommitFrequency, DelegationRecord},
     delegation_record_parser::DelegationRecordParser,
     errors::{CoreError, CoreResult},
 };
+use dlp::state::utils::discriminator::AccountWithDiscriminator;
 
-pub struct DelegationRecordParserImpl;
+/// Size of the discriminator that precedes the record in the account data
+pub const DELEGATION_RECORD_DISCRIMINATOR_LEN: usize = 8;
+/// Size of the account data holding a delegation record of the current
+/// layout
+pub const DELEGATION_RECORD_DATA_LEN: usize =
+    DelegationRecordVersion::V1.data_len();
 
-impl DelegationRecordParser for DelegationRecordParserImpl {
-    fn try_parse(&self, data: &[u8]) -> CoreResult<DelegationRecord> {
-        parse_delegation_record(data)
+// -----------------
+// DelegationRecordVersion
+// -----------------
+/// The delegation record layouts written by the delegation program versions
+/// we know about. Each of them is identified by its discriminator together
+/// with the size of its data, since a layout may grow while keeping the
+/// discriminator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DelegationRecordVersion {
+    /// The layout of the delegation program we build against
+    V1,
+}
+
+impl DelegationRecordVersion {
+    pub const ALL: [DelegationRecordVersion; 1] = [DelegationRecordVersion::V1];
+
+    pub fn discriminator(&self) -> [u8; DELEGATION_RECORD_DISCRIMINATOR_LEN] {
+        match self {
+            DelegationRecordVersion::V1 => {
+                dlp::state::DelegationRecord::discriminator().to_bytes()
+            }
+        }
+    }
+
+    /// Size of the account data including the discriminator
+    pub const fn data_len(&self) -> usize {
+        match self {
+            DelegationRecordVersion::V1 => {
+                DELEGATION_RECORD_DISCRIMINATOR_LEN
+                    + size_of::<dlp::state::DelegationRecord>()
+            }
+        }
+    }
+
+    /// Detects the layout of the record by its discriminator and the exact
+    /// size of the data, fails with
+    /// [CoreError::UnknownDelegationRecordVersion] if it matches none of the
+    /// known layouts
+    pub fn detect(data: &[u8]) -> CoreResult<Self> {
+        if data.len() < min_data_len() {
+            return Err(data_too_short(data.len(), min_data_len()));
+        }
+        let discriminator = &data[..DELEGATION_RECORD_DISCRIMINATOR_LEN];
+        Self::ALL
+            .into_iter()
+            .find(|version| {
+                version.discriminator() == discriminator
+                    && version.data_len() == data.len()
+            })
+            .ok_or_else(|| {
+                CoreError::UnknownDelegationRecordVersion(format!(
+                    "discriminator {:?} of data with {} bytes matches no known layout",
+                    discriminator,
+                    data.len()
+                ))
+            })
     }
+
+    /// Parses data holding exactly a record of this layout
+    pub fn try_parse(&self, data: &[u8]) -> CoreResult<DelegationRecord> {
+        if data.len() < self.data_len() {
+            return Err(data_too_short(data.len(), self.data_len()));
+        }
+        if data.len() > self.data_len() {
+            return Err(CoreError::FailedToParseDelegationRecord(format!(
+                "data has {} bytes, but {:?} records have {}",
+                data.len(),
+                self,
+                self.data_len()
+            )));
+        }
+        let (discriminator, record) =
+            data.split_at(DELEGATION_RECORD_DISCRIMINATOR_LEN);
+        if discriminator != self.discriminator() {
+            return Err(CoreError::FailedToParseDelegationRecord(format!(
+                "discriminator {:?} does not match {:?} of {:?}",
+                discriminator,
+                self.discriminator(),
+                self
+            )));
+        }
+        match self {
+            DelegationRecordVersion::V1 => parse_v1(record),
+        }
+    }
+}
+
+fn min_data_len() -> usize {
+    DelegationRecordVersion::ALL
+        .iter()
+        .map(DelegationRecordVersion::data_len)
+        .min()
+        .unwrap_or(DELEGATION_RECORD_DISCRIMINATOR_LEN)
 }
 
-fn parse_delegation_record(data: &[u8]) -> CoreResult<DelegationRecord> {
-    // bytemuck fails with TargetAlignmentGreaterAndInputNotAligned when the data isn't
-    // properly aligned. This happens sporadically depending on how the data was stored, i.e.
-    // only in debug mode and is fine in release mode or if we add unrelated code before the call.
-    // The below forces the data to be aligned since vecs are always aligned.
-    // NOTE: I didn't find 100% confirmation that vecs are always correctly aligned, but
-    // the issue I encountered was fixed by this change.
-    // TODO(thlorenz): with this fix we copy data and should revisit this to avoid that
-    let aligned_data = data[8..].to_vec();
+fn data_too_short(len: usize, min_len: usize) -> CoreError {
+    CoreError::FailedToParseDelegationRecord(format!(
+        "data has {} bytes, but needs at least {}",
+        len, min_len
+    ))
+}
+
+fn parse_v1(record: &[u8]) -> CoreResult<DelegationRecord> {
+    // Account data has no alignment guarantees, thus we read the record
+    // into a properly aligned value on the stack instead of casting in place
     let state =
-        bytemuck::try_from_bytes::<dlp::state::DelegationRecord>(&aligned_data)
-            .map_err(|err| {
-                CoreError::FailedToParseDelegationRecord(format!(
-                    "Failed to deserialize DelegationRecord: {}",
-                    err
-                ))
-            })?;
+        bytemuck::try_pod_read_unaligned::<dlp::state::DelegationRecord>(
+            record,
+        )
+        .map_err(|err| {
+            CoreError::FailedToParseDelegationRecord(format!(
+                "failed to read record: {}",
+                err
+            ))
+        })?;
     Ok(DelegationRecord {
         authority: state.authority,
         owner: state.owner,
         delegation_slot: state.delegation_slot,
-        commit_frequency: CommitFrequency::Millis(state.commit_frequency_ms),
+        commit_frequency: CommitFrequency::from_commit_frequency_ms(
+            state.commit_frequency_ms,
+        ),
         lamports: state.lamports,
     })
 }
+
+// -----------------
+// Parsers
+// -----------------
+/// Parses delegation records of any known layout
+pub struct DelegationRecordParserImpl;
+
+impl DelegationRecordParser for DelegationRecordParserImpl {
+    fn try_parse(&self, data: &[u8]) -> CoreResult<DelegationRecord> {
+        DelegationRecordVersion::detect(data)?.try_parse(data)
+    }
+}
+
+/// Only parses delegation records of one layout, i.e. to reject records
+/// written by other versions of the delegation program
+pub struct DelegationRecordParserForVersion(pub DelegationRecordVersion);
+
+impl DelegationRecordParser for DelegationRecordParserForVersion {
+    fn try_parse(&self, data: &[u8]) -> CoreResult<DelegationRecord> {
+        self.0.try_parse(data)
+    }
+}
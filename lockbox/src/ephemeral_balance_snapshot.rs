@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+/// State of the escrow a payer pays its fees from inside the ephemeral
+/// validator, see [crate::account_chain_snapshot::AccountChainSnapshot::ephemeral_balance_pda]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EphemeralBalanceSnapshot {
+    pub payer: Pubkey,
+    pub pubkey: Pubkey,
+    pub at_slot: Slot,
+    /// The escrowed lamports, `None` if the escrow does not exist or is not
+    /// owned by the delegation program
+    pub lamports: Option<u64>,
+}
+
+impl EphemeralBalanceSnapshot {
+    pub fn is_escrowed(&self) -> bool {
+        self.lamports.is_some()
+    }
+}
@@ -3,4 +3,5 @@ pub mod account_chain_snapshot_provider;
 pub mod account_chain_snapshot_shared;
 pub mod account_chain_state;
 pub mod delegation_record_parser_impl;
+pub mod ephemeral_balance_snapshot;
 pub mod errors;
@@ -1,17 +1,25 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
 use conjunto_core::{
     delegation_inconsistency::DelegationInconsistency,
     delegation_record::{CommitFrequency, DelegationRecord},
+    errors::CoreResult,
+    AccountProvider,
 };
 use conjunto_lockbox::{
     account_chain_snapshot::AccountChainSnapshot,
     account_chain_snapshot_provider::AccountChainSnapshotProvider,
     account_chain_state::AccountChainState,
+    delegation_record_parser_impl::{
+        DelegationRecordParserImpl, DELEGATION_RECORD_DATA_LEN,
+    },
 };
 use conjunto_test_tools::{
     account_provider_stub::AccountProviderStub,
     accounts::{
         account_owned_by_delegation_program, account_owned_by_system_program,
-        account_with_data, delegated_account_ids,
+        account_with_data, delegated_account_ids, DELEGATION_PROGRAM_ID,
     },
     delegation_record_parser_stub::DelegationRecordParserStub,
 };
@@ -322,9 +330,177 @@ async fn test_snapshot_delegation_record_data_invalid() {
                 account,
                 delegation_inconsistency:
                     DelegationInconsistency::DelegationRecordDataInvalid(
-                        "Failed to parse account data".to_string()
+                        "Failed to parse delegation record: Test error"
+                            .to_string()
                     ),
             }
         }
     );
 }
+
+#[tokio::test]
+async fn test_snapshot_delegation_record_version_unknown() {
+    let (pubkey, delegation_record_pubkey) = delegated_account_ids();
+
+    let mut account_provider = AccountProviderStub::default();
+    account_provider.at_slot = EXPECTED_SLOT;
+    account_provider.add(pubkey, account_owned_by_delegation_program());
+    account_provider.add(
+        delegation_record_pubkey,
+        Account {
+            owner: DELEGATION_PROGRAM_ID,
+            data: vec![255; DELEGATION_RECORD_DATA_LEN],
+            ..Account::default()
+        },
+    );
+    let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+        account_provider,
+        DelegationRecordParserImpl,
+    );
+
+    let chain_snapshot = account_chain_snapshot_provider
+        .try_fetch_chain_snapshot_of_pubkey(&pubkey, None)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        chain_snapshot.chain_state,
+        AccountChainState::Undelegated {
+            delegation_inconsistency:
+                DelegationInconsistency::DelegationRecordVersionUnknown(_),
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_ephemeral_balance_of_payer() {
+    let payer = Keypair::new().pubkey();
+    let ephemeral_balance_pda =
+        AccountChainSnapshot::ephemeral_balance_pda(&payer);
+
+    let account_chain_snapshot_provider = setup(
+        vec![(
+            ephemeral_balance_pda,
+            Account {
+                lamports: 1_000,
+                ..account_owned_by_delegation_program()
+            },
+        )],
+        None,
+    );
+
+    let ephemeral_balance = account_chain_snapshot_provider
+        .try_fetch_ephemeral_balance_of_payer(&payer, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(ephemeral_balance.payer, payer);
+    assert_eq!(ephemeral_balance.pubkey, ephemeral_balance_pda);
+    assert_eq!(ephemeral_balance.at_slot, EXPECTED_SLOT);
+    assert_eq!(ephemeral_balance.lamports, Some(1_000));
+    assert!(ephemeral_balance.is_escrowed());
+}
+
+#[tokio::test]
+async fn test_ephemeral_balance_of_payer_not_owned_by_delegation_program() {
+    let payer = Keypair::new().pubkey();
+    let ephemeral_balance_pda =
+        AccountChainSnapshot::ephemeral_balance_pda(&payer);
+
+    let account_chain_snapshot_provider = setup(
+        vec![(ephemeral_balance_pda, account_owned_by_system_program())],
+        None,
+    );
+
+    let ephemeral_balance = account_chain_snapshot_provider
+        .try_fetch_ephemeral_balance_of_payer(&payer, None, None)
+        .await
+        .unwrap();
+
+    assert_eq!(ephemeral_balance.lamports, None);
+    assert!(!ephemeral_balance.is_escrowed());
+}
+
+/// Records how many accounts each request fetches
+#[derive(Default)]
+struct RequestRecordingAccountProvider {
+    inner: AccountProviderStub,
+    requests: Arc<Mutex<Vec<usize>>>,
+}
+
+#[async_trait]
+impl AccountProvider for RequestRecordingAccountProvider {
+    async fn get_account(
+        &self,
+        pubkey: &Pubkey,
+        min_context_slot: Option<Slot>,
+    ) -> CoreResult<(Slot, Option<Account>)> {
+        self.requests.lock().unwrap().push(1);
+        self.inner.get_account(pubkey, min_context_slot).await
+    }
+
+    async fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+    ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        self.requests.lock().unwrap().push(pubkeys.len());
+        self.inner
+            .get_multiple_accounts(pubkeys, min_context_slot)
+            .await
+    }
+}
+
+#[tokio::test]
+async fn test_snapshots_of_pubkeys_are_fetched_in_batches() {
+    let (delegated_id, delegation_record_pubkey) = delegated_account_ids();
+    let delegation_record = dummy_delegation_record();
+    let mut account_provider = RequestRecordingAccountProvider::default();
+    account_provider.inner.at_slot = EXPECTED_SLOT;
+    account_provider
+        .inner
+        .add(delegated_id, account_owned_by_delegation_program());
+    account_provider.inner.add(
+        delegation_record_pubkey,
+        account_owned_by_delegation_program(),
+    );
+    let requests = account_provider.requests.clone();
+    let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+        account_provider,
+        DelegationRecordParserStub::new(Some(delegation_record.clone())),
+    );
+    // The delegated account ends up in the second batch
+    let mut pubkeys = (0..59).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+    pubkeys.insert(55, delegated_id);
+
+    let snapshots = account_chain_snapshot_provider
+        .try_fetch_chain_snapshots_of_pubkeys(&pubkeys, None)
+        .await
+        .unwrap();
+
+    // Each account is fetched together with its delegation record
+    assert_eq!(*requests.lock().unwrap(), vec![100, 20]);
+    assert_eq!(snapshots.len(), pubkeys.len());
+    for (snapshot, pubkey) in snapshots.iter().zip(&pubkeys) {
+        assert_eq!(snapshot.pubkey, *pubkey);
+        assert_eq!(snapshot.at_slot, EXPECTED_SLOT);
+    }
+    assert_eq!(
+        snapshots[55].chain_state,
+        AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record,
+        }
+    );
+    assert_eq!(
+        snapshots
+            .iter()
+            .filter(|snapshot| matches!(
+                snapshot.chain_state,
+                AccountChainState::Delegated { .. }
+            ))
+            .count(),
+        1
+    );
+}
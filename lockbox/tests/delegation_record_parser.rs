@@ -1,9 +1,14 @@
 use conjunto_core::{
     delegation_record::{CommitFrequency, DelegationRecord},
     delegation_record_parser::DelegationRecordParser,
+    errors::CoreError,
 };
-use conjunto_lockbox::delegation_record_parser_impl::DelegationRecordParserImpl;
-use solana_sdk::pubkey;
+use conjunto_lockbox::delegation_record_parser_impl::{
+    DelegationRecordParserForVersion, DelegationRecordParserImpl,
+    DelegationRecordVersion, DELEGATION_RECORD_DATA_LEN,
+    DELEGATION_RECORD_DISCRIMINATOR_LEN,
+};
+use solana_sdk::{pubkey, pubkey::Pubkey};
 
 #[test]
 fn test_delegation_record_parser() {
@@ -33,3 +38,252 @@ fn test_delegation_record_parser() {
         }
     );
 }
+
+fn parse_commit_frequency(commit_frequency_ms: u64) -> CommitFrequency {
+    let mut data = [0u8; size_of::<DelegationRecord>() + 8];
+    dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms,
+        lamports: 500,
+    }
+    .to_bytes_with_discriminator(&mut data)
+    .unwrap();
+    DelegationRecordParserImpl
+        .try_parse(&data)
+        .unwrap()
+        .commit_frequency
+}
+
+#[test]
+fn test_delegation_record_parser_commit_frequency_sentinel() {
+    assert_eq!(
+        parse_commit_frequency(1_000),
+        CommitFrequency::Millis(1_000)
+    );
+    assert_eq!(parse_commit_frequency(0), CommitFrequency::Millis(0));
+    assert_eq!(
+        parse_commit_frequency(u32::MAX as u64),
+        CommitFrequency::Never
+    );
+}
+
+// -----------------
+// Versions
+// -----------------
+#[test]
+fn test_delegation_record_version_detect() {
+    let data = record_data(dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    });
+    assert_eq!(
+        DelegationRecordVersion::detect(&data).unwrap(),
+        DelegationRecordVersion::V1
+    );
+    assert_eq!(
+        DelegationRecordVersion::V1.data_len(),
+        DELEGATION_RECORD_DATA_LEN
+    );
+    let record = DelegationRecordParserForVersion(DelegationRecordVersion::V1)
+        .try_parse(&data)
+        .unwrap();
+    assert_eq!(record, DelegationRecordParserImpl.try_parse(&data).unwrap());
+}
+
+#[test]
+fn test_delegation_record_unknown_version() {
+    let data = vec![255; DELEGATION_RECORD_DATA_LEN];
+    assert!(matches!(
+        DelegationRecordVersion::detect(&data),
+        Err(CoreError::UnknownDelegationRecordVersion(_))
+    ));
+    assert!(matches!(
+        DelegationRecordParserImpl.try_parse(&data),
+        Err(CoreError::UnknownDelegationRecordVersion(_))
+    ));
+    // A parser for one version reports other layouts as invalid data
+    assert!(matches!(
+        DelegationRecordParserForVersion(DelegationRecordVersion::V1)
+            .try_parse(&data),
+        Err(CoreError::FailedToParseDelegationRecord(_))
+    ));
+}
+
+#[test]
+fn test_delegation_record_grown_layout_is_unknown() {
+    // A later layout could append fields while keeping the discriminator
+    let mut data = record_data(dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    });
+    data.extend([0; 8]);
+    assert!(matches!(
+        DelegationRecordVersion::detect(&data),
+        Err(CoreError::UnknownDelegationRecordVersion(_))
+    ));
+    assert!(matches!(
+        DelegationRecordParserImpl.try_parse(&data),
+        Err(CoreError::UnknownDelegationRecordVersion(_))
+    ));
+    assert!(matches!(
+        DelegationRecordParserForVersion(DelegationRecordVersion::V1)
+            .try_parse(&data),
+        Err(CoreError::FailedToParseDelegationRecord(_))
+    ));
+}
+
+// -----------------
+// Property Tests
+// -----------------
+const ITERATIONS: usize = 2_000;
+
+/// Deterministic xorshift generator so failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next_u64() % max as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+
+    fn pubkey(&mut self) -> Pubkey {
+        Pubkey::new_from_array(self.bytes(32).try_into().unwrap())
+    }
+}
+
+fn record_data(record: dlp::state::DelegationRecord) -> Vec<u8> {
+    let mut data = [0u8; DELEGATION_RECORD_DATA_LEN];
+    record.to_bytes_with_discriminator(&mut data).unwrap();
+    data.to_vec()
+}
+
+fn valid_discriminator() -> Vec<u8> {
+    record_data(dlp::state::DelegationRecord {
+        authority: Pubkey::default(),
+        owner: Pubkey::default(),
+        delegation_slot: 0,
+        commit_frequency_ms: 0,
+        lamports: 0,
+    })[..DELEGATION_RECORD_DISCRIMINATOR_LEN]
+        .to_vec()
+}
+
+#[test]
+fn test_delegation_record_parser_random_bytes_never_panic() {
+    let mut rng = Rng(0x5eed);
+    let discriminator = valid_discriminator();
+    for _ in 0..ITERATIONS {
+        let len = rng.below(2 * DELEGATION_RECORD_DATA_LEN);
+        let mut data = rng.bytes(len);
+        // Make the discriminator match half of the time to get past that check
+        if rng.below(2) == 0 && len >= DELEGATION_RECORD_DISCRIMINATOR_LEN {
+            data[..DELEGATION_RECORD_DISCRIMINATOR_LEN]
+                .copy_from_slice(&discriminator);
+        }
+        let res = DelegationRecordParserImpl.try_parse(&data);
+        let expected_ok = len == DELEGATION_RECORD_DATA_LEN
+            && data[..DELEGATION_RECORD_DISCRIMINATOR_LEN] == discriminator;
+        assert_eq!(res.is_ok(), expected_ok, "data: {:?}", data);
+    }
+}
+
+#[test]
+fn test_delegation_record_parser_roundtrips_at_any_offset() {
+    let mut rng = Rng(0xdecaf);
+    for _ in 0..ITERATIONS {
+        let state = dlp::state::DelegationRecord {
+            authority: rng.pubkey(),
+            owner: rng.pubkey(),
+            delegation_slot: rng.next_u64(),
+            commit_frequency_ms: rng.next_u64(),
+            lamports: rng.next_u64(),
+        };
+        // Place the data at a random offset inside a larger buffer followed
+        // by random bytes so it isn't aligned
+        let offset = rng.below(16);
+        let trailing = rng.below(16);
+        let mut buf = rng.bytes(offset);
+        buf.extend(record_data(state));
+        buf.extend(rng.bytes(trailing));
+
+        let record = DelegationRecordParserImpl
+            .try_parse(&buf[offset..offset + DELEGATION_RECORD_DATA_LEN])
+            .unwrap();
+        assert_eq!(
+            record,
+            DelegationRecord {
+                authority: state.authority,
+                owner: state.owner,
+                delegation_slot: state.delegation_slot,
+                commit_frequency: CommitFrequency::from_commit_frequency_ms(
+                    state.commit_frequency_ms
+                ),
+                lamports: state.lamports,
+            }
+        );
+    }
+}
+
+#[test]
+fn test_delegation_record_parser_truncated_data() {
+    let data = record_data(dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    });
+    for len in 0..DELEGATION_RECORD_DATA_LEN {
+        let err = DelegationRecordParserImpl
+            .try_parse(&data[..len])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Failed to parse delegation record: data has {} bytes, but needs at least {}",
+                len, DELEGATION_RECORD_DATA_LEN
+            )
+        );
+    }
+}
+
+#[test]
+fn test_delegation_record_parser_invalid_discriminator() {
+    let mut rng = Rng(0xbad);
+    let valid = record_data(dlp::state::DelegationRecord {
+        authority: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        delegation_slot: 4,
+        commit_frequency_ms: 30_000,
+        lamports: 500,
+    });
+    for _ in 0..ITERATIONS {
+        let mut data = valid.clone();
+        let idx = rng.below(DELEGATION_RECORD_DISCRIMINATOR_LEN);
+        data[idx] ^= 1 + rng.below(255) as u8;
+        let err = DelegationRecordParserImpl.try_parse(&data).unwrap_err();
+        assert!(
+            err.to_string().contains("discriminator"),
+            "unexpected error: {}",
+            err
+        );
+    }
+}
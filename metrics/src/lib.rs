@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::*;
+use prometheus::{
+    exponential_buckets, register_histogram_vec_with_registry,
+    register_histogram_with_registry, register_int_counter_vec_with_registry,
+    register_int_gauge_with_registry, Encoder, Histogram, HistogramVec,
+    IntCounterVec, IntGauge, Registry, TextEncoder,
+};
+
+/// Content type of the text format returned by [encode]
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// -----------------
+// Metrics
+// -----------------
+lazy_static! {
+    static ref REGISTRY: Registry =
+        Registry::new_custom(Some("conjunto".to_string()), None)
+            .expect("valid registry prefix");
+
+    // RPC
+    static ref RPC_REQUESTS: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "rpc_requests_total",
+            "RPC requests by method, route and result",
+            &["method", "route", "result"],
+            REGISTRY
+        )
+        .unwrap();
+    static ref TRANSACTION_ENDPOINTS: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "transaction_endpoints_total",
+            "Transactions by the endpoint they were routed to",
+            &["endpoint"],
+            REGISTRY
+        )
+        .unwrap();
+    static ref UNROUTABLE_TRANSACTIONS: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "unroutable_transactions_total",
+            "Unroutable transactions by reason",
+            &["reason"],
+            REGISTRY
+        )
+        .unwrap();
+    static ref UPSTREAM_LATENCY: HistogramVec =
+        register_histogram_vec_with_registry!(
+            "upstream_request_duration_seconds",
+            "Duration of requests forwarded to the backends",
+            &["backend", "method"],
+            latency_buckets(),
+            REGISTRY
+        )
+        .unwrap();
+
+    // Transwise
+    static ref SNAPSHOT_FETCH_LATENCY: Histogram =
+        register_histogram_with_registry!(
+            "snapshot_fetch_duration_seconds",
+            "Duration of fetching the chain snapshots of a request's accounts",
+            latency_buckets(),
+            REGISTRY
+        )
+        .unwrap();
+
+    // Pubsub
+    static ref PUBSUB_ROUTED_MESSAGES: IntCounterVec =
+        register_int_counter_vec_with_registry!(
+            "pubsub_routed_messages_total",
+            "Pubsub client messages by the endpoint they were routed to",
+            &["endpoint"],
+            REGISTRY
+        )
+        .unwrap();
+    static ref PUBSUB_CONNECTIONS: IntGauge =
+        register_int_gauge_with_registry!(
+            "pubsub_connections",
+            "Active pubsub client connections",
+            REGISTRY
+        )
+        .unwrap();
+    static ref PUBSUB_SUBSCRIPTIONS: IntGauge =
+        register_int_gauge_with_registry!(
+            "pubsub_subscriptions",
+            "Active pubsub subscriptions of all clients",
+            REGISTRY
+        )
+        .unwrap();
+}
+
+/// From 1ms to ~16s
+fn latency_buckets() -> Vec<f64> {
+    exponential_buckets(0.001, 2.0, 15).expect("valid buckets")
+}
+
+// -----------------
+// Recording
+// -----------------
+pub fn inc_rpc_request(method: &str, route: &str, success: bool) {
+    let result = if success { "ok" } else { "error" };
+    RPC_REQUESTS
+        .with_label_values(&[method, route, result])
+        .inc();
+}
+
+pub fn inc_transaction_endpoint(endpoint: &str) {
+    TRANSACTION_ENDPOINTS.with_label_values(&[endpoint]).inc();
+}
+
+pub fn inc_unroutable_transaction(reason: &str) {
+    UNROUTABLE_TRANSACTIONS.with_label_values(&[reason]).inc();
+}
+
+pub fn observe_upstream_latency(
+    backend: &str,
+    method: &str,
+    duration: Duration,
+) {
+    UPSTREAM_LATENCY
+        .with_label_values(&[backend, method])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn observe_snapshot_fetch_latency(duration: Duration) {
+    SNAPSHOT_FETCH_LATENCY.observe(duration.as_secs_f64());
+}
+
+pub fn inc_pubsub_routed_message(endpoint: &str) {
+    PUBSUB_ROUTED_MESSAGES.with_label_values(&[endpoint]).inc();
+}
+
+pub fn inc_pubsub_connections() {
+    PUBSUB_CONNECTIONS.inc();
+}
+
+pub fn dec_pubsub_connections() {
+    PUBSUB_CONNECTIONS.dec();
+}
+
+/// Adjusts the active subscriptions by `delta` which is negative when
+/// subscriptions end
+pub fn add_pubsub_subscriptions(delta: i64) {
+    PUBSUB_SUBSCRIPTIONS.add(delta);
+}
+
+// -----------------
+// Export
+// -----------------
+/// All metrics in the Prometheus text format
+pub fn encode() -> String {
+    let mut buf = vec![];
+    if let Err(err) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buf) {
+        error!("Failed to encode metrics: {:?}", err);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_includes_recorded_metrics() {
+        inc_rpc_request("getSlot", "guided", true);
+        inc_transaction_endpoint("ephemeral");
+        inc_unroutable_transaction("WRITABLE_SYSVAR");
+        observe_upstream_latency("chain", "getSlot", Duration::from_millis(3));
+        add_pubsub_subscriptions(2);
+
+        let metrics = encode();
+        assert!(metrics.contains(
+            r#"conjunto_rpc_requests_total{method="getSlot",result="ok",route="guided"}"#
+        ));
+        assert!(metrics.contains(
+            r#"conjunto_transaction_endpoints_total{endpoint="ephemeral"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"conjunto_unroutable_transactions_total{reason="WRITABLE_SYSVAR"} 1"#
+        ));
+        assert!(metrics.contains(
+            r#"conjunto_upstream_request_duration_seconds_count{backend="chain",method="getSlot"} 1"#
+        ));
+        assert!(metrics.contains("conjunto_pubsub_subscriptions 2"));
+    }
+}
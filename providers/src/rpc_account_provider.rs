@@ -4,7 +4,9 @@ use solana_account_decoder::UiAccountEncoding;
 use solana_rpc_client::nonblocking::rpc_client::RpcClient;
 use solana_rpc_client_api::config::RpcAccountInfoConfig;
 use solana_sdk::{
-    account::Account, clock::Slot, commitment_config::CommitmentConfig,
+    account::Account,
+    clock::Slot,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
     pubkey::Pubkey,
 };
 
@@ -57,12 +59,29 @@ impl AccountProvider for RpcAccountProvider {
         pubkeys: &[Pubkey],
         min_context_slot: Option<Slot>,
     ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        self.get_multiple_accounts_with_commitment(
+            pubkeys,
+            min_context_slot,
+            None,
+        )
+        .await
+    }
+
+    async fn get_multiple_accounts_with_commitment(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> CoreResult<(Slot, Vec<Option<Account>>)> {
+        let commitment = commitment
+            .map(|commitment| CommitmentConfig { commitment })
+            .unwrap_or_else(|| self.rpc_client.commitment());
         let response = self
             .rpc_client
             .get_multiple_accounts_with_config(
                 pubkeys,
                 RpcAccountInfoConfig {
-                    commitment: Some(self.rpc_client.commitment()),
+                    commitment: Some(commitment),
                     min_context_slot,
                     encoding: Some(UiAccountEncoding::Base64Zstd),
                     data_slice: None,
@@ -0,0 +1,26 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::Duration,
+};
+
+/// Replaces the file at `path` with `content`, a reload never sees it
+/// partially written.
+/// If the file existed its modification time is moved a second past the
+/// previous one so that the change is noticed even on file systems with a
+/// coarse timestamp granularity.
+pub fn replace_file(path: &Path, content: impl AsRef<[u8]>) {
+    let previous_modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp).unwrap();
+    file.write_all(content.as_ref()).unwrap();
+    if let Some(modified) = previous_modified {
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+    }
+    drop(file);
+    fs::rename(tmp, path).unwrap();
+}
@@ -2,4 +2,8 @@ pub mod account_provider_stub;
 pub mod accounts;
 pub mod delegation_record_parser_stub;
 pub mod diagnostics;
+pub mod files;
+pub mod mock_rpc_server;
+pub mod mock_websocket_server;
 pub mod signature_status_provider_stub;
+pub mod tls;
@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use jsonrpsee::{
+    core::RpcResult,
+    server::{Server, ServerHandle},
+    types::{ErrorObject, ErrorObjectOwned, Params},
+    RpcModule,
+};
+use serde_json::{json, Value};
+use solana_sdk::{
+    account::Account, clock::Slot, hash::Hash, pubkey::Pubkey,
+    signature::Signature, transaction,
+};
+
+/// Error code the Solana RPC uses when a transaction fails preflight
+const SEND_TRANSACTION_PREFLIGHT_FAILURE: i32 = -32002;
+/// Error code the Solana RPC uses when the node is behind the requested
+/// `minContextSlot`
+const MIN_CONTEXT_SLOT_NOT_REACHED: i32 = -32016;
+/// Error code the Solana RPC uses for `getHealth` when the node is behind
+const NODE_UNHEALTHY: i32 = -32005;
+
+struct MockRpcState {
+    slot: Slot,
+    slots_behind: u64,
+    accounts: HashMap<Pubkey, Account>,
+    signature_statuses: HashMap<Signature, transaction::Result<()>>,
+    latest_blockhash: Hash,
+    send_transaction_result: Result<Signature, String>,
+    send_transaction_delay: Duration,
+    simulation_err: Option<Value>,
+    simulated_accounts: HashMap<Pubkey, Account>,
+    sent_transactions: Vec<String>,
+    requested_methods: Vec<String>,
+}
+
+impl Default for MockRpcState {
+    fn default() -> Self {
+        Self {
+            slot: 0,
+            slots_behind: 0,
+            accounts: HashMap::new(),
+            signature_statuses: HashMap::new(),
+            latest_blockhash: Hash::default(),
+            send_transaction_result: Ok(Signature::default()),
+            send_transaction_delay: Duration::ZERO,
+            simulation_err: None,
+            simulated_accounts: HashMap::new(),
+            sent_transactions: Vec::new(),
+            requested_methods: Vec::new(),
+        }
+    }
+}
+
+type MockRpcStateShared = Arc<RwLock<MockRpcState>>;
+
+/// An in-process JSON-RPC server standing in for a chain or ephemeral
+/// validator RPC.
+/// It serves the scripted accounts, signature statuses and blockhash and
+/// records all transactions that are sent to it.
+pub struct MockRpcServer {
+    addr: SocketAddr,
+    state: MockRpcStateShared,
+    handle: ServerHandle,
+}
+
+impl MockRpcServer {
+    pub async fn start() -> Self {
+        let server = Server::builder()
+            .build("127.0.0.1:0")
+            .await
+            .expect("failed to bind mock rpc server");
+        let addr = server.local_addr().unwrap();
+
+        let state = MockRpcStateShared::default();
+        let module = create_rpc_module(state.clone());
+        let handle = server.start(module);
+
+        Self {
+            addr,
+            state,
+            handle,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn set_slot(&self, slot: Slot) {
+        self.state.write().unwrap().slot = slot;
+    }
+
+    /// Makes `getHealth` report that this server is behind the cluster by
+    /// the provided number of slots, `0` reports it as healthy
+    pub fn set_slots_behind(&self, slots_behind: u64) {
+        self.state.write().unwrap().slots_behind = slots_behind;
+    }
+
+    pub fn add_account(&self, pubkey: Pubkey, account: Account) {
+        self.state.write().unwrap().accounts.insert(pubkey, account);
+    }
+
+    pub fn add_signature_status(
+        &self,
+        signature: Signature,
+        status: transaction::Result<()>,
+    ) {
+        self.state
+            .write()
+            .unwrap()
+            .signature_statuses
+            .insert(signature, status);
+    }
+
+    pub fn set_latest_blockhash(&self, blockhash: Hash) {
+        self.state.write().unwrap().latest_blockhash = blockhash;
+    }
+
+    /// Determines the response for all following `sendTransaction` requests,
+    /// either the signature or a preflight failure with the given message.
+    pub fn set_send_transaction_result(
+        &self,
+        result: Result<Signature, String>,
+    ) {
+        self.state.write().unwrap().send_transaction_result = result;
+    }
+
+    /// Delays the responses to all following `sendTransaction` requests,
+    /// i.e. to keep them in flight.
+    pub fn set_send_transaction_delay(&self, delay: Duration) {
+        self.state.write().unwrap().send_transaction_delay = delay;
+    }
+
+    /// Makes all following `simulateTransaction` requests report that the
+    /// account has the provided state after the transaction executed.
+    /// Accounts without simulated state are reported unchanged.
+    pub fn add_simulated_account(&self, pubkey: Pubkey, account: Account) {
+        self.state
+            .write()
+            .unwrap()
+            .simulated_accounts
+            .insert(pubkey, account);
+    }
+
+    /// Makes all following `simulateTransaction` requests report that the
+    /// transaction failed with the provided error.
+    pub fn set_simulation_err(&self, err: Option<Value>) {
+        self.state.write().unwrap().simulation_err = err;
+    }
+
+    /// The encoded transactions that were sent to this server.
+    pub fn sent_transactions(&self) -> Vec<String> {
+        self.state.read().unwrap().sent_transactions.clone()
+    }
+
+    /// The methods of all requests this server received in order.
+    pub fn requested_methods(&self) -> Vec<String> {
+        self.state.read().unwrap().requested_methods.clone()
+    }
+}
+
+impl Drop for MockRpcServer {
+    fn drop(&mut self) {
+        let _ = self.handle.stop();
+    }
+}
+
+// -----------------
+// RPC Methods
+// -----------------
+fn create_rpc_module(
+    state: MockRpcStateShared,
+) -> RpcModule<MockRpcStateShared> {
+    let mut module = RpcModule::new(state);
+    register(&mut module, "getAccountInfo", |params, state| {
+        let mut seq = params.sequence();
+        let pubkey = parse_pubkey(seq.next()?)?;
+        ensure_min_context_slot(state, seq.optional_next()?)?;
+        let value = state.accounts.get(&pubkey).map(encode);
+        Ok(with_context(state.slot, json!(value)))
+    });
+    register(&mut module, "getMultipleAccounts", |params, state| {
+        let mut seq = params.sequence();
+        let pubkeys: Vec<String> = seq.next()?;
+        ensure_min_context_slot(state, seq.optional_next()?)?;
+        let value = pubkeys
+            .into_iter()
+            .map(|pubkey| {
+                let pubkey = parse_pubkey(pubkey)?;
+                Ok(state.accounts.get(&pubkey).map(encode))
+            })
+            .collect::<RpcResult<Vec<_>>>()?;
+        Ok(with_context(state.slot, json!(value)))
+    });
+    register(&mut module, "getSignatureStatuses", |params, state| {
+        let signatures: Vec<String> = params.sequence().next()?;
+        let value = signatures
+            .into_iter()
+            .map(|signature| {
+                let signature = signature
+                    .parse::<Signature>()
+                    .map_err(|err| invalid_params(err.to_string()))?;
+                Ok(state.signature_statuses.get(&signature).map(|status| {
+                    json!({
+                        "slot": state.slot,
+                        "confirmations": null,
+                        "status": status,
+                        "err": status.clone().err(),
+                        "confirmationStatus": "finalized",
+                    })
+                }))
+            })
+            .collect::<RpcResult<Vec<_>>>()?;
+        Ok(with_context(state.slot, json!(value)))
+    });
+    register(&mut module, "getLatestBlockhash", |_params, state| {
+        Ok(with_context(
+            state.slot,
+            json!({
+                "blockhash": state.latest_blockhash.to_string(),
+                "lastValidBlockHeight": state.slot + 150,
+            }),
+        ))
+    });
+    register(&mut module, "simulateTransaction", |params, state| {
+        let mut seq = params.sequence();
+        let _data: String = seq.next()?;
+        let config: Option<Value> = seq.optional_next()?;
+        let addresses = config
+            .as_ref()
+            .and_then(|config| config.pointer("/accounts/addresses"))
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        let accounts = addresses
+            .into_iter()
+            .map(|address| {
+                let pubkey = parse_pubkey(
+                    address.as_str().unwrap_or_default().to_string(),
+                )?;
+                Ok(state
+                    .simulated_accounts
+                    .get(&pubkey)
+                    .or_else(|| state.accounts.get(&pubkey))
+                    .map(encode))
+            })
+            .collect::<RpcResult<Vec<_>>>()?;
+        Ok(with_context(
+            state.slot,
+            json!({
+                "err": state.simulation_err,
+                "logs": [],
+                "accounts": accounts,
+                "unitsConsumed": 0,
+            }),
+        ))
+    });
+    register(&mut module, "getSlot", |_params, state| {
+        Ok(json!(state.slot))
+    });
+    register(&mut module, "getHealth", |_params, state| {
+        match state.slots_behind {
+            0 => Ok(json!("ok")),
+            slots_behind => Err(ErrorObject::owned(
+                NODE_UNHEALTHY,
+                format!("Node is behind by {} slots", slots_behind),
+                Some(json!({ "numSlotsBehind": slots_behind })),
+            )),
+        }
+    });
+    register(&mut module, "getVersion", |_params, _state| {
+        Ok(json!({ "solana-core": "2.2.0", "feature-set": 0 }))
+    });
+    module
+        .register_async_method("sendTransaction", |params, state| async move {
+            let data: String = params.sequence().next()?;
+            let (result, delay) = {
+                let mut state = state.write().unwrap();
+                state.requested_methods.push("sendTransaction".to_string());
+                state.sent_transactions.push(data);
+                (
+                    state.send_transaction_result.clone(),
+                    state.send_transaction_delay,
+                )
+            };
+            tokio::time::sleep(delay).await;
+            match result {
+                Ok(signature) => Ok(json!(signature.to_string())),
+                Err(msg) => Err(ErrorObject::owned(
+                    SEND_TRANSACTION_PREFLIGHT_FAILURE,
+                    msg,
+                    None::<()>,
+                )),
+            }
+        })
+        .expect("failed to register sendTransaction");
+    module
+}
+
+/// Registers a read only method which records that it was requested
+fn register<F>(
+    module: &mut RpcModule<MockRpcStateShared>,
+    method: &'static str,
+    handler: F,
+) where
+    F: Fn(Params, &MockRpcState) -> RpcResult<Value> + Send + Sync + 'static,
+{
+    module
+        .register_method(method, move |params, state| {
+            state
+                .write()
+                .unwrap()
+                .requested_methods
+                .push(method.to_string());
+            handler(params, &state.read().unwrap())
+        })
+        .unwrap_or_else(|_| panic!("failed to register {}", method));
+}
+
+/// Fails like the Solana RPC does if the request asks for a slot that this
+/// server has not reached yet
+fn ensure_min_context_slot(
+    state: &MockRpcState,
+    config: Option<Value>,
+) -> RpcResult<()> {
+    let min_context_slot = config
+        .as_ref()
+        .and_then(|config| config.get("minContextSlot"))
+        .and_then(Value::as_u64);
+    match min_context_slot {
+        Some(min_context_slot) if min_context_slot > state.slot => {
+            Err(ErrorObject::owned(
+                MIN_CONTEXT_SLOT_NOT_REACHED,
+                "Minimum context slot has not been reached",
+                Some(json!({ "contextSlot": state.slot })),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn with_context(slot: Slot, value: Value) -> Value {
+    json!({
+        "context": { "slot": slot },
+        "value": value,
+    })
+}
+
+fn encode(account: &Account) -> Value {
+    json!({
+        "lamports": account.lamports,
+        "data": [BASE64_STANDARD.encode(&account.data), "base64"],
+        "owner": account.owner.to_string(),
+        "executable": account.executable,
+        "rentEpoch": account.rent_epoch,
+        "space": account.data.len(),
+    })
+}
+
+fn parse_pubkey(pubkey: String) -> RpcResult<Pubkey> {
+    pubkey
+        .parse()
+        .map_err(|err| invalid_params(format!("{:?}", err)))
+}
+
+fn invalid_params(msg: String) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        jsonrpsee::types::ErrorCode::InvalidParams.code(),
+        msg,
+        None::<()>,
+    )
+}
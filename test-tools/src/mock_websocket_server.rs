@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use log::*;
+use serde_json::Value;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    task::JoinHandle,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+type ScriptedResponses = Arc<Mutex<HashMap<String, Value>>>;
+
+/// An in-process websocket server standing in for a chain or ephemeral
+/// pubsub backend.
+/// It records all messages it receives, responds to requests for which a
+/// result was scripted and pushes notifications to all connected clients.
+pub struct MockWebsocketServer {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Message>>>,
+    responses: ScriptedResponses,
+    outgoing: broadcast::Sender<Message>,
+    connections: Arc<Mutex<usize>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockWebsocketServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind mock websocket server");
+        let addr = listener.local_addr().unwrap();
+
+        let received = Arc::<Mutex<Vec<Message>>>::default();
+        let responses = ScriptedResponses::default();
+        let connections = Arc::<Mutex<usize>>::default();
+        let (outgoing, _) = broadcast::channel(64);
+
+        let handle = {
+            let received = received.clone();
+            let responses = responses.clone();
+            let connections = connections.clone();
+            let outgoing = outgoing.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    *connections.lock().unwrap() += 1;
+                    tokio::spawn(serve_connection(
+                        stream,
+                        received.clone(),
+                        responses.clone(),
+                        outgoing.subscribe(),
+                    ));
+                }
+            })
+        };
+
+        Self {
+            addr,
+            received,
+            responses,
+            outgoing,
+            connections,
+            handle,
+        }
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Responds to every request with the given method with the provided
+    /// result, using the id of the request.
+    pub fn respond_to(&self, method: &str, result: Value) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(method.to_string(), result);
+    }
+
+    /// Sends the notification to all connected clients.
+    pub fn notify(&self, notification: Value) {
+        self.send(Message::Text(notification.to_string()));
+    }
+
+    /// Closes the connection to all connected clients.
+    pub fn close_connections(&self) {
+        self.send(Message::Close(None));
+    }
+
+    fn send(&self, msg: Message) {
+        if self.outgoing.send(msg).is_err() {
+            warn!("No client connected to mock websocket server");
+        }
+    }
+
+    pub fn connections(&self) -> usize {
+        *self.connections.lock().unwrap()
+    }
+
+    pub fn received(&self) -> Vec<Message> {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// All received text messages parsed as JSON.
+    pub fn received_json(&self) -> Vec<Value> {
+        self.received()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                Message::Text(txt) => serde_json::from_str(&txt).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Waits until at least `count` messages were received or the timeout
+    /// elapsed, returning all received messages.
+    pub async fn wait_for_received(
+        &self,
+        count: usize,
+        timeout: Duration,
+    ) -> Vec<Message> {
+        let _ = tokio::time::timeout(timeout, async {
+            while self.received.lock().unwrap().len() < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        self.received()
+    }
+}
+
+impl Drop for MockWebsocketServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    received: Arc<Mutex<Vec<Message>>>,
+    responses: ScriptedResponses,
+    mut outgoing: broadcast::Receiver<Message>,
+) {
+    let ws = match accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(err) => {
+            error!("Mock websocket server handshake failed: {:?}", err);
+            return;
+        }
+    };
+    let (mut write, mut read) = ws.split();
+    loop {
+        tokio::select! {
+            next = read.next() => {
+                let msg = match next {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(_)) | None => break,
+                };
+                trace!("Mock websocket server received: {:?}", msg);
+                received.lock().unwrap().push(msg.clone());
+                let response = match &msg {
+                    Message::Text(txt) => scripted_response(&responses, txt),
+                    Message::Close(_) => break,
+                    _ => None,
+                };
+                if let Some(response) = response {
+                    if write.send(Message::Text(response)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            next = outgoing.recv() => {
+                let msg = match next {
+                    Ok(msg) => msg,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let is_close = msg.is_close();
+                if write.send(msg).await.is_err() || is_close {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn scripted_response(
+    responses: &ScriptedResponses,
+    txt: &str,
+) -> Option<String> {
+    let request: Value = serde_json::from_str(txt).ok()?;
+    let method = request.get("method")?.as_str()?;
+    let result = responses.lock().unwrap().get(method)?.clone();
+    let response = serde_json::json!({
+        "jsonrpc": "2.0",
+        "result": result,
+        "id": request.get("id").cloned().unwrap_or(Value::Null),
+    });
+    Some(response.to_string())
+}
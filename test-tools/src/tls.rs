@@ -0,0 +1,91 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rcgen::CertifiedKey;
+use solana_sdk::pubkey::Pubkey;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+use crate::files::replace_file;
+
+/// Self-signed certificate for `localhost` whose certificate and private key
+/// are written to PEM files in the temp dir
+pub struct TestCertificate {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    cert: CertificateDer<'static>,
+}
+
+impl TestCertificate {
+    pub fn generate() -> Self {
+        let id = Pubkey::new_unique();
+        let cert_file =
+            std::env::temp_dir().join(format!("conjunto-cert-{}.pem", id));
+        let key_file =
+            std::env::temp_dir().join(format!("conjunto-key-{}.pem", id));
+        let cert = write_certified_key(&cert_file, &key_file);
+        Self {
+            cert_file,
+            key_file,
+            cert,
+        }
+    }
+
+    /// Replaces the files with a new certificate which the clients of
+    /// [Self::connect] trust from now on, like a renewal would.
+    /// Their modification times move forward so a reload always notices it.
+    pub fn renew(&mut self) {
+        self.cert = write_certified_key(&self.cert_file, &self.key_file);
+    }
+
+    /// Connects to the server at `addr` trusting only this certificate
+    pub async fn connect(
+        &self,
+        addr: &str,
+    ) -> std::io::Result<TlsStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.cert.clone()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(
+            ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let stream = TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, stream)
+            .await
+    }
+}
+
+impl Drop for TestCertificate {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.cert_file);
+        let _ = fs::remove_file(&self.key_file);
+    }
+}
+
+fn write_certified_key(
+    cert_file: &Path,
+    key_file: &Path,
+) -> CertificateDer<'static> {
+    let CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap();
+    replace_file(cert_file, cert.pem());
+    replace_file(key_file, key_pair.serialize_pem());
+    cert.der().clone()
+}
@@ -36,7 +36,7 @@ async fn main() {
         );
         let sanitized_tx = SanitizedTransaction::from_transaction_for_tests(tx);
         let endpoint = transwise
-            .guide_sanitized_transaction(&sanitized_tx)
+            .guide_sanitized_transaction(&sanitized_tx, None, None)
             .await
             .unwrap();
         println!("{:#?}", endpoint);
@@ -53,7 +53,7 @@ async fn main() {
         );
         let sanitized_tx = SanitizedTransaction::from_transaction_for_tests(tx);
         let endpoint = transwise
-            .guide_sanitized_transaction(&sanitized_tx)
+            .guide_sanitized_transaction(&sanitized_tx, None, None)
             .await
             .unwrap();
         println!("{:#?}", endpoint);
@@ -74,7 +74,7 @@ async fn main() {
         );
         let sanitized_tx = SanitizedTransaction::from_transaction_for_tests(tx);
         let endpoint = transwise
-            .guide_sanitized_transaction(&sanitized_tx)
+            .guide_sanitized_transaction(&sanitized_tx, None, None)
             .await
             .unwrap();
         println!("{:#?}", endpoint);
@@ -1,14 +1,182 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
 
-use crate::transaction_accounts_snapshot::TransactionAccountsSnapshot;
+use crate::{
+    ephemeral_constraints::EphemeralConstraints,
+    transaction_accounts_snapshot::TransactionAccountsSnapshot,
+};
 
 #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum UnroutableReason {
+    /// Writing undelegated accounts requires chain while writing delegated
+    /// accounts requires the ephemeral validator
     ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
         writable_undelegated_pubkeys: Vec<Pubkey>,
         writable_delegated_pubkeys: Vec<Pubkey>,
     },
+    /// The accounts are owned by the delegation program, but their delegation
+    /// record is corrupted, thus they cannot be written anywhere
+    DelegationRecordDataInvalid { writable_pubkeys: Vec<Pubkey> },
+    /// The transaction needs to run in the ephemeral validator, but the
+    /// payer has no escrow to pay its fees there
+    PayerNotEscrowed {
+        payer: Pubkey,
+        ephemeral_balance_pubkey: Pubkey,
+    },
+    /// The transaction needs to run in the ephemeral validator, but the
+    /// payer's escrow holds less lamports than required
+    PayerEscrowInsufficient {
+        payer: Pubkey,
+        ephemeral_balance_pubkey: Pubkey,
+        lamports: u64,
+        min_lamports: u64,
+    },
+    /// The transaction needs to run in the ephemeral validator which does not
+    /// allow writing to programs
+    WritableProgramAccount {
+        writable_program_pubkeys: Vec<Pubkey>,
+    },
+    /// The transaction needs to run in the ephemeral validator which does not
+    /// allow writing to sysvars
+    WritableSysvar {
+        writable_sysvar_pubkeys: Vec<Pubkey>,
+    },
+    /// The accounts are delegated to a validator other than the ones we route
+    /// ephemeral transactions to
+    DelegatedToDifferentValidator {
+        writable_pubkeys: Vec<Pubkey>,
+        validator_authorities: Vec<Pubkey>,
+    },
+    /// The accounts are delegated to different validators, thus there is no
+    /// single ephemeral validator that can write all of them
+    DelegatedToMultipleValidators {
+        writable_pubkeys: Vec<Pubkey>,
+        validator_authorities: Vec<Pubkey>,
+    },
+}
+
+impl UnroutableReason {
+    /// Stable code identifying the reason which clients can match on
+    pub fn code(&self) -> &'static str {
+        use UnroutableReason::*;
+        match self {
+            ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                ..
+            } => "WRITABLE_UNDELEGATED_AND_DELEGATED",
+            DelegationRecordDataInvalid { .. } => {
+                "DELEGATION_RECORD_DATA_INVALID"
+            }
+            PayerNotEscrowed { .. } => "PAYER_NOT_ESCROWED",
+            PayerEscrowInsufficient { .. } => "PAYER_ESCROW_INSUFFICIENT",
+            WritableProgramAccount { .. } => "WRITABLE_PROGRAM_ACCOUNT",
+            WritableSysvar { .. } => "WRITABLE_SYSVAR",
+            DelegatedToDifferentValidator { .. } => {
+                "DELEGATED_TO_DIFFERENT_VALIDATOR"
+            }
+            DelegatedToMultipleValidators { .. } => {
+                "DELEGATED_TO_MULTIPLE_VALIDATORS"
+            }
+        }
+    }
+
+    /// The accounts that prevent the transaction from being routed
+    pub fn pubkeys(&self) -> Vec<Pubkey> {
+        use UnroutableReason::*;
+        match self {
+            ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                writable_undelegated_pubkeys,
+                writable_delegated_pubkeys,
+            } => writable_undelegated_pubkeys
+                .iter()
+                .chain(writable_delegated_pubkeys)
+                .cloned()
+                .collect(),
+            DelegationRecordDataInvalid { writable_pubkeys } => {
+                writable_pubkeys.clone()
+            }
+            PayerNotEscrowed { payer, .. }
+            | PayerEscrowInsufficient { payer, .. } => vec![*payer],
+            WritableProgramAccount {
+                writable_program_pubkeys,
+            } => writable_program_pubkeys.clone(),
+            WritableSysvar {
+                writable_sysvar_pubkeys,
+            } => writable_sysvar_pubkeys.clone(),
+            DelegatedToDifferentValidator {
+                writable_pubkeys, ..
+            }
+            | DelegatedToMultipleValidators {
+                writable_pubkeys, ..
+            } => writable_pubkeys.clone(),
+        }
+    }
+}
+
+impl fmt::Display for UnroutableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use UnroutableReason::*;
+        match self {
+            ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                ..
+            } => {
+                write!(
+                    f,
+                    "both undelegated and delegated accounts are writable"
+                )
+            }
+            DelegationRecordDataInvalid { .. } => {
+                write!(f, "writable accounts have an invalid delegation record")
+            }
+            PayerNotEscrowed {
+                payer,
+                ephemeral_balance_pubkey,
+            } => write!(
+                f,
+                "payer {} has no escrow at {}",
+                payer, ephemeral_balance_pubkey
+            ),
+            PayerEscrowInsufficient {
+                payer,
+                lamports,
+                min_lamports,
+                ..
+            } => write!(
+                f,
+                "payer {} has {} lamports escrowed, but needs {}",
+                payer, lamports, min_lamports
+            ),
+            WritableProgramAccount { .. } => {
+                write!(f, "program accounts are writable")
+            }
+            WritableSysvar { .. } => write!(f, "sysvars are writable"),
+            DelegatedToDifferentValidator {
+                validator_authorities,
+                ..
+            } => write!(
+                f,
+                "writable accounts are not delegated to validators [{}]",
+                join_pubkeys(validator_authorities)
+            ),
+            DelegatedToMultipleValidators {
+                validator_authorities,
+                ..
+            } => write!(
+                f,
+                "writable accounts are delegated to multiple validators [{}]",
+                join_pubkeys(validator_authorities)
+            ),
+        }
+    }
+}
+
+fn join_pubkeys(pubkeys: &[Pubkey]) -> String {
+    pubkeys
+        .iter()
+        .map(Pubkey::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -36,6 +204,15 @@ impl Endpoint {
         matches!(self, Endpoint::Unroutable { .. })
     }
 
+    /// Name of the endpoint without any details, i.e. to label metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Endpoint::Chain { .. } => "chain",
+            Endpoint::Ephemeral { .. } => "ephemeral",
+            Endpoint::Unroutable { .. } => "unroutable",
+        }
+    }
+
     pub fn transaction_accounts_snapshot(
         &self,
     ) -> &TransactionAccountsSnapshot {
@@ -54,41 +231,152 @@ impl Endpoint {
             } => transaction_accounts_snapshot,
         }
     }
+
+    /// The authority of the ephemeral validator the transaction needs to be
+    /// sent to, `None` if it is not ephemeral or any validator will do
+    pub fn ephemeral_validator_authority(&self) -> Option<Pubkey> {
+        match self {
+            Endpoint::Ephemeral {
+                transaction_accounts_snapshot,
+            } => transaction_accounts_snapshot
+                .writable_delegated_validator_authorities()
+                .first()
+                .cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn unroutable_reason(&self) -> Option<&UnroutableReason> {
+        match self {
+            Endpoint::Unroutable { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
 }
 
 impl Endpoint {
     pub fn from(
         transaction_accounts_snapshot: TransactionAccountsSnapshot,
     ) -> Endpoint {
-        let writable_undelegated_pubkeys =
-            transaction_accounts_snapshot.writable_undelegated_pubkeys();
+        Self::from_with_constraints(
+            transaction_accounts_snapshot,
+            &EphemeralConstraints::default(),
+        )
+    }
+
+    /// Same as [Endpoint::from], but transactions that would run in the
+    /// ephemeral validator are unroutable unless they meet the `constraints`
+    /// of that validator.
+    pub fn from_with_constraints(
+        transaction_accounts_snapshot: TransactionAccountsSnapshot,
+        constraints: &EphemeralConstraints,
+    ) -> Endpoint {
+        // Accounts with a corrupted delegation record cannot be written
+        // neither on chain nor in the ephemeral validator
+        let writable_pubkeys = transaction_accounts_snapshot
+            .writable_invalid_delegation_record_pubkeys();
+        if !writable_pubkeys.is_empty() {
+            return Endpoint::Unroutable {
+                transaction_accounts_snapshot,
+                reason: UnroutableReason::DelegationRecordDataInvalid {
+                    writable_pubkeys,
+                },
+            };
+        }
+
         let writable_delegated_pubkeys =
             transaction_accounts_snapshot.writable_delegated_pubkeys();
+        // If there are no delegated accounts as writable, its for the chain
+        // (which is also the default if there are no writables at all)
+        if writable_delegated_pubkeys.is_empty() {
+            return Endpoint::Chain {
+                transaction_accounts_snapshot,
+            };
+        }
 
-        let has_writable_undelegated = !writable_undelegated_pubkeys.is_empty();
-        let has_writable_delegated = !writable_delegated_pubkeys.is_empty();
+        // Sysvars and programs are undelegated as well, but writing them is
+        // a separate problem from writing data accounts
+        let writable_sysvar_pubkeys =
+            transaction_accounts_snapshot.writable_sysvar_pubkeys();
+        let writable_program_pubkeys =
+            transaction_accounts_snapshot.writable_program_pubkeys();
+        let writable_undelegated_pubkeys = transaction_accounts_snapshot
+            .writable_undelegated_pubkeys()
+            .into_iter()
+            .filter(|pubkey| {
+                !writable_sysvar_pubkeys.contains(pubkey)
+                    && !writable_program_pubkeys.contains(pubkey)
+            })
+            .collect::<Vec<_>>();
 
-        match (has_writable_undelegated, has_writable_delegated) {
+        let reason = if !writable_undelegated_pubkeys.is_empty() {
             // If there are both data and delegated accounts as writable, its not possible to route
-            (true, true) => Endpoint::Unroutable {
-                transaction_accounts_snapshot,
-                reason: UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
-                    writable_undelegated_pubkeys,
-                    writable_delegated_pubkeys,
-                },
-            },
-            // If there is neither delegated nor data accounts as writable, just default to chain
-            (false, false) => Endpoint::Chain {
-                transaction_accounts_snapshot,
-            },
-            // If there are only data accounts as writable, its for the chain
-            (true, false) => Endpoint::Chain {
+            Some(UnroutableReason::ContainsBothUndelegatedAndDelegatedAccountsAsWritable {
+                writable_undelegated_pubkeys,
+                writable_delegated_pubkeys,
+            })
+        } else if !writable_sysvar_pubkeys.is_empty() {
+            Some(UnroutableReason::WritableSysvar {
+                writable_sysvar_pubkeys,
+            })
+        } else if !writable_program_pubkeys.is_empty() {
+            Some(UnroutableReason::WritableProgramAccount {
+                writable_program_pubkeys,
+            })
+        } else if transaction_accounts_snapshot
+            .writable_delegated_validator_authorities()
+            .len()
+            > 1
+        {
+            Some(UnroutableReason::DelegatedToMultipleValidators {
+                writable_pubkeys: writable_delegated_pubkeys,
+                validator_authorities: transaction_accounts_snapshot
+                    .writable_delegated_validator_authorities(),
+            })
+        } else {
+            ephemeral_constraints_unroutable_reason(
+                &transaction_accounts_snapshot,
+                constraints,
+            )
+        };
+
+        match reason {
+            Some(reason) => Endpoint::Unroutable {
                 transaction_accounts_snapshot,
+                reason,
             },
             // If there are only delegated accounts as writable, its for the ephemeral
-            (false, true) => Endpoint::Ephemeral {
+            None => Endpoint::Ephemeral {
                 transaction_accounts_snapshot,
-            }
+            },
+        }
+    }
+}
+
+/// Checks the constraints of the ephemeral validator for a transaction that
+/// only writes delegated accounts
+pub(crate) fn ephemeral_constraints_unroutable_reason(
+    transaction_accounts_snapshot: &TransactionAccountsSnapshot,
+    constraints: &EphemeralConstraints,
+) -> Option<UnroutableReason> {
+    if !constraints.validator_authorities.is_empty() {
+        let writable_pubkeys = transaction_accounts_snapshot
+            .writable_delegated_to_other_validator_pubkeys(
+                &constraints.validator_authorities,
+            );
+        if !writable_pubkeys.is_empty() {
+            return Some(UnroutableReason::DelegatedToDifferentValidator {
+                writable_pubkeys,
+                validator_authorities: constraints
+                    .validator_authorities
+                    .clone(),
+            });
         }
     }
+    constraints
+        .payer_escrow_min_lamports
+        .and_then(|min_lamports| {
+            transaction_accounts_snapshot
+                .payer_escrow_unroutable_reason(min_lamports)
+        })
 }
@@ -0,0 +1,14 @@
+use solana_sdk::pubkey::Pubkey;
+
+/// Requirements a transaction needs to meet in order to run in a specific
+/// ephemeral validator on top of writing only delegated accounts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EphemeralConstraints {
+    /// Authorities of the ephemeral validators transactions can be routed to,
+    /// if not empty accounts delegated to other validators cannot be written
+    pub validator_authorities: Vec<Pubkey>,
+    /// If provided, payers that are not delegated themselves need an escrow
+    /// holding at least this many lamports to pay fees in the ephemeral
+    /// validator
+    pub payer_escrow_min_lamports: Option<u64>,
+}
@@ -1,6 +1,8 @@
 use solana_sdk::pubkey::Pubkey;
 use thiserror::Error;
 
+use crate::endpoint::UnroutableReason;
+
 pub type TranswiseResult<T> = std::result::Result<T, TranswiseError>;
 
 #[derive(Error, Debug)]
@@ -16,6 +18,9 @@ pub enum TranswiseError {
         writable_undelegated_pubkeys: Vec<Pubkey>,
     },
 
+    #[error("Transaction cannot run in the ephemeral validator: {0}")]
+    TransactionNotAllowedInEphemeral(UnroutableReason),
+
     #[error("Transaction is missing payer account")]
     TransactionIsMissingPayerAccount,
 
@@ -1,4 +1,5 @@
 pub mod endpoint;
+pub mod ephemeral_constraints;
 pub mod errors;
 pub mod transaction_accounts_extractor;
 pub mod transaction_accounts_holder;
@@ -16,6 +17,7 @@ pub use conjunto_lockbox::{
     account_chain_snapshot_shared::AccountChainSnapshotShared,
     account_chain_state::AccountChainState,
     delegation_record_parser_impl::DelegationRecordParserImpl,
+    ephemeral_balance_snapshot::EphemeralBalanceSnapshot,
     errors::{LockboxError, LockboxResult},
 };
 pub use conjunto_providers::{
@@ -1,16 +1,20 @@
 use conjunto_core::{
+    delegation_inconsistency::DelegationInconsistency,
     delegation_record_parser::DelegationRecordParser, AccountProvider,
 };
 use conjunto_lockbox::{
     account_chain_snapshot_provider::AccountChainSnapshotProvider,
     account_chain_snapshot_shared::AccountChainSnapshotShared,
+    account_chain_state::AccountChainState,
 };
 use futures_util::future::{try_join, try_join_all, TryFutureExt};
 use serde::{Deserialize, Serialize};
-use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use solana_sdk::{
+    clock::Slot, commitment_config::CommitmentLevel, pubkey::Pubkey, sysvar,
+};
 
 use crate::{
-    errors::TranswiseResult,
+    endpoint::UnroutableReason, errors::TranswiseResult,
     transaction_accounts_holder::TransactionAccountsHolder,
 };
 
@@ -19,9 +23,17 @@ pub struct TransactionAccountsSnapshot {
     pub readonly: Vec<AccountChainSnapshotShared>,
     pub writable: Vec<AccountChainSnapshotShared>,
     pub payer: Pubkey,
+    /// The escrow of the payer, only present if it was fetched which it
+    /// only is for transactions that could run in the ephemeral validator
+    #[serde(default)]
+    pub payer_ephemeral_balance: Option<EphemeralBalanceSnapshot>,
 }
 
 impl TransactionAccountsSnapshot {
+    /// Fetches the chain state of all accounts of the holder.
+    /// The state is at least as recent as `min_context_slot` and read at the
+    /// `commitment` if provided, otherwise the commitment of the account
+    /// provider is used.
     pub async fn from_accounts_holder<
         T: AccountProvider,
         V: DelegationRecordParser,
@@ -29,22 +41,25 @@ impl TransactionAccountsSnapshot {
         holder: &TransactionAccountsHolder,
         account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
         min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
     ) -> TranswiseResult<Self> {
         // Fully parallelize snapshot fetching using join(s)
         let (readonly, writable) = try_join(
             try_join_all(holder.readonly.iter().map(|pubkey| {
                 account_chain_snapshot_provider
-                    .try_fetch_chain_snapshot_of_pubkey(
+                    .try_fetch_chain_snapshot_of_pubkey_with_commitment(
                         pubkey,
                         min_context_slot,
+                        commitment,
                     )
                     .map_ok(AccountChainSnapshotShared::from)
             })),
             try_join_all(holder.writable.iter().map(|pubkey| {
                 account_chain_snapshot_provider
-                    .try_fetch_chain_snapshot_of_pubkey(
+                    .try_fetch_chain_snapshot_of_pubkey_with_commitment(
                         pubkey,
                         min_context_slot,
+                        commitment,
                     )
                     .map_ok(AccountChainSnapshotShared::from)
             })),
@@ -54,6 +69,46 @@ impl TransactionAccountsSnapshot {
             readonly,
             writable,
             payer: holder.payer,
+            payer_ephemeral_balance: None,
+        })
+    }
+
+    /// Same as [Self::from_accounts_holder], but also fetches the escrow the
+    /// payer pays its fees from inside the ephemeral validator.
+    /// The escrow is only fetched once the accounts show that the transaction
+    /// writes delegated accounts and that the payer isn't delegated itself,
+    /// transactions going to chain don't need it.
+    pub async fn from_accounts_holder_with_payer_escrow<
+        T: AccountProvider,
+        V: DelegationRecordParser,
+    >(
+        holder: &TransactionAccountsHolder,
+        account_chain_snapshot_provider: &AccountChainSnapshotProvider<T, V>,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> TranswiseResult<Self> {
+        let snapshot = Self::from_accounts_holder(
+            holder,
+            account_chain_snapshot_provider,
+            min_context_slot,
+            commitment,
+        )
+        .await?;
+        if snapshot.writable_delegated_pubkeys().is_empty()
+            || snapshot.payer_is_delegated()
+        {
+            return Ok(snapshot);
+        }
+        let payer_ephemeral_balance = account_chain_snapshot_provider
+            .try_fetch_ephemeral_balance_of_payer(
+                &holder.payer,
+                min_context_slot,
+                commitment,
+            )
+            .await?;
+        Ok(Self {
+            payer_ephemeral_balance: Some(payer_ephemeral_balance),
+            ..snapshot
         })
     }
 
@@ -74,4 +129,146 @@ impl TransactionAccountsSnapshot {
             .map(|chain_snapshot| chain_snapshot.pubkey)
             .collect()
     }
+
+    /// Writable accounts owned by the delegation program whose delegation
+    /// record could not be parsed or has an unknown layout, those cannot be
+    /// written anywhere
+    pub fn writable_invalid_delegation_record_pubkeys(&self) -> Vec<Pubkey> {
+        self.writable
+            .iter()
+            .filter(|chain_snapshot| match &chain_snapshot.chain_state {
+                AccountChainState::Undelegated {
+                    delegation_inconsistency,
+                    ..
+                } => {
+                    use DelegationInconsistency::*;
+                    matches!(
+                        delegation_inconsistency,
+                        DelegationRecordDataInvalid(_)
+                            | DelegationRecordVersionUnknown(_)
+                    )
+                }
+                _ => false,
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    pub fn writable_sysvar_pubkeys(&self) -> Vec<Pubkey> {
+        self.writable
+            .iter()
+            .filter(|chain_snapshot| {
+                chain_snapshot
+                    .chain_state
+                    .account()
+                    .map(|account| sysvar::check_id(&account.owner))
+                    .unwrap_or(false)
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    pub fn writable_program_pubkeys(&self) -> Vec<Pubkey> {
+        self.writable
+            .iter()
+            .filter(|chain_snapshot| {
+                chain_snapshot
+                    .chain_state
+                    .account()
+                    .map(|account| account.executable)
+                    .unwrap_or(false)
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    /// Writable delegated accounts whose delegation record names an authority
+    /// other than the provided validator authorities.
+    /// Records without an authority (default pubkey) allow any validator.
+    pub fn writable_delegated_to_other_validator_pubkeys(
+        &self,
+        validator_authorities: &[Pubkey],
+    ) -> Vec<Pubkey> {
+        self.writable
+            .iter()
+            .filter(|chain_snapshot| {
+                match writable_delegation_authority(chain_snapshot) {
+                    Some(authority) => {
+                        !validator_authorities.contains(&authority)
+                    }
+                    None => false,
+                }
+            })
+            .map(|chain_snapshot| chain_snapshot.pubkey)
+            .collect()
+    }
+
+    /// The distinct authorities of the validators the writable accounts are
+    /// delegated to, excluding records that allow any validator
+    pub fn writable_delegated_validator_authorities(&self) -> Vec<Pubkey> {
+        let mut authorities = vec![];
+        for authority in self
+            .writable
+            .iter()
+            .filter_map(writable_delegation_authority)
+        {
+            if !authorities.contains(&authority) {
+                authorities.push(authority);
+            }
+        }
+        authorities
+    }
+
+    fn payer_is_delegated(&self) -> bool {
+        self.writable
+            .iter()
+            .chain(&self.readonly)
+            .any(|chain_snapshot| {
+                chain_snapshot.pubkey == self.payer
+                    && chain_snapshot.chain_state.is_delegated()
+            })
+    }
+
+    /// Checks that the payer can pay fees in the ephemeral validator which is
+    /// the case if it is delegated itself or has a large enough escrow.
+    /// Nothing is checked if the escrow was not fetched.
+    pub fn payer_escrow_unroutable_reason(
+        &self,
+        min_lamports: u64,
+    ) -> Option<UnroutableReason> {
+        if self.payer_is_delegated() {
+            return None;
+        }
+        let ephemeral_balance = self.payer_ephemeral_balance.as_ref()?;
+        match ephemeral_balance.lamports {
+            None => Some(UnroutableReason::PayerNotEscrowed {
+                payer: self.payer,
+                ephemeral_balance_pubkey: ephemeral_balance.pubkey,
+            }),
+            Some(lamports) if lamports < min_lamports => {
+                Some(UnroutableReason::PayerEscrowInsufficient {
+                    payer: self.payer,
+                    ephemeral_balance_pubkey: ephemeral_balance.pubkey,
+                    lamports,
+                    min_lamports,
+                })
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// The authority the account is delegated to unless it is not delegated or
+/// its delegation record allows any validator
+fn writable_delegation_authority(
+    chain_snapshot: &AccountChainSnapshotShared,
+) -> Option<Pubkey> {
+    match &chain_snapshot.chain_state {
+        AccountChainState::Delegated {
+            delegation_record, ..
+        } if delegation_record.authority != Pubkey::default() => {
+            Some(delegation_record.authority)
+        }
+        _ => None,
+    }
 }
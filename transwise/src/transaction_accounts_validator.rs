@@ -1,4 +1,6 @@
 use crate::{
+    endpoint::{ephemeral_constraints_unroutable_reason, UnroutableReason},
+    ephemeral_constraints::EphemeralConstraints,
     errors::{TranswiseError, TranswiseResult},
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
 };
@@ -11,6 +13,27 @@ pub trait TransactionAccountsValidator {
         &self,
         transaction_accounts: &TransactionAccountsSnapshot,
     ) -> TranswiseResult<()>;
+
+    /// Same as [TransactionAccountsValidator::validate_ephemeral_transaction_accounts],
+    /// but also makes sure that the transaction meets the constraints of the
+    /// ephemeral validator, i.e. that all writable accounts are delegated to
+    /// it and that the payer's escrow is large enough
+    fn validate_ephemeral_transaction_accounts_with_constraints(
+        &self,
+        transaction_accounts: &TransactionAccountsSnapshot,
+        constraints: &EphemeralConstraints,
+    ) -> TranswiseResult<()> {
+        self.validate_ephemeral_transaction_accounts(transaction_accounts)?;
+        match ephemeral_constraints_unroutable_reason(
+            transaction_accounts,
+            constraints,
+        ) {
+            Some(reason) => {
+                Err(TranswiseError::TransactionNotAllowedInEphemeral(reason))
+            }
+            None => Ok(()),
+        }
+    }
 }
 
 pub struct TransactionAccountsValidatorImpl;
@@ -20,19 +43,53 @@ impl TransactionAccountsValidator for TransactionAccountsValidatorImpl {
         &self,
         transaction_accounts: &TransactionAccountsSnapshot,
     ) -> TranswiseResult<()> {
+        // Accounts that are undelegated for a specific reason get a more
+        // actionable error than the generic one below
+        let writable_pubkeys =
+            transaction_accounts.writable_invalid_delegation_record_pubkeys();
+        if !writable_pubkeys.is_empty() {
+            return Err(TranswiseError::TransactionNotAllowedInEphemeral(
+                UnroutableReason::DelegationRecordDataInvalid {
+                    writable_pubkeys,
+                },
+            ));
+        }
+        let writable_sysvar_pubkeys =
+            transaction_accounts.writable_sysvar_pubkeys();
+        if !writable_sysvar_pubkeys.is_empty() {
+            return Err(TranswiseError::TransactionNotAllowedInEphemeral(
+                UnroutableReason::WritableSysvar {
+                    writable_sysvar_pubkeys,
+                },
+            ));
+        }
+        let writable_program_pubkeys =
+            transaction_accounts.writable_program_pubkeys();
+        if !writable_program_pubkeys.is_empty() {
+            return Err(TranswiseError::TransactionNotAllowedInEphemeral(
+                UnroutableReason::WritableProgramAccount {
+                    writable_program_pubkeys,
+                },
+            ));
+        }
         // We need make sure that none of the writables are data accounts
         let writable_undelegated_pubkeys =
             transaction_accounts.writable_undelegated_pubkeys();
-        let has_writable_undelegated = !writable_undelegated_pubkeys.is_empty();
-        if has_writable_undelegated {
-            let writable_undelegated_pubkeys =
-                transaction_accounts.writable_undelegated_pubkeys();
+        if !writable_undelegated_pubkeys.is_empty() {
             return Err(
                 TranswiseError::TransactionIncludeUndelegatedAccountsAsWritable {
                     writable_undelegated_pubkeys,
                 },
             );
         }
+        // If the payer's escrow was fetched it needs to exist
+        if let Some(reason) =
+            transaction_accounts.payer_escrow_unroutable_reason(0)
+        {
+            return Err(TranswiseError::TransactionNotAllowedInEphemeral(
+                reason,
+            ));
+        }
         // Transaction should work fine in other cases
         Ok(())
     }
@@ -1,15 +1,28 @@
+use std::time::Instant;
+
+use conjunto_core::{
+    delegation_record_parser::DelegationRecordParser, AccountProvider,
+};
 use conjunto_lockbox::{
+    account_chain_snapshot::AccountChainSnapshot,
     account_chain_snapshot_provider::AccountChainSnapshotProvider,
     delegation_record_parser_impl::DelegationRecordParserImpl,
 };
+use conjunto_metrics::observe_snapshot_fetch_latency;
 use conjunto_providers::{
     rpc_account_provider::RpcAccountProvider,
     rpc_provider_config::RpcProviderConfig,
 };
-use solana_sdk::transaction::{SanitizedTransaction, VersionedTransaction};
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::CommitmentLevel,
+    pubkey::Pubkey,
+    transaction::{SanitizedTransaction, VersionedTransaction},
+};
 
 use crate::{
-    endpoint::Endpoint, errors::TranswiseResult,
+    endpoint::Endpoint, ephemeral_constraints::EphemeralConstraints,
+    errors::TranswiseResult,
     transaction_accounts_holder::TransactionAccountsHolder,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
 };
@@ -17,48 +30,124 @@ use crate::{
 /// The API that allows us to guide a transaction given a cluster
 /// Guiding decisions are made by consulting the state of accounts on chain
 /// See [../examples/guiding_transactions.rs] for more info.
-pub struct Transwise {
-    account_chain_snapshot_provider: AccountChainSnapshotProvider<
-        RpcAccountProvider,
-        DelegationRecordParserImpl,
-    >,
+pub struct Transwise<T: AccountProvider, U: DelegationRecordParser> {
+    account_chain_snapshot_provider: AccountChainSnapshotProvider<T, U>,
+    /// Constraints of the ephemeral validator transactions are guided to
+    ephemeral_constraints: EphemeralConstraints,
 }
 
-impl Transwise {
+impl Transwise<RpcAccountProvider, DelegationRecordParserImpl> {
     pub fn new(config: RpcProviderConfig) -> Self {
-        let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+        Transwise::with_providers(
             RpcAccountProvider::new(config),
             DelegationRecordParserImpl,
+        )
+    }
+}
+
+impl<T: AccountProvider, U: DelegationRecordParser> Transwise<T, U> {
+    pub fn with_providers(
+        account_provider: T,
+        delegation_record_parser: U,
+    ) -> Self {
+        let account_chain_snapshot_provider = AccountChainSnapshotProvider::new(
+            account_provider,
+            delegation_record_parser,
         );
         Self {
             account_chain_snapshot_provider,
+            ephemeral_constraints: EphemeralConstraints::default(),
         }
     }
 
+    /// Makes transactions writing accounts that are delegated to a validator
+    /// other than the ones with the provided authorities unroutable.
+    /// Can be called multiple times when routing to multiple validators.
+    pub fn with_validator_authority(
+        mut self,
+        validator_authority: Pubkey,
+    ) -> Self {
+        if !self
+            .ephemeral_constraints
+            .validator_authorities
+            .contains(&validator_authority)
+        {
+            self.ephemeral_constraints
+                .validator_authorities
+                .push(validator_authority);
+        }
+        self
+    }
+
+    /// Makes transactions that would run in the ephemeral validator
+    /// unroutable unless their payer is delegated or has an escrow holding
+    /// at least `min_lamports`
+    pub fn with_payer_escrow_check(mut self, min_lamports: u64) -> Self {
+        self.ephemeral_constraints.payer_escrow_min_lamports =
+            Some(min_lamports);
+        self
+    }
+
     /// Extracts information of all accounts involved in the transaction,
     /// checks their lock state on chain and based on that returns an endpoint.
+    /// The chain state is at least as recent as `min_context_slot` and read
+    /// at the `commitment` if provided.
     pub async fn guide_versioned_transaction(
         &self,
         tx: &VersionedTransaction,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
     ) -> TranswiseResult<Endpoint> {
-        Ok(Endpoint::from(
-            self.transaction_accounts_snapshot_from_versioned_transaction(tx)
-                .await?,
+        Ok(Endpoint::from_with_constraints(
+            self.transaction_accounts_snapshot_from_versioned_transaction(
+                tx,
+                min_context_slot,
+                commitment,
+            )
+            .await?,
+            &self.ephemeral_constraints,
         ))
     }
 
     /// Extracts information of all accounts involved in the transaction,
     /// checks their lock state on chain and based on that returns an endpoint.
+    /// The chain state is at least as recent as `min_context_slot` and read
+    /// at the `commitment` if provided.
     pub async fn guide_sanitized_transaction(
         &self,
         tx: &SanitizedTransaction,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
     ) -> TranswiseResult<Endpoint> {
-        Ok(Endpoint::from(
-            self.transaction_accounts_snapshot_from_sanitized_transaction(tx)
-                .await?,
+        Ok(Endpoint::from_with_constraints(
+            self.transaction_accounts_snapshot_from_sanitized_transaction(
+                tx,
+                min_context_slot,
+                commitment,
+            )
+            .await?,
+            &self.ephemeral_constraints,
         ))
     }
 
+    /// Checks the lock state of the provided accounts on chain, i.e. to find
+    /// out which of them are delegated.
+    /// The accounts are fetched in batches together with their delegation
+    /// records, the chain state is at least as recent as `min_context_slot`.
+    pub async fn account_chain_snapshots(
+        &self,
+        pubkeys: &[Pubkey],
+        min_context_slot: Option<Slot>,
+    ) -> TranswiseResult<Vec<AccountChainSnapshot>> {
+        let start = Instant::now();
+        let snapshots = self
+            .account_chain_snapshot_provider
+            .try_fetch_chain_snapshots_of_pubkeys(pubkeys, min_context_slot)
+            .await?;
+        observe_snapshot_fetch_latency(start.elapsed());
+        Ok(snapshots)
+    }
+
     /// Extracts information of all accounts involved in the transaction and
     /// checks their lock state on chain.
     /// This method is a convenience API but inefficient since it validates
@@ -67,11 +156,13 @@ impl Transwise {
     async fn transaction_accounts_snapshot_from_versioned_transaction(
         &self,
         tx: &VersionedTransaction,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
     ) -> TranswiseResult<TransactionAccountsSnapshot> {
-        TransactionAccountsSnapshot::from_accounts_holder(
+        self.transaction_accounts_snapshot_from_holder(
             &TransactionAccountsHolder::try_from(tx)?,
-            &self.account_chain_snapshot_provider,
-            None,
+            min_context_slot,
+            commitment,
         )
         .await
     }
@@ -84,12 +175,47 @@ impl Transwise {
     async fn transaction_accounts_snapshot_from_sanitized_transaction(
         &self,
         tx: &SanitizedTransaction,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
     ) -> TranswiseResult<TransactionAccountsSnapshot> {
-        TransactionAccountsSnapshot::from_accounts_holder(
+        self.transaction_accounts_snapshot_from_holder(
             &TransactionAccountsHolder::try_from(tx)?,
-            &self.account_chain_snapshot_provider,
-            None,
+            min_context_slot,
+            commitment,
         )
         .await
     }
+
+    /// Only fetches the payer's escrow if it is going to be checked
+    async fn transaction_accounts_snapshot_from_holder(
+        &self,
+        holder: &TransactionAccountsHolder,
+        min_context_slot: Option<Slot>,
+        commitment: Option<CommitmentLevel>,
+    ) -> TranswiseResult<TransactionAccountsSnapshot> {
+        let start = Instant::now();
+        let snapshot = if self
+            .ephemeral_constraints
+            .payer_escrow_min_lamports
+            .is_some()
+        {
+            TransactionAccountsSnapshot::from_accounts_holder_with_payer_escrow(
+                holder,
+                &self.account_chain_snapshot_provider,
+                min_context_slot,
+                commitment,
+            )
+            .await
+        } else {
+            TransactionAccountsSnapshot::from_accounts_holder(
+                holder,
+                &self.account_chain_snapshot_provider,
+                min_context_slot,
+                commitment,
+            )
+            .await
+        }?;
+        observe_snapshot_fetch_latency(start.elapsed());
+        Ok(snapshot)
+    }
 }
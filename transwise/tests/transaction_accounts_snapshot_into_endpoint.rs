@@ -5,14 +5,16 @@ use conjunto_test_tools::{
     account_provider_stub::AccountProviderStub,
     accounts::{
         account_owned_by_delegation_program, account_with_data,
-        delegated_account_ids,
+        delegated_account_ids, program_account,
     },
     delegation_record_parser_stub::DelegationRecordParserStub,
 };
 use conjunto_transwise::{
     endpoint::{Endpoint, UnroutableReason},
+    ephemeral_constraints::EphemeralConstraints,
     transaction_accounts_holder::TransactionAccountsHolder,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
+    AccountChainSnapshot, AccountChainSnapshotShared, AccountChainState,
     CommitFrequency, DelegationRecord,
 };
 use solana_sdk::{
@@ -20,7 +22,7 @@ use solana_sdk::{
     clock::Slot,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
-    system_program,
+    system_program, sysvar,
 };
 
 const EXPECTED_SLOT: Slot = 42;
@@ -79,6 +81,7 @@ async fn test_one_undelegated_readonly_and_one_delegated_writable_and_payer() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -136,6 +139,7 @@ async fn test_one_writable_delegated_and_one_writable_undelegated() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -191,6 +195,7 @@ async fn test_one_writable_inconsistent_with_missing_delegation_account() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -239,6 +244,7 @@ async fn test_one_writable_inconsistent_with_invalid_delegation_record() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -258,8 +264,11 @@ async fn test_one_writable_inconsistent_with_invalid_delegation_record() {
 
     assert_eq!(
         endpoint,
-        Endpoint::Chain {
+        Endpoint::Unroutable {
             transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::DelegationRecordDataInvalid {
+                writable_pubkeys: vec![writable_undelegated],
+            },
         }
     );
 }
@@ -285,6 +294,7 @@ async fn test_one_writable_undelegated_with_writable_feepayer() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -333,6 +343,7 @@ async fn test_one_writable_undelegated_as_payer() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -379,6 +390,7 @@ async fn test_one_writable_undelegated_as_payer_and_one_writable_delegated() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -435,6 +447,7 @@ async fn test_two_readonly_datas_and_payer() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -491,6 +504,7 @@ async fn test_two_readonly_undelegated_and_one_writable_undelegated() {
         &acc_holder,
         &chain_snapshot_provider,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -519,3 +533,397 @@ async fn test_two_readonly_undelegated_and_one_writable_undelegated() {
         }
     );
 }
+
+async fn snapshot_writing_delegated_and(
+    other_writable: (Pubkey, Account),
+    delegation_record: DelegationRecord,
+) -> (TransactionAccountsSnapshot, Pubkey) {
+    let (writable_delegated, delegation_record_pubkey) =
+        delegated_account_ids();
+    let writable_feepayer = Keypair::new().pubkey();
+    let (other_pubkey, other_account) = other_writable;
+
+    let chain_snapshot_provider = setup_chain_snapshot_provider(
+        vec![
+            (writable_delegated, account_owned_by_delegation_program()),
+            (
+                delegation_record_pubkey,
+                account_owned_by_delegation_program(),
+            ),
+            (other_pubkey, other_account),
+        ],
+        Some(delegation_record),
+    );
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![writable_delegated, other_pubkey, writable_feepayer],
+        payer: writable_feepayer,
+    };
+
+    let acc_snapshot = TransactionAccountsSnapshot::from_accounts_holder(
+        &acc_holder,
+        &chain_snapshot_provider,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    (acc_snapshot, writable_delegated)
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_and_one_writable_sysvar() {
+    let sysvar_account = Account {
+        owner: sysvar::ID,
+        ..account_with_data()
+    };
+    let (acc_snapshot, _) = snapshot_writing_delegated_and(
+        (sysvar::clock::ID, sysvar_account),
+        dummy_delegation_record_with_owner(Pubkey::new_unique()),
+    )
+    .await;
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    let reason = UnroutableReason::WritableSysvar {
+        writable_sysvar_pubkeys: vec![sysvar::clock::ID],
+    };
+    assert_eq!(reason.code(), "WRITABLE_SYSVAR");
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_and_one_writable_program() {
+    let writable_program =
+        Pubkey::find_program_address(&[&[1]], &Pubkey::new_unique()).0;
+    let (acc_snapshot, _) = snapshot_writing_delegated_and(
+        (writable_program, program_account()),
+        dummy_delegation_record_with_owner(Pubkey::new_unique()),
+    )
+    .await;
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    let reason = UnroutableReason::WritableProgramAccount {
+        writable_program_pubkeys: vec![writable_program],
+    };
+    assert_eq!(reason.code(), "WRITABLE_PROGRAM_ACCOUNT");
+    assert_eq!(reason.pubkeys(), vec![writable_program]);
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_to_different_validator() {
+    let validator_authority = Pubkey::new_unique();
+    let (acc_snapshot, writable_delegated) = snapshot_writing_delegated_and(
+        (Keypair::new().pubkey(), Account::default()),
+        dummy_delegation_record_with_owner(Pubkey::new_unique()),
+    )
+    .await;
+
+    // Without knowing the validator authority we cannot tell
+    assert!(Endpoint::from(acc_snapshot.clone()).is_ephemeral());
+
+    let endpoint = Endpoint::from_with_constraints(
+        acc_snapshot.clone(),
+        &EphemeralConstraints {
+            validator_authorities: vec![validator_authority],
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::DelegatedToDifferentValidator {
+                writable_pubkeys: vec![writable_delegated],
+                validator_authorities: vec![validator_authority],
+            },
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_to_same_or_any_validator() {
+    let validator_authority = Pubkey::new_unique();
+    for authority in [validator_authority, Pubkey::default()] {
+        let (acc_snapshot, _) = snapshot_writing_delegated_and(
+            (Keypair::new().pubkey(), Account::default()),
+            DelegationRecord {
+                authority,
+                ..dummy_delegation_record_with_owner(Pubkey::new_unique())
+            },
+        )
+        .await;
+
+        let endpoint = Endpoint::from_with_constraints(
+            acc_snapshot,
+            &EphemeralConstraints {
+                validator_authorities: vec![validator_authority],
+                ..Default::default()
+            },
+        );
+
+        assert!(endpoint.is_ephemeral());
+    }
+}
+
+async fn snapshot_writing_delegated_with_payer_escrow(
+    escrow: Option<Account>,
+) -> (TransactionAccountsSnapshot, Pubkey) {
+    let (writable_delegated, delegation_record) = delegated_account_ids();
+    let writable_feepayer = Keypair::new().pubkey();
+
+    let mut accounts = vec![
+        (writable_delegated, account_owned_by_delegation_program()),
+        (delegation_record, account_owned_by_delegation_program()),
+    ];
+    if let Some(escrow) = escrow {
+        accounts.push((
+            AccountChainSnapshot::ephemeral_balance_pda(&writable_feepayer),
+            escrow,
+        ));
+    }
+    let chain_snapshot_provider = setup_chain_snapshot_provider(
+        accounts,
+        Some(dummy_delegation_record_with_owner(Pubkey::new_unique())),
+    );
+
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![writable_delegated, writable_feepayer],
+        payer: writable_feepayer,
+    };
+
+    let acc_snapshot =
+        TransactionAccountsSnapshot::from_accounts_holder_with_payer_escrow(
+            &acc_holder,
+            &chain_snapshot_provider,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    (acc_snapshot, writable_feepayer)
+}
+
+#[tokio::test]
+async fn test_payer_escrow_is_not_fetched_for_chain_transactions() {
+    let writable_undelegated = Pubkey::new_unique();
+    let writable_feepayer = Keypair::new().pubkey();
+    let chain_snapshot_provider = setup_chain_snapshot_provider(
+        vec![
+            (writable_undelegated, account_with_data()),
+            (
+                AccountChainSnapshot::ephemeral_balance_pda(&writable_feepayer),
+                account_owned_by_delegation_program(),
+            ),
+        ],
+        None,
+    );
+    let acc_holder = TransactionAccountsHolder {
+        readonly: vec![],
+        writable: vec![writable_undelegated, writable_feepayer],
+        payer: writable_feepayer,
+    };
+
+    let acc_snapshot =
+        TransactionAccountsSnapshot::from_accounts_holder_with_payer_escrow(
+            &acc_holder,
+            &chain_snapshot_provider,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(acc_snapshot.payer_ephemeral_balance, None);
+    let endpoint = Endpoint::from_with_constraints(
+        acc_snapshot,
+        &payer_escrow_constraints(),
+    );
+    assert!(endpoint.is_chain());
+}
+
+fn payer_escrow_constraints() -> EphemeralConstraints {
+    EphemeralConstraints {
+        payer_escrow_min_lamports: Some(1_000),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_and_escrowed_payer() {
+    let (acc_snapshot, payer) =
+        snapshot_writing_delegated_with_payer_escrow(Some(Account {
+            lamports: 1_000,
+            ..account_owned_by_delegation_program()
+        }))
+        .await;
+
+    let ephemeral_balance = acc_snapshot.payer_ephemeral_balance.clone();
+    assert_eq!(ephemeral_balance.map(|balance| balance.payer), Some(payer));
+
+    let endpoint = Endpoint::from_with_constraints(
+        acc_snapshot,
+        &payer_escrow_constraints(),
+    );
+
+    assert!(endpoint.is_ephemeral());
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_and_payer_without_escrow() {
+    // Escrows not owned by the delegation program do not count
+    for escrow in [None, Some(account_with_data())] {
+        let (acc_snapshot, payer) =
+            snapshot_writing_delegated_with_payer_escrow(escrow).await;
+
+        let endpoint = Endpoint::from_with_constraints(
+            acc_snapshot.clone(),
+            &payer_escrow_constraints(),
+        );
+
+        assert_eq!(
+            endpoint,
+            Endpoint::Unroutable {
+                transaction_accounts_snapshot: acc_snapshot,
+                reason: UnroutableReason::PayerNotEscrowed {
+                    payer,
+                    ephemeral_balance_pubkey:
+                        AccountChainSnapshot::ephemeral_balance_pda(&payer),
+                },
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_one_writable_delegated_and_payer_with_insufficient_escrow() {
+    let (acc_snapshot, payer) =
+        snapshot_writing_delegated_with_payer_escrow(Some(Account {
+            lamports: 999,
+            ..account_owned_by_delegation_program()
+        }))
+        .await;
+
+    let endpoint = Endpoint::from_with_constraints(
+        acc_snapshot.clone(),
+        &payer_escrow_constraints(),
+    );
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::PayerEscrowInsufficient {
+                payer,
+                ephemeral_balance_pubkey:
+                    AccountChainSnapshot::ephemeral_balance_pda(&payer),
+                lamports: 999,
+                min_lamports: 1_000,
+            },
+        }
+    );
+}
+
+fn delegated_to(authority: Pubkey) -> AccountChainSnapshotShared {
+    AccountChainSnapshot {
+        pubkey: Pubkey::new_unique(),
+        at_slot: EXPECTED_SLOT,
+        chain_state: AccountChainState::Delegated {
+            account: account_owned_by_delegation_program(),
+            delegation_record: DelegationRecord {
+                authority,
+                ..dummy_delegation_record_with_owner(Pubkey::new_unique())
+            },
+        },
+    }
+    .into()
+}
+
+#[test]
+fn test_writable_delegated_names_ephemeral_validator() {
+    let validator_authority = Pubkey::new_unique();
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            delegated_to(Pubkey::default()),
+            delegated_to(validator_authority),
+            delegated_to(validator_authority),
+        ],
+        payer: Keypair::new().pubkey(),
+        payer_ephemeral_balance: None,
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot);
+
+    assert!(endpoint.is_ephemeral());
+    assert_eq!(
+        endpoint.ephemeral_validator_authority(),
+        Some(validator_authority)
+    );
+}
+
+#[test]
+fn test_writable_delegated_to_any_validator_names_none() {
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![delegated_to(Pubkey::default())],
+        payer: Keypair::new().pubkey(),
+        payer_ephemeral_balance: None,
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot);
+
+    assert!(endpoint.is_ephemeral());
+    assert_eq!(endpoint.ephemeral_validator_authority(), None);
+}
+
+#[test]
+fn test_writable_delegated_to_multiple_validators() {
+    let authority1 = Pubkey::new_unique();
+    let authority2 = Pubkey::new_unique();
+    let writable_delegated1 = delegated_to(authority1);
+    let writable_delegated2 = delegated_to(authority2);
+    let acc_snapshot = TransactionAccountsSnapshot {
+        readonly: vec![],
+        writable: vec![
+            writable_delegated1.clone(),
+            writable_delegated2.clone(),
+        ],
+        payer: Keypair::new().pubkey(),
+        payer_ephemeral_balance: None,
+    };
+
+    let endpoint = Endpoint::from(acc_snapshot.clone());
+
+    assert_eq!(
+        endpoint,
+        Endpoint::Unroutable {
+            transaction_accounts_snapshot: acc_snapshot,
+            reason: UnroutableReason::DelegatedToMultipleValidators {
+                writable_pubkeys: vec![
+                    writable_delegated1.pubkey,
+                    writable_delegated2.pubkey,
+                ],
+                validator_authorities: vec![authority1, authority2],
+            },
+        }
+    );
+    assert_eq!(endpoint.ephemeral_validator_authority(), None);
+}
@@ -7,13 +7,17 @@ use conjunto_test_tools::accounts::{
     account_owned_by_delegation_program, account_with_data,
 };
 use conjunto_transwise::{
+    endpoint::UnroutableReason,
+    ephemeral_constraints::EphemeralConstraints,
+    errors::TranswiseError,
     transaction_accounts_snapshot::TransactionAccountsSnapshot,
     transaction_accounts_validator::{
         TransactionAccountsValidator, TransactionAccountsValidatorImpl,
     },
     AccountChainSnapshotShared, CommitFrequency, DelegationRecord,
+    EphemeralBalanceSnapshot,
 };
-use solana_sdk::{pubkey::Pubkey, system_program};
+use solana_sdk::{account::Account, pubkey::Pubkey, system_program, sysvar};
 
 fn transaction_accounts_validator() -> TransactionAccountsValidatorImpl {
     TransactionAccountsValidatorImpl {}
@@ -73,6 +77,7 @@ fn test_two_readonly_undelegated_and_two_writable_delegated_and_feepayers() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![
                     readonly_undelegated1,
                     readonly_undelegated2,
@@ -96,6 +101,7 @@ fn test_empty_transaction_accounts() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![],
             },
@@ -113,6 +119,7 @@ fn test_only_one_readonly_undelegated() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![readonly_undelegated],
                 writable: vec![],
             },
@@ -130,6 +137,7 @@ fn test_only_one_writable_delegated() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_delegated],
             },
@@ -147,6 +155,7 @@ fn test_only_one_writable_feepayer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_feepayer],
             },
@@ -164,6 +173,7 @@ fn test_only_one_readable_undelegated_as_payer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: readable_undelegated.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![readable_undelegated],
                 writable: vec![],
             },
@@ -181,6 +191,7 @@ fn test_only_one_writable_undelegated_as_payer_fail() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_undelegated.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_undelegated],
             },
@@ -198,6 +209,7 @@ fn test_only_one_writable_delegated_as_payer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_delegated.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_delegated],
             },
@@ -215,6 +227,7 @@ fn test_only_one_writable_feepayer_as_payer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_feepayer],
             },
@@ -233,6 +246,7 @@ fn test_one_readonly_undelegated_and_writable_feepayer_as_payer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_feepayer],
             },
@@ -252,6 +266,7 @@ fn test_one_readonly_undelegated_and_one_writable_delegated_and_feepayer() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_delegated, writable_feepayer],
             },
@@ -271,6 +286,7 @@ fn test_one_readonly_undelegated_and_one_writable_undelegated_and_payer_fail() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: Pubkey::new_unique(),
+                payer_ephemeral_balance: None,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_undelegated, writable_feepayer],
             },
@@ -289,6 +305,7 @@ fn test_one_readonly_undelegated_and_one_writable_undelegated_as_payer_fail() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_undelegated.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![readonly_undelegated],
                 writable: vec![writable_undelegated],
             },
@@ -307,6 +324,7 @@ fn test_one_writable_undelegated_and_writable_feepayer_as_payer_fail() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![],
                 writable: vec![writable_undelegated, writable_feepayer],
             },
@@ -329,6 +347,7 @@ fn test_one_of_each_valid_type() {
         .validate_ephemeral_transaction_accounts(
             &TransactionAccountsSnapshot {
                 payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
                 readonly: vec![
                     readonly_undelegated,
                     readonly_delegated,
@@ -341,3 +360,228 @@ fn test_one_of_each_valid_type() {
     // This should work just right in strict mode
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_writable_invalid_delegation_record_fail() {
+    let writable_invalid: AccountChainSnapshotShared = AccountChainSnapshot {
+        pubkey: Pubkey::new_unique(),
+        at_slot: 42,
+        chain_state: AccountChainState::Undelegated {
+            account: account_owned_by_delegation_program(),
+            delegation_inconsistency:
+                DelegationInconsistency::DelegationRecordDataInvalid(
+                    "bad data".to_string(),
+                ),
+        },
+    }
+    .into();
+    let writable_feepayer = chain_snapshot_feepayer();
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts(
+            &TransactionAccountsSnapshot {
+                payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
+                readonly: vec![],
+                writable: vec![writable_invalid.clone(), writable_feepayer],
+            },
+        );
+
+    match result {
+        Err(TranswiseError::TransactionNotAllowedInEphemeral(reason)) => {
+            assert_eq!(
+                reason,
+                UnroutableReason::DelegationRecordDataInvalid {
+                    writable_pubkeys: vec![writable_invalid.pubkey],
+                }
+            );
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_writable_sysvar_fail() {
+    let writable_sysvar: AccountChainSnapshotShared = AccountChainSnapshot {
+        pubkey: sysvar::clock::ID,
+        at_slot: 42,
+        chain_state: AccountChainState::Undelegated {
+            account: Account {
+                owner: sysvar::ID,
+                ..account_with_data()
+            },
+            delegation_inconsistency:
+                DelegationInconsistency::AccountInvalidOwner,
+        },
+    }
+    .into();
+    let writable_delegated = chain_snapshot_delegated();
+    let writable_feepayer = chain_snapshot_feepayer();
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts(
+            &TransactionAccountsSnapshot {
+                payer: writable_feepayer.pubkey,
+                payer_ephemeral_balance: None,
+                readonly: vec![],
+                writable: vec![
+                    writable_delegated,
+                    writable_sysvar,
+                    writable_feepayer,
+                ],
+            },
+        );
+
+    match result {
+        Err(TranswiseError::TransactionNotAllowedInEphemeral(reason)) => {
+            assert_eq!(reason.code(), "WRITABLE_SYSVAR");
+            assert_eq!(reason.pubkeys(), vec![sysvar::clock::ID]);
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_writable_delegated_to_different_validator_fail() {
+    let writable_delegated = chain_snapshot_delegated();
+    let writable_feepayer = chain_snapshot_feepayer();
+    let transaction_accounts = TransactionAccountsSnapshot {
+        payer: writable_feepayer.pubkey,
+        payer_ephemeral_balance: None,
+        readonly: vec![],
+        writable: vec![writable_delegated.clone(), writable_feepayer],
+    };
+    let delegation_authority = match &writable_delegated.chain_state {
+        AccountChainState::Delegated {
+            delegation_record, ..
+        } => delegation_record.authority,
+        _ => unreachable!(),
+    };
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts_with_constraints(
+            &transaction_accounts,
+            &EphemeralConstraints {
+                validator_authorities: vec![delegation_authority],
+                ..Default::default()
+            },
+        );
+    assert!(result.is_ok());
+
+    let validator_authority = Pubkey::new_unique();
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts_with_constraints(
+            &transaction_accounts,
+            &EphemeralConstraints {
+                validator_authorities: vec![validator_authority],
+                ..Default::default()
+            },
+        );
+    match result {
+        Err(TranswiseError::TransactionNotAllowedInEphemeral(reason)) => {
+            assert_eq!(
+                reason,
+                UnroutableReason::DelegatedToDifferentValidator {
+                    writable_pubkeys: vec![writable_delegated.pubkey],
+                    validator_authorities: vec![validator_authority],
+                }
+            );
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+fn ephemeral_balance(
+    payer: Pubkey,
+    lamports: Option<u64>,
+) -> Option<EphemeralBalanceSnapshot> {
+    Some(EphemeralBalanceSnapshot {
+        payer,
+        pubkey: AccountChainSnapshot::ephemeral_balance_pda(&payer),
+        at_slot: 42,
+        lamports,
+    })
+}
+
+#[test]
+fn test_payer_without_escrow_fail() {
+    let writable_delegated = chain_snapshot_delegated();
+    let writable_feepayer = chain_snapshot_feepayer();
+    let payer = writable_feepayer.pubkey;
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts(
+            &TransactionAccountsSnapshot {
+                payer,
+                payer_ephemeral_balance: ephemeral_balance(payer, None),
+                readonly: vec![],
+                writable: vec![writable_delegated, writable_feepayer],
+            },
+        );
+
+    match result {
+        Err(TranswiseError::TransactionNotAllowedInEphemeral(reason)) => {
+            assert_eq!(
+                reason,
+                UnroutableReason::PayerNotEscrowed {
+                    payer,
+                    ephemeral_balance_pubkey:
+                        AccountChainSnapshot::ephemeral_balance_pda(&payer),
+                }
+            );
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_payer_with_insufficient_escrow_fail() {
+    let writable_delegated = chain_snapshot_delegated();
+    let writable_feepayer = chain_snapshot_feepayer();
+    let payer = writable_feepayer.pubkey;
+    let transaction_accounts = TransactionAccountsSnapshot {
+        payer,
+        payer_ephemeral_balance: ephemeral_balance(payer, Some(999)),
+        readonly: vec![],
+        writable: vec![writable_delegated, writable_feepayer],
+    };
+
+    // Any escrow will do without a minimum
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts(&transaction_accounts);
+    assert!(result.is_ok());
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts_with_constraints(
+            &transaction_accounts,
+            &EphemeralConstraints {
+                payer_escrow_min_lamports: Some(1_000),
+                ..Default::default()
+            },
+        );
+    match result {
+        Err(TranswiseError::TransactionNotAllowedInEphemeral(reason)) => {
+            assert_eq!(reason.code(), "PAYER_ESCROW_INSUFFICIENT");
+            assert_eq!(reason.pubkeys(), vec![payer]);
+        }
+        result => panic!("Unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_delegated_payer_needs_no_escrow() {
+    let writable_delegated = chain_snapshot_delegated();
+    let payer = writable_delegated.pubkey;
+
+    let result = transaction_accounts_validator()
+        .validate_ephemeral_transaction_accounts(
+            &TransactionAccountsSnapshot {
+                payer,
+                payer_ephemeral_balance: ephemeral_balance(payer, None),
+                readonly: vec![],
+                writable: vec![writable_delegated],
+            },
+        );
+
+    assert!(result.is_ok());
+}
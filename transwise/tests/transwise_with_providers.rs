@@ -0,0 +1,87 @@
+use conjunto_test_tools::{
+    account_provider_stub::AccountProviderStub,
+    accounts::{
+        account_owned_by_delegation_program, account_with_data,
+        delegated_account_ids,
+    },
+    delegation_record_parser_stub::DelegationRecordParserStub,
+};
+use conjunto_transwise::{
+    transwise::Transwise, CommitFrequency, DelegationRecord,
+};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, VersionedTransaction},
+};
+
+fn setup_transwise(
+    accounts: Vec<(Pubkey, Account)>,
+) -> Transwise<AccountProviderStub, DelegationRecordParserStub> {
+    let mut account_provider = AccountProviderStub::default();
+    for (pubkey, account) in accounts {
+        account_provider.add(pubkey, account);
+    }
+    let delegation_record_parser =
+        DelegationRecordParserStub::new(Some(DelegationRecord {
+            authority: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            delegation_slot: 0,
+            lamports: 1000,
+            commit_frequency: CommitFrequency::Millis(1_000),
+        }));
+    Transwise::with_providers(account_provider, delegation_record_parser)
+}
+
+fn transaction_writing(writable: &[Pubkey]) -> VersionedTransaction {
+    let ix = Instruction::new_with_bytes(
+        Pubkey::new_unique(),
+        &[],
+        writable
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false))
+            .collect(),
+    );
+    let payer = Keypair::new().pubkey();
+    VersionedTransaction::from(Transaction::new_with_payer(&[ix], Some(&payer)))
+}
+
+#[tokio::test]
+async fn test_guide_transaction_writing_delegated_with_stub_providers() {
+    let (delegated, delegation_record) = delegated_account_ids();
+    let transwise = setup_transwise(vec![
+        (delegated, account_owned_by_delegation_program()),
+        (delegation_record, account_owned_by_delegation_program()),
+    ]);
+
+    let endpoint = transwise
+        .guide_versioned_transaction(
+            &transaction_writing(&[delegated]),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert!(endpoint.is_ephemeral());
+}
+
+#[tokio::test]
+async fn test_guide_transaction_writing_undelegated_with_stub_providers() {
+    let undelegated =
+        Pubkey::find_program_address(&[&[0]], &Pubkey::new_unique()).0;
+    let transwise = setup_transwise(vec![(undelegated, account_with_data())]);
+
+    let endpoint = transwise
+        .guide_versioned_transaction(
+            &transaction_writing(&[undelegated]),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert!(endpoint.is_chain());
+}